@@ -30,11 +30,11 @@ buildah run "${builder_ctr}" -- bash -c '
   mkdir -p /app/bin
 
   cd /app/client
-  cargo build --release
+  cargo build --release --features nvml
   mv target/release/client /app/bin/job_helper
   
   cd /app/monitor
-  cargo build --release
+  cargo build --release --features nvml
   mv target/release/monitor /app/bin/node_monitor
 '
 echo "--- Builder stage complete. Artifacts are ready. ---"
@@ -71,6 +71,7 @@ buildah run "${final_ctr}" -- bash -c '
 echo ">>> Copying configuration files..."
 buildah copy "${final_ctr}" ./ssh_config/sshd_config /etc/ssh/sshd_config
 buildah copy "${final_ctr}" ./pam.d/sshd ./pam.d/system-auth ./pam.d/password-auth /etc/pam.d/
+buildah copy "${final_ctr}" ./monitor_config/node_monitor.toml /etc/node_monitor.toml
 buildah copy "${final_ctr}" ./custom_script/epilog.sh ./custom_script/prolog.sh ./custom_script/task_epilog.sh ./custom_script/task_prolog.sh /etc/slurm/
 
 # --- 第 3 部分: 从 builder 复制编译产物到最终镜像 ---
@@ -1,16 +1,28 @@
+use std::collections::VecDeque;
 use std::env;
-use std::path::PathBuf;
-use std::process::Command;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
 use std::str;
 use std::time::Duration;
 
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::Utc;
 use clap::Parser;
-use log::{error, info, warn};
-use serde::{Deserialize, Serialize};
+use log::{debug, error, info, warn};
+use protocol::{
+    CancelPayload, CancelResponse, ErrorCode, ErrorResponse, GpuInfo, GpuMetric, HelloPayload, HelloResponse, Message,
+    MetricsPayload, RegisterPayload, RegisterResponse, StatusPayload, StatusResponse,
+};
+use serde::Deserialize;
+use serde::de::{DeserializeOwned, IgnoredAny};
 use sysinfo::System;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
+use tokio::time::{self, Instant};
+
+use sampler::cgroup::{self, JobCpuSampler};
+use sampler::gpu::{self, BackendKind, GpuBackend, GpuDevice};
 
 // ============================================================================
 // 常量定义 (Constants)
@@ -20,62 +32,121 @@ const SOCKET_PATH: &str = "/var/run/node_monitor.sock";
 // 发送监控信息间隔
 const METRICS_SEND_INTERVAL: Duration = Duration::from_secs(60);
 
-// 各种卡取样次数
-const RTX_5090_CHECK_COUNT: i32 = 20;
-const RTX_A6000_CHECK_COUNT: i32 = 20;
-const RTX_4090_CHECK_COUNT: i32 = 20;
-const RTX_3090_CHECK_COUNT: i32 = 60;
-const RTX_A10_CHECK_COUNT: i32 = 60;
+// 与守护进程断开期间最多缓存的样本数 (按发送间隔约 2 小时), 超出时丢弃最旧的
+const MAX_BUFFERED_SAMPLES: usize = 120;
+
+// 重连守护进程的指数退避初始/最大间隔
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
 
-// 默认取样次数
-const DEFAULT_GPU_CHECK_COUNT: i32 = 60;
-const DEFAULT_CPU_CHECK_COUNT: i32 = 60;
+// 连接守护进程和等待答复的超时, 避免守护进程无响应时 prolog 一直卡住
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+// 旧版守护进程不答复无法解析的请求 (包括 HELLO), 超时后按旧版协议重新连接
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
 
-// 对无限次取样的定义 (约 100 天)
-const INFINITE_CHECK_COUNT: i32 = 144000;
+// 退出码, task_prolog.sh 据此决定是否继续启动监控进程 (2 为 clap 的参数错误)
+const EXIT_FAILURE: u8 = 1;
+// 连不上守护进程, 或守护进程暂时不接受新连接
+const EXIT_DAEMON_UNAVAILABLE: u8 = 3;
+// 连上了但没有在超时内得到答复, 或连接中途断开
+const EXIT_NO_RESPONSE: u8 = 4;
+// 守护进程拒绝了请求
+const EXIT_REJECTED: u8 = 5;
 
 // ============================================================================
 // 数据结构定义 (Data Structures)
 // ============================================================================
 
-// 使用与服务端一致的 enum，更加类型安全和易于使用
-#[derive(Serialize, Debug)]
-#[serde(tag = "type", content = "payload")]
-enum Message {
-    #[serde(rename = "REGISTER")]
-    Register(RegisterPayload),
-    #[serde(rename = "METRICS")]
-    Metrics(MetricsPayload),
-    #[serde(rename = "CANCEL")]
-    Cancel(CancelPayload),
+// 守护进程对每个请求答复一行 JSON, status 为 "ok" 时再按请求解析为对应的答复
+#[derive(Deserialize, Debug)]
+struct ResponseStatus {
+    status: String,
 }
 
-#[derive(Serialize, Debug)]
-struct RegisterPayload {
-    job_id: String,
-    gpu_monitor_count: i32,
-    cpu_monitor_count: i32,
-    log_path: PathBuf,
+// 与守护进程的连接, 以及协商出的协议版本
+struct DaemonConnection {
+    reader: BufReader<UnixStream>,
+    version: u32,
+    monitor_launch: MonitorLaunch,
 }
 
-#[derive(Serialize, Debug)]
-struct MetricsPayload {
-    job_id: String,
-    gpu_utilization: f64,
-    gpu_memory_utilization: f64,
-    cpu_utilization: f64,
+// job_helper monitor 由谁启动, 来自守护进程对 HELLO 的答复.
+// register 把它打印到标准输出, 供 task_prolog.sh 判断
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MonitorLaunch {
+    // task_prolog.sh 自行启动 (旧版守护进程也是如此)
+    Prolog,
+    // 守护进程在任务的 cgroup 中启动并看护
+    Supervised,
+    // 守护进程自行采样, 不需要 job_helper monitor
+    NotNeeded,
 }
 
-#[derive(Serialize, Debug)]
-struct CancelPayload {
+impl MonitorLaunch {
+    fn from_hello(response: &HelloResponse) -> Self {
+        if response.daemon_sampling {
+            MonitorLaunch::NotNeeded
+        } else if response.supervise_monitors {
+            MonitorLaunch::Supervised
+        } else {
+            MonitorLaunch::Prolog
+        }
+    }
+}
+
+impl fmt::Display for MonitorLaunch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MonitorLaunch::Prolog => "prolog",
+            MonitorLaunch::Supervised => "supervised",
+            MonitorLaunch::NotNeeded => "none",
+        })
+    }
+}
+
+// job_helper monitor 的采样状态. 采样要等待 nvidia-smi 和 CPU 计数器, 因此整体移到阻塞线程中执行
+struct MetricsSampler {
     job_id: String,
+    cuda_visible_devices: String,
+    gpu_backend: Box<dyn GpuBackend>,
+    gpu_devices: Vec<GpuDevice>,
+    cpu_sampler: Option<JobCpuSampler>,
+    sys: System,
 }
 
-#[derive(Deserialize, Debug)]
-struct DaemonResponse {
-    status: String,
+// 与守护进程之间的通信故障 (而不是守护进程拒绝了请求), 通常稍后重试即可
+#[derive(Debug)]
+enum TransportError {
+    Connect(std::io::Error),
+    ConnectTimeout,
+    Io(std::io::Error),
+    ResponseTimeout,
+    Closed,
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Connect(e) => write!(f, "failed to connect to node monitor daemon: {}", e),
+            TransportError::ConnectTimeout => write!(
+                f,
+                "timed out after {}s connecting to node monitor daemon",
+                CONNECT_TIMEOUT.as_secs()
+            ),
+            TransportError::Io(e) => write!(f, "connection to node monitor daemon failed: {}", e),
+            TransportError::ResponseTimeout => write!(
+                f,
+                "node monitor daemon did not answer within {}s",
+                RESPONSE_TIMEOUT.as_secs()
+            ),
+            TransportError::Closed => write!(f, "node monitor daemon closed the connection without answering"),
+        }
+    }
 }
 
+impl std::error::Error for TransportError {}
+
 // ============================================================================
 // 命令行接口定义 (Command-Line Interface)
 // ============================================================================
@@ -83,6 +154,14 @@ struct DaemonResponse {
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Slurm job monitor client", long_about = None)]
 struct Cli {
+    /// GPU metrics source
+    #[arg(long, global = true, value_enum, env = "JOB_HELPER_GPU_BACKEND", default_value = "auto")]
+    gpu_backend: BackendKind,
+
+    /// Canned readings replayed by the fake GPU backend
+    #[arg(long, global = true, value_name = "PATH", env = "JOB_HELPER_GPU_FAKE_FILE")]
+    gpu_fake_file: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -93,8 +172,21 @@ enum Commands {
         #[arg(value_name = "LOG_PATH")]
         log_path: PathBuf,
     },
-    Monitor,
+    Monitor {
+        /// Job log file to re-register with after reconnecting to the daemon
+        #[arg(long, value_name = "LOG_PATH")]
+        log_path: Option<PathBuf>,
+    },
     Cancel,
+    /// Show how close a job is to being cancelled for idleness
+    Status {
+        /// Job to query (defaults to SLURM_JOB_ID)
+        #[arg(value_name = "JOB_ID")]
+        job_id: Option<String>,
+        /// Print the raw daemon response as JSON
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 // ============================================================================
@@ -102,10 +194,44 @@ enum Commands {
 // ============================================================================
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
     let cli = Cli::parse();
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{:#}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+// 按失败原因区分退出码
+fn exit_code(error: &anyhow::Error) -> u8 {
+    if let Some(rejection) = error.downcast_ref::<ErrorResponse>() {
+        // 例如连接数已达上限, 与连不上守护进程一样处理
+        if rejection.code.is_retryable() {
+            return EXIT_DAEMON_UNAVAILABLE;
+        }
+        return EXIT_REJECTED;
+    }
+    match error.downcast_ref::<TransportError>() {
+        Some(TransportError::Connect(_) | TransportError::ConnectTimeout) => EXIT_DAEMON_UNAVAILABLE,
+        Some(_) => EXIT_NO_RESPONSE,
+        None => EXIT_FAILURE,
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    // status 也可以在登录会话中指定任务号运行
+    if let Commands::Status { job_id, json } = cli.command {
+        let job_id = match job_id {
+            Some(id) => id,
+            None => env::var("SLURM_JOB_ID").context("No job ID given and SLURM_JOB_ID is not set.")?,
+        };
+        return status(&job_id, json).await;
+    }
 
     let job_id = env::var("SLURM_JOB_ID")
         .context("SLURM_JOB_ID environment variable not set. This must be run inside a Slurm job.")?;
@@ -114,9 +240,16 @@ async fn main() -> Result<()> {
         .unwrap_or_default();
 
     match cli.command {
-        Commands::Register { log_path } => register(&job_id, log_path, &cuda_visible_devices).await?,
-        Commands::Monitor => monitor(&job_id, &cuda_visible_devices).await?,
+        Commands::Register { log_path } => {
+            let mut gpu_backend = gpu::create_backend(cli.gpu_backend, cli.gpu_fake_file.as_deref())?;
+            register(&job_id, log_path, &cuda_visible_devices, gpu_backend.as_mut()).await?
+        }
+        Commands::Monitor { log_path } => {
+            let gpu_backend = gpu::create_backend(cli.gpu_backend, cli.gpu_fake_file.as_deref())?;
+            monitor(&job_id, &cuda_visible_devices, log_path, gpu_backend).await?
+        }
         Commands::Cancel => cancel(&job_id).await?,
+        Commands::Status { .. } => unreachable!("handled above"),
     }
 
     Ok(())
@@ -126,180 +259,551 @@ async fn main() -> Result<()> {
 // 命令处理函数 (Command Handlers)
 // ============================================================================
 
-async fn register(job_id: &str, log_path: PathBuf, cuda_visible_devices: &str) -> Result<()> {
-    let job_partition = env::var("SLURM_JOB_PARTITION").unwrap_or_default();
-    let lower_job_partition = job_partition.to_lowercase();
-
-    let (gpu_monitor_count, cpu_monitor_count) = if lower_job_partition.contains("debug") {
-        info!(
-            "Detected debug partition: {}. Setting CPU and GPU check count to {}.",
-            job_partition, INFINITE_CHECK_COUNT
-        );
-        (INFINITE_CHECK_COUNT, INFINITE_CHECK_COUNT)
-    } else if lower_job_partition.contains("gpu") {
-        let gpu_count = determine_gpu_check_count(cuda_visible_devices)?;
-        info!(
-            "Dynamically determined GPU monitoring count: {}, CPU monitoring count: {}",
-            gpu_count, INFINITE_CHECK_COUNT
-        );
-        (gpu_count, INFINITE_CHECK_COUNT)
-    } else {
-        let gpu_count = determine_gpu_check_count(cuda_visible_devices)?;
+async fn register(
+    job_id: &str,
+    log_path: PathBuf,
+    cuda_visible_devices: &str,
+    gpu_backend: &mut dyn GpuBackend,
+) -> Result<()> {
+    let mut connection = connect().await?;
+    // 守护进程自行采样时注册会让它等待 job_helper monitor 的心跳, 因此不注册
+    if connection.monitor_launch == MonitorLaunch::NotNeeded {
         info!(
-            "Dynamically determined GPU monitoring count: {}, CPU monitoring count: {}",
-            gpu_count, DEFAULT_CPU_CHECK_COUNT
+            "Node monitor daemon samples job {} itself, skipping registration.",
+            job_id
         );
-        (gpu_count, DEFAULT_CPU_CHECK_COUNT)
-    };
-
-    // 创建消息
-    let reg_payload = RegisterPayload {
-        job_id: job_id.to_string(),
-        gpu_monitor_count,
-        cpu_monitor_count,
-        log_path: log_path.clone(),
-    };
-    let msg = Message::Register(reg_payload);
-
-    // 序列化消息并添加换行符
-    let mut msg_bytes = serde_json::to_vec(&msg)?;
-    msg_bytes.push(b'\n');
-
-    let stream = UnixStream::connect(SOCKET_PATH)
-        .await
-        .context("Failed to connect to node monitor daemon")?;
-
-    // 使用 BufReader 来读取带缓冲的行
-    let mut reader = BufReader::new(stream);
-
-    reader.write_all(&msg_bytes).await?;
-
-    // 使用 read_line 读取响应
-    let mut response_buf = String::new();
-    reader
-        .read_line(&mut response_buf)
-        .await
-        .context("Failed to read response from daemon")?;
-
-    info!("Received response from daemon: {}", response_buf.trim());
-    let resp: DaemonResponse = serde_json::from_str(&response_buf).context("Failed to decode daemon response")?;
-
-    if resp.status != "ok" {
-        return Err(anyhow!("Registration failed. Daemon response: {:?}", resp));
+        println!("{}", connection.monitor_launch);
+        return Ok(());
     }
+    let reg_payload = registration_payload(job_id, log_path.clone(), cuda_visible_devices, gpu_backend);
+    let resp = register_on(&mut connection, reg_payload)
+        .await
+        .context("Registration failed")?;
 
     info!(
-        "Job {} registered successfully. GPU_Count={}, CPU_Count={}, Log file: {}",
+        "Job {} registered successfully. Daemon assigned GPU window={}, CPU window={}, Log file: {}",
         job_id,
-        gpu_monitor_count,
-        cpu_monitor_count,
+        resp.gpu_window_secs.map_or("off".to_string(), format_secs),
+        resp.cpu_window_secs.map_or("off".to_string(), format_secs),
         log_path.display()
     );
+    println!("{}", connection.monitor_launch);
 
     Ok(())
 }
 
-async fn monitor(job_id: &str, cuda_visible_devices: &str) -> Result<()> {
-    write_pid_file(job_id).context("Failed to write PID file")?;
+fn registration_payload(
+    job_id: &str,
+    log_path: PathBuf,
+    cuda_visible_devices: &str,
+    gpu_backend: &mut dyn GpuBackend,
+) -> RegisterPayload {
+    let gpus = query_job_gpus(cuda_visible_devices, gpu_backend);
+    let allocated_cpus = env::var("SLURM_CPUS_ON_NODE")
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    RegisterPayload {
+        job_id: job_id.to_string(),
+        log_path,
+        partition: env::var("SLURM_JOB_PARTITION").unwrap_or_default(),
+        qos: env::var("SLURM_JOB_QOS").unwrap_or_default(),
+        account: env::var("SLURM_JOB_ACCOUNT").unwrap_or_default(),
+        gpus,
+        allocated_cpus,
+        gpu_monitor_count: None,
+        cpu_monitor_count: None,
+    }
+}
 
-    let mut stream = UnixStream::connect(SOCKET_PATH)
-        .await
-        .context("Monitor failed to connect to daemon")?;
+// 旧版守护进程要求 v1 的取样次数
+async fn register_on(connection: &mut DaemonConnection, payload: RegisterPayload) -> Result<RegisterResponse> {
+    let payload = if connection.version == protocol::LEGACY_VERSION {
+        payload.with_legacy_counts()
+    } else {
+        payload
+    };
+    info!("Registering job with {:?}", payload);
+    request(connection, &Message::Register(payload)).await
+}
 
-    let mut interval = tokio::time::interval(METRICS_SEND_INTERVAL);
-    let mut sys = System::new();
+async fn monitor(
+    job_id: &str,
+    cuda_visible_devices: &str,
+    log_path: Option<PathBuf>,
+    mut gpu_backend: Box<dyn GpuBackend>,
+) -> Result<()> {
+    write_pid_file(job_id).context("Failed to write PID file")?;
+    if log_path.is_none() {
+        warn!("No --log-path given. The job will not be re-registered if the daemon loses track of it.");
+    }
 
-    let has_gpus = !cuda_visible_devices.is_empty();
-    if !has_gpus {
+    let mut interval = time::interval(METRICS_SEND_INTERVAL);
+    // 优先从任务自己的 cgroup 统计 CPU, 找不到时退回到整机 CPU 利用率
+    let cpu_sampler = match JobCpuSampler::new(job_id) {
+        Ok(sampler) => {
+            info!(
+                "Measuring CPU usage from job cgroup {:?} over {} allocated CPUs.",
+                sampler.cgroup(),
+                sampler.allocated_cpus()
+            );
+            Some(sampler)
+        }
+        Err(e) => {
+            warn!(
+                "Could not locate cgroup for job {}: {:#}. Falling back to node-wide CPU utilization.",
+                job_id, e
+            );
+            None
+        }
+    };
+
+    if cuda_visible_devices.is_empty() {
         info!("No GPUs detected (CUDA_VISIBLE_DEVICES is empty). GPU metrics will be reported as 0.");
     }
+    // 重连后重新注册用; 任务的 GPU 在运行期间不变, 启动时查询一次, 重连时不再调用 GPU 后端
+    let registration =
+        log_path.map(|log_path| registration_payload(job_id, log_path, cuda_visible_devices, gpu_backend.as_mut()));
+    let mut sampler = MetricsSampler {
+        job_id: job_id.to_string(),
+        cuda_visible_devices: cuda_visible_devices.to_string(),
+        gpu_backend,
+        gpu_devices: Vec::new(),
+        cpu_sampler,
+        sys: System::new(),
+    };
     info!("Starting monitoring for job {job_id}...");
 
+    // 断开期间的样本先缓存, 重连后按原采样时间补发
+    let mut connection: Option<DaemonConnection> = None;
+    let mut pending: VecDeque<MetricsPayload> = VecDeque::new();
+    let mut reconnect_at = Instant::now();
+    let mut reconnect_delay = RECONNECT_INITIAL_DELAY;
+
     loop {
-        interval.tick().await;
+        tokio::select! {
+            _ = interval.tick() => {
+                let (returned, metrics_payload) = tokio::task::spawn_blocking(move || {
+                    let metrics_payload = sampler.sample();
+                    (sampler, metrics_payload)
+                })
+                .await
+                .context("Sampling task panicked")?;
+                sampler = returned;
+                if pending.len() >= MAX_BUFFERED_SAMPLES {
+                    pending.pop_front();
+                    warn!("Sample buffer is full, dropping the oldest sample.");
+                }
+                pending.push_back(metrics_payload);
+            }
+            _ = time::sleep_until(reconnect_at), if connection.is_none() => {
+                match connect_monitor(registration.as_ref()).await {
+                    Ok(daemon) => {
+                        info!("Connected to node monitor daemon.");
+                        connection = Some(daemon);
+                        reconnect_delay = RECONNECT_INITIAL_DELAY;
+                    }
+                    // 守护进程拒绝注册 (例如任务已被取消) 时不再重试
+                    Err(e) if e.downcast_ref::<ErrorResponse>().is_some_and(|r| !r.code.is_retryable()) => {
+                        return Err(e.context(format!("Daemon refused to monitor job {}", job_id)));
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Could not reach node monitor daemon: {:#}. Retrying in {}s ({} samples buffered).",
+                            e,
+                            reconnect_delay.as_secs(),
+                            pending.len()
+                        );
+                        reconnect_at = Instant::now() + reconnect_delay;
+                        reconnect_delay = (reconnect_delay * 2).min(RECONNECT_MAX_DELAY);
+                    }
+                }
+            }
+        }
 
-        let (gpu_util, gpu_mem_util) = if has_gpus {
-            let util = get_gpu_utilization(cuda_visible_devices).unwrap_or_else(|e| {
-                warn!("Could not get GPU utilization: {}", e);
-                0.0
-            });
-            let mem_util = get_gpu_memory_utilization(cuda_visible_devices).unwrap_or_else(|e| {
-                warn!("Could not get GPU memory utilization: {}", e);
-                0.0
-            });
-            (util, mem_util)
-        } else {
-            // 如果没有 GPU，直接返回 0
-            (0.0, 0.0)
+        let Some(daemon) = connection.as_mut() else {
+            continue;
         };
+        if let Err(e) = send_pending_metrics(daemon, &mut pending).await {
+            if e.downcast_ref::<ErrorResponse>()
+                .is_some_and(|r| r.code == ErrorCode::JobCancelled)
+            {
+                return Err(e.context(format!("Job {} was cancelled by the node monitor", job_id)));
+            }
+            warn!(
+                "Lost connection to node monitor daemon: {:#}. Buffering samples until it is back.",
+                e
+            );
+            connection = None;
+            reconnect_at = Instant::now();
+        }
+    }
+}
 
-        let cpu_util = get_cpu_utilization(&mut sys).unwrap_or_else(|e| {
-            warn!("Could not get CPU utilization: {}", e);
-            0.0
-        });
+impl MetricsSampler {
+    // 阻塞: 调用 GPU 后端, 第一次还要等待一秒取得 CPU 的基准值
+    fn sample(&mut self) -> MetricsPayload {
+        sample_metrics(
+            &self.job_id,
+            &self.cuda_visible_devices,
+            self.gpu_backend.as_mut(),
+            &mut self.gpu_devices,
+            self.cpu_sampler.as_mut(),
+            &mut self.sys,
+        )
+    }
+}
 
-        // 直接创建 MetricsPayload 和 Message enum
-        let metrics_payload = MetricsPayload {
-            job_id: job_id.to_string(),
-            gpu_utilization: gpu_util,
-            gpu_memory_utilization: gpu_mem_util,
-            cpu_utilization: cpu_util,
-        };
-        let msg = Message::Metrics(metrics_payload);
+fn sample_metrics(
+    job_id: &str,
+    cuda_visible_devices: &str,
+    gpu_backend: &mut dyn GpuBackend,
+    gpu_devices: &mut Vec<GpuDevice>,
+    cpu_sampler: Option<&mut JobCpuSampler>,
+    sys: &mut System,
+) -> MetricsPayload {
+    let gpu_usage = if !cuda_visible_devices.is_empty() {
+        sample_job_gpus(gpu_backend, gpu_devices, cuda_visible_devices, job_id).unwrap_or_else(|e| {
+            warn!("Could not get GPU utilization: {:#}", e);
+            Default::default()
+        })
+    } else {
+        // 如果没有 GPU，直接返回 0
+        Default::default()
+    };
+    let (gpu_util, gpu_mem_util) = (gpu_usage.utilization, gpu_usage.memory_utilization);
 
-        // 序列化消息并添加换行符
-        let mut msg_bytes = serde_json::to_vec(&msg)?;
-        msg_bytes.push(b'\n');
+    let cpu_util = match cpu_sampler {
+        Some(sampler) => sampler.sample(),
+        None => get_cpu_utilization(sys),
+    }
+    .unwrap_or_else(|e| {
+        warn!("Could not get CPU utilization: {}", e);
+        0.0
+    });
 
-        if let Err(e) = stream.write_all(&msg_bytes).await {
-            error!(
-                "Failed to send metrics, daemon may have terminated the job or is down. Exiting. Error: {}",
-                e
-            );
-            break;
-        }
+    info!(
+        "Sampled metrics: GPU_Util={:.1}%, GPU_Mem={:.1}%, CPU_Util={:.1}%",
+        gpu_util, gpu_mem_util, cpu_util
+    );
+    MetricsPayload {
+        job_id: job_id.to_string(),
+        gpu_utilization: gpu_util,
+        gpu_memory_utilization: gpu_mem_util,
+        cpu_utilization: cpu_util,
+        gpus: gpu_usage
+            .devices
+            .into_iter()
+            .map(|d| GpuMetric {
+                uuid: d.uuid,
+                utilization: d.utilization,
+                memory_utilization: d.memory_utilization,
+            })
+            .collect(),
+        sampled_at: Some(Utc::now()),
+        gpu_unknown: gpu_usage.unknown,
+    }
+}
 
-        info!(
-            "Sent metrics: GPU_Util={:.1}%, GPU_Mem={:.1}%, CPU_Util={:.1}%",
-            gpu_util, gpu_mem_util, cpu_util
-        );
+// 连接守护进程并重新注册 (守护进程已跟踪该任务时注册是幂等的).
+// 守护进程拒绝注册时返回 ErrorResponse, 例如任务已被取消; 其余错误稍后重试
+async fn connect_monitor(registration: Option<&RegisterPayload>) -> Result<DaemonConnection> {
+    let mut connection = connect().await?;
+    if let Some(payload) = registration {
+        register_on(&mut connection, payload.clone()).await?;
     }
+    Ok(connection)
+}
 
+// 按顺序发送缓存的样本, 守护进程确认 (或明确拒绝) 后才从队列中移除.
+// 旧版守护进程不确认样本, 写入成功即移除. 被限速时保留连接, 剩下的样本留到下一次采样后再发:
+// 重新连接会得到新的限速额度, 等于绕过守护进程的限制
+async fn send_pending_metrics(connection: &mut DaemonConnection, pending: &mut VecDeque<MetricsPayload>) -> Result<()> {
+    let backlog = pending.len();
+    while let Some(metrics_payload) = pending.front() {
+        let msg = Message::Metrics(metrics_payload.clone());
+        if !protocol::has_reply(&msg, connection.version) {
+            // 旧版守护进程不认识 gpu_unknown, 会把其中的 0 当作空闲, 所以不发送这样的样本
+            if !metrics_payload.gpu_unknown {
+                send(connection, &msg).await?;
+            }
+            pending.pop_front();
+            continue;
+        }
+        if let Err(e) = request::<IgnoredAny>(connection, &msg).await {
+            match e.downcast_ref::<ErrorResponse>() {
+                Some(rejection) if rejection.code.is_retryable() => {
+                    warn!(
+                        "Daemon deferred a sample: {}. Sending the {} buffered samples after the next one.",
+                        rejection,
+                        pending.len()
+                    );
+                    return Ok(());
+                }
+                Some(rejection) if rejection.code != ErrorCode::JobCancelled => {
+                    warn!("Daemon rejected a sample: {}", rejection);
+                    pending.pop_front();
+                    // 守护进程不再跟踪该任务, 重连后重新注册
+                    if rejection.code == ErrorCode::UnknownJob {
+                        return Err(e);
+                    }
+                    continue;
+                }
+                _ => return Err(e),
+            }
+        }
+        pending.pop_front();
+    }
+    if backlog > 1 {
+        info!("Replayed {} buffered samples to the daemon.", backlog);
+    }
     Ok(())
 }
 
 async fn cancel(job_id: &str) -> Result<()> {
-    let cancel_payload = CancelPayload {
+    let mut connection = connect().await?;
+    let msg = Message::Cancel(CancelPayload {
         job_id: job_id.to_string(),
-    };
-    let msg = Message::Cancel(cancel_payload);
+    });
+    if !protocol::has_reply(&msg, connection.version) {
+        send(&mut connection, &msg).await?;
+        info!("Sent cancellation request for job {}.", job_id);
+        return Ok(());
+    }
+    let resp: CancelResponse = request(&mut connection, &msg)
+        .await
+        .with_context(|| format!("Failed to cancel monitoring of job {}", job_id))?;
 
-    // 序列化消息并添加换行符
-    let mut msg_bytes = serde_json::to_vec(&msg)?;
-    msg_bytes.push(b'\n');
+    if resp.removed {
+        info!("Successfully sent cancellation request for job {}.", job_id);
+    } else {
+        info!("Job {} was not being monitored by the daemon.", job_id);
+    }
+    Ok(())
+}
 
-    match UnixStream::connect(SOCKET_PATH).await {
-        Ok(mut stream) => {
-            if let Err(e) = stream.write_all(&msg_bytes).await {
-                warn!("Failed to send cancellation request for job {}: {}", job_id, e);
-            } else {
-                info!("Successfully sent cancellation request for job {}.", job_id);
+async fn status(job_id: &str, json: bool) -> Result<()> {
+    let mut connection = connect().await?;
+    let msg = Message::Status(StatusPayload {
+        job_id: job_id.to_string(),
+    });
+    if !protocol::has_reply(&msg, connection.version) {
+        bail!("The node monitor daemon is too old to report job status");
+    }
+    let response_line = request_line(&mut connection, &msg)
+        .await
+        .context("Status query failed")?;
+
+    if json {
+        println!("{}", response_line.trim());
+        return Ok(());
+    }
+    let resp: StatusResponse = serde_json::from_str(&response_line).context("Failed to decode daemon response")?;
+
+    println!("Job {} (partition: {})", job_id, resp.partition);
+    println!("  Enforcement:     {}", resp.enforcement_mode);
+    println!("  Warning stage:   {}", resp.escalation.stage());
+    println!("  Samples sent:    {}", resp.metrics_received);
+    if let Some(lost) = &resp.monitor_lost {
+        println!(
+            "  Monitor:         lost {} ago ({}), cancelled in {} unless it reconnects",
+            format_secs((Utc::now() - lost.since).num_seconds().max(0) as u64),
+            if lost.clean { "closed cleanly" } else { "connection broken" },
+            format_secs((lost.deadline - Utc::now()).num_seconds().max(0) as u64)
+        );
+    }
+    if let Some(supervised) = &resp.supervised_monitor {
+        let state = match supervised.pid {
+            Some(pid) => format!("running as PID {}", pid),
+            None => "not running".to_string(),
+        };
+        println!(
+            "  Supervised:      {}, restarted {} time(s){}",
+            state,
+            supervised.restarts,
+            match &supervised.last_exit {
+                Some(exit) => format!(", last exited with {}", exit),
+                None => String::new(),
             }
+        );
+    }
+    if resp.buffer_remaining_secs > 0 {
+        println!(
+            "  Buffer period:   samples are ignored for {} more",
+            format_secs(resp.buffer_remaining_secs)
+        );
+    }
+    for metric in &resp.metrics {
+        if !metric.enforced {
+            println!("  {:<24} not monitored", metric.name);
+            continue;
         }
-        Err(e) => {
+        println!(
+            "  {:<24} covered {}/{}, max {}, mean {}, threshold {:.1}%",
+            metric.name,
+            format_secs(metric.covered_secs),
+            format_secs(metric.window_secs),
+            format_percent(metric.max),
+            format_percent(metric.mean),
+            metric.threshold
+        );
+        if let Some(secs) = metric.secs_until_idle {
+            println!("  {:<24} idle in ~{} if nothing runs", "", format_secs(secs));
+        }
+    }
+    if !resp.gpus.is_empty() {
+        print!("  Idle GPUs:       {} of {}", resp.idle_gpus, resp.gpus.len());
+        if resp.min_idle_gpus > 0 {
+            print!(" (flagged at {})", resp.min_idle_gpus);
+        }
+        println!();
+    }
+    for gpu in &resp.gpus {
+        println!(
+            "    {} covered {}/{}, max {}",
+            gpu.name,
+            format_secs(gpu.covered_secs),
+            format_secs(gpu.window_secs),
+            format_percent(gpu.max)
+        );
+    }
+    match &resp.closest_to_firing {
+        Some(name) => println!("  Closest to firing: {}", name),
+        None => println!("  No idle checks apply to this job."),
+    }
+    Ok(())
+}
+
+// ============================================================================
+// 守护进程通信 (Daemon Requests)
+// ============================================================================
+
+// 连接守护进程并协商协议版本
+async fn connect() -> Result<DaemonConnection> {
+    connect_to(Path::new(SOCKET_PATH), HELLO_TIMEOUT).await
+}
+
+async fn connect_to(path: &Path, hello_timeout: Duration) -> Result<DaemonConnection> {
+    let mut connection = DaemonConnection {
+        reader: open_stream(path).await?,
+        version: protocol::PROTOCOL_VERSION,
+        monitor_launch: MonitorLaunch::Prolog,
+    };
+
+    send(&mut connection, &Message::Hello(HelloPayload::current())).await?;
+    let response = match time::timeout(hello_timeout, read_response(&mut connection.reader)).await {
+        Ok(response) => response
+            .and_then(|line| serde_json::from_str::<HelloResponse>(&line).context("Failed to decode daemon response")),
+        Err(_) => Err(TransportError::ResponseTimeout.into()),
+    };
+    connection.version = match response {
+        Ok(response) => {
+            connection.monitor_launch = MonitorLaunch::from_hello(&response);
+            response.version
+        }
+        // 旧版守护进程读到 HELLO 后既不答复也不关闭连接: 重新连接, 不发送 HELLO
+        Err(e) if is_silent_peer(&e) => {
             warn!(
-                "Could not connect to node monitor daemon to cancel job {}: {}. The daemon might be down.",
-                job_id, e
+                "Node monitor daemon did not answer HELLO, reconnecting with protocol version {}",
+                protocol::LEGACY_VERSION
             );
+            return Ok(DaemonConnection {
+                reader: open_stream(path).await?,
+                version: protocol::LEGACY_VERSION,
+                monitor_launch: MonitorLaunch::Prolog,
+            });
         }
+        Err(e) => {
+            // 守护进程不认识 HELLO 但答复了错误, 按旧版协议继续
+            let fallback = e.downcast_ref::<ErrorResponse>().and_then(protocol::fallback_version);
+            match fallback {
+                Some(version) => {
+                    warn!(
+                        "Node monitor daemon does not support HELLO, using protocol version {}",
+                        version
+                    );
+                    version
+                }
+                None => return Err(e.context("Protocol handshake with node monitor daemon failed")),
+            }
+        }
+    };
+    debug!("Using protocol version {}", connection.version);
+    Ok(connection)
+}
+
+fn is_silent_peer(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<TransportError>(),
+        Some(TransportError::ResponseTimeout | TransportError::Closed)
+    )
+}
+
+async fn open_stream(path: &Path) -> Result<BufReader<UnixStream>> {
+    match time::timeout(CONNECT_TIMEOUT, UnixStream::connect(path)).await {
+        Ok(Ok(stream)) => Ok(BufReader::new(stream)),
+        Ok(Err(e)) => Err(TransportError::Connect(e).into()),
+        Err(_) => Err(TransportError::ConnectTimeout.into()),
     }
+}
+
+// 发送一个请求并解析答复; 守护进程拒绝请求时返回 ErrorResponse
+async fn request<T: DeserializeOwned>(connection: &mut DaemonConnection, msg: &Message) -> Result<T> {
+    let response_line = request_line(connection, msg).await?;
+    serde_json::from_str(&response_line).context("Failed to decode daemon response")
+}
+
+// 发送一个请求, 返回 status 为 "ok" 的原始答复
+async fn request_line(connection: &mut DaemonConnection, msg: &Message) -> Result<String> {
+    send(connection, msg).await?;
+    read_response(&mut connection.reader).await
+}
+
+// 只发送请求, 不等待答复
+async fn send(connection: &mut DaemonConnection, msg: &Message) -> Result<()> {
+    // 序列化消息并添加换行符
+    let mut msg_bytes = serde_json::to_vec(msg)?;
+    msg_bytes.push(b'\n');
+    connection
+        .reader
+        .get_mut()
+        .write_all(&msg_bytes)
+        .await
+        .map_err(TransportError::Io)?;
     Ok(())
 }
 
+async fn read_response(reader: &mut BufReader<UnixStream>) -> Result<String> {
+    let mut response_line = String::new();
+    match time::timeout(RESPONSE_TIMEOUT, reader.read_line(&mut response_line)).await {
+        Ok(Ok(0)) => return Err(TransportError::Closed.into()),
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => return Err(TransportError::Io(e).into()),
+        Err(_) => return Err(TransportError::ResponseTimeout.into()),
+    }
+
+    let response: ResponseStatus = serde_json::from_str(&response_line).context("Failed to decode daemon response")?;
+    if response.status != "ok" {
+        let error: ErrorResponse =
+            serde_json::from_str(&response_line).context("Failed to decode daemon error response")?;
+        return Err(error.into());
+    }
+    Ok(response_line)
+}
+
 // ============================================================================
 // 辅助函数 (Helper Functions)
 // ============================================================================
 
+fn format_secs(secs: u64) -> String {
+    match secs {
+        s if s >= 3600 && s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s >= 60 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
 fn run_command(program: &str, args: &[&str]) -> Result<String> {
     let output = Command::new(program)
         .args(args)
@@ -319,102 +823,53 @@ fn run_command(program: &str, args: &[&str]) -> Result<String> {
     Ok(str::from_utf8(&output.stdout)?.trim().to_string())
 }
 
-fn get_gpu_check_count(gpu_name: &str) -> i32 {
-    let upper_name = gpu_name.to_uppercase();
-    match upper_name {
-        s if s.contains("5090") => RTX_5090_CHECK_COUNT,
-        s if s.contains("A6000") => RTX_A6000_CHECK_COUNT,
-        s if s.contains("4090") => RTX_4090_CHECK_COUNT,
-        s if s.contains("3090") => RTX_3090_CHECK_COUNT,
-        s if s.contains("A10") => RTX_A10_CHECK_COUNT,
-        _ => DEFAULT_GPU_CHECK_COUNT,
-    }
-}
-
-fn determine_gpu_check_count(cuda_visible_devices: &str) -> Result<i32> {
+fn query_job_gpus(cuda_visible_devices: &str, gpu_backend: &mut dyn GpuBackend) -> Vec<GpuInfo> {
     if cuda_visible_devices.is_empty() {
-        info!("CUDA_VISIBLE_DEVICES is empty. Assuming no GPUs are available. Setting GPU check count to infinite.");
-        return Ok(INFINITE_CHECK_COUNT);
+        info!("CUDA_VISIBLE_DEVICES is empty. Assuming no GPUs are available.");
+        return Vec::new();
     }
 
-    let output = match run_command(
-        "nvidia-smi",
-        &["--query-gpu=index,name", "--format=csv,noheader,nounits", "--id", cuda_visible_devices],
-    ) {
-        Ok(out) => out,
+    match gpu_backend.devices(cuda_visible_devices) {
+        Ok(devices) => devices
+            .into_iter()
+            .map(|device| {
+                info!("  - Detected GPU: {} ({})", device.name, device.uuid);
+                GpuInfo {
+                    uuid: device.uuid,
+                    name: device.name,
+                }
+            })
+            .collect(),
         Err(e) => {
             warn!(
-                "'nvidia-smi' command failed: {}. Assuming no GPUs or driver issue. Setting GPU check count to infinite.",
+                "GPU backend '{}' failed: {:#}. Assuming no GPUs or driver issue.",
+                gpu_backend.name(),
                 e
             );
-            return Ok(INFINITE_CHECK_COUNT);
+            Vec::new()
         }
-    };
-
-    if output.is_empty() {
-        info!("No GPUs detected by nvidia-smi. Setting GPU check count to infinite.");
-        return Ok(INFINITE_CHECK_COUNT);
     }
-
-    let min_check_count = output
-        .lines()
-        .filter_map(|line| line.split(',').nth(1))
-        .map(|gpu_name| {
-            let count = get_gpu_check_count(gpu_name.trim());
-            info!("  - Detected GPU: {} -> Check Count: {}", gpu_name.trim(), count);
-            count
-        })
-        .min()
-        .unwrap_or(DEFAULT_GPU_CHECK_COUNT);
-
-    info!("Setting monitoring count to: {}", min_check_count);
-    Ok(min_check_count)
 }
 
-fn get_gpu_utilization(cuda_visible_devices: &str) -> Result<f64> {
-    let output = run_command(
-        "nvidia-smi",
-        &["--query-gpu=utilization.gpu", "--format=csv,noheader,nounits", "--id", cuda_visible_devices],
-    )?;
-
-    let utils: Vec<f64> = output
-        .lines()
-        .filter_map(|line| line.trim().parse::<f64>().ok())
-        .collect();
-
-    if utils.is_empty() {
-        return Err(anyhow!("No valid GPU utilization data found"));
+// GPU 列表只在第一次成功时查询, 之后每次只采样进程占用
+fn sample_job_gpus(
+    gpu_backend: &mut dyn GpuBackend,
+    devices: &mut Vec<GpuDevice>,
+    cuda_visible_devices: &str,
+    job_id: &str,
+) -> Result<gpu::JobGpuUsage> {
+    if devices.is_empty() {
+        *devices = gpu_backend.devices(cuda_visible_devices)?;
+        if devices.is_empty() {
+            return Err(anyhow!("No GPUs found for CUDA_VISIBLE_DEVICES={}", cuda_visible_devices));
+        }
     }
-
-    Ok(utils.iter().sum::<f64>() / utils.len() as f64)
+    let samples = gpu_backend.process_samples(devices)?;
+    Ok(gpu::job_gpu_usage(devices, &samples, |pid| cgroup::process_in_job(pid, job_id)))
 }
 
-fn get_gpu_memory_utilization(cuda_visible_devices: &str) -> Result<f64> {
-    let output = run_command(
-        "nvidia-smi",
-        &["--query-gpu=memory.used,memory.total", "--format=csv,noheader,nounits", "--id", cuda_visible_devices],
-    )?;
-
-    let percentages: Vec<f64> = output
-        .lines()
-        .filter_map(|line| {
-            let parts: Vec<&str> = line.split(',').map(str::trim).collect();
-            if parts.len() == 2 {
-                let used = parts[0].parse::<f64>().ok()?;
-                let total = parts[1].parse::<f64>().ok()?;
-                if total > 0.0 {
-                    return Some((used / total) * 100.0);
-                }
-            }
-            None
-        })
-        .collect();
-
-    if percentages.is_empty() {
-        return Err(anyhow!("No valid GPU memory usage data found"));
-    }
-
-    Ok(percentages.iter().sum::<f64>() / percentages.len() as f64)
+fn format_percent(value: Option<f64>) -> String {
+    value.map_or("-".to_string(), |v| format!("{:.1}%", v))
 }
 
 fn get_cpu_utilization(sys: &mut System) -> Result<f64> {
@@ -442,3 +897,209 @@ fn write_pid_file(job_id: &str) -> Result<()> {
     info!("PID file written to {}", pid_file_path.display());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    use protocol::AckResponse;
+    use tokio::net::UnixListener;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("job_helper_test_{}_{}.sock", process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn cancel_message() -> Message {
+        Message::Cancel(CancelPayload {
+            job_id: "4242".to_string(),
+        })
+    }
+
+    async fn read_line(reader: &mut BufReader<UnixStream>) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        line
+    }
+
+    // 旧版守护进程: 读到 HELLO 后不答复, 第二个连接上直接收到 v1 请求
+    #[tokio::test]
+    async fn silent_peer_gets_v1_without_hello() {
+        let path = socket_path("silent");
+        let listener = UnixListener::bind(&path).unwrap();
+        let peer = tokio::spawn(async move {
+            let mut first = BufReader::new(listener.accept().await.unwrap().0);
+            let hello = read_line(&mut first).await;
+            let mut second = BufReader::new(listener.accept().await.unwrap().0);
+            (first, hello, read_line(&mut second).await)
+        });
+
+        let mut connection = connect_to(&path, Duration::from_millis(200)).await.unwrap();
+        assert_eq!(connection.version, protocol::LEGACY_VERSION);
+        assert_eq!(connection.monitor_launch, MonitorLaunch::Prolog);
+        send(&mut connection, &cancel_message()).await.unwrap();
+
+        let (_first, hello, request) = peer.await.unwrap();
+        assert!(hello.contains("HELLO"), "{}", hello);
+        assert!(request.contains("CANCEL"), "{}", request);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn peer_closing_on_hello_gets_v1() {
+        let path = socket_path("closing");
+        let listener = UnixListener::bind(&path).unwrap();
+        let peer = tokio::spawn(async move {
+            let mut first = BufReader::new(listener.accept().await.unwrap().0);
+            read_line(&mut first).await;
+            drop(first);
+            let mut second = BufReader::new(listener.accept().await.unwrap().0);
+            read_line(&mut second).await
+        });
+
+        let mut connection = connect_to(&path, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(connection.version, protocol::LEGACY_VERSION);
+        send(&mut connection, &cancel_message()).await.unwrap();
+        assert!(peer.await.unwrap().contains("CANCEL"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn current_peer_negotiates_on_the_same_connection() {
+        let path = socket_path("current");
+        let listener = UnixListener::bind(&path).unwrap();
+        let peer = tokio::spawn(async move {
+            let mut stream = BufReader::new(listener.accept().await.unwrap().0);
+            read_line(&mut stream).await;
+            let mut response = serde_json::to_vec(&HelloResponse::new(protocol::PROTOCOL_VERSION)).unwrap();
+            response.push(b'\n');
+            stream.get_mut().write_all(&response).await.unwrap();
+            read_line(&mut stream).await
+        });
+
+        let mut connection = connect_to(&path, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(connection.version, protocol::PROTOCOL_VERSION);
+        assert_eq!(connection.monitor_launch, MonitorLaunch::Prolog);
+        send(&mut connection, &cancel_message()).await.unwrap();
+        assert!(peer.await.unwrap().contains("CANCEL"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn hello_response_decides_who_starts_the_monitor() {
+        let cases = [
+            (false, false, MonitorLaunch::Prolog, "prolog"),
+            (false, true, MonitorLaunch::Supervised, "supervised"),
+            (true, false, MonitorLaunch::NotNeeded, "none"),
+            // 守护进程自行采样时不看 supervise_monitors
+            (true, true, MonitorLaunch::NotNeeded, "none"),
+        ];
+        for (daemon_sampling, supervise_monitors, expected, printed) in cases {
+            let path = socket_path("launch");
+            let listener = UnixListener::bind(&path).unwrap();
+            let peer = tokio::spawn(async move {
+                let mut stream = BufReader::new(listener.accept().await.unwrap().0);
+                read_line(&mut stream).await;
+                let hello = HelloResponse {
+                    daemon_sampling,
+                    supervise_monitors,
+                    ..HelloResponse::new(protocol::PROTOCOL_VERSION)
+                };
+                let mut response = serde_json::to_vec(&hello).unwrap();
+                response.push(b'\n');
+                stream.get_mut().write_all(&response).await.unwrap();
+                stream
+            });
+
+            let connection = connect_to(&path, Duration::from_secs(5)).await.unwrap();
+            assert_eq!(connection.monitor_launch, expected);
+            assert_eq!(connection.monitor_launch.to_string(), printed);
+            drop(peer.await.unwrap());
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejecting_peer_gets_v1_on_the_same_connection() {
+        let path = socket_path("rejecting");
+        let listener = UnixListener::bind(&path).unwrap();
+        let peer = tokio::spawn(async move {
+            let mut stream = BufReader::new(listener.accept().await.unwrap().0);
+            read_line(&mut stream).await;
+            let rejection = ErrorResponse::new(ErrorCode::InvalidRequest, "unknown variant `HELLO`");
+            let mut response = serde_json::to_vec(&rejection).unwrap();
+            response.push(b'\n');
+            stream.get_mut().write_all(&response).await.unwrap();
+            read_line(&mut stream).await
+        });
+
+        let mut connection = connect_to(&path, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(connection.version, protocol::LEGACY_VERSION);
+        send(&mut connection, &cancel_message()).await.unwrap();
+        assert!(peer.await.unwrap().contains("CANCEL"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn sample(cpu_utilization: f64) -> MetricsPayload {
+        MetricsPayload {
+            job_id: "4242".to_string(),
+            gpu_utilization: 0.0,
+            gpu_memory_utilization: 0.0,
+            cpu_utilization,
+            gpus: Vec::new(),
+            sampled_at: Some(Utc::now()),
+            gpu_unknown: false,
+        }
+    }
+
+    async fn write_json<T: serde::Serialize>(stream: &mut BufReader<UnixStream>, response: &T) {
+        let mut response = serde_json::to_vec(response).unwrap();
+        response.push(b'\n');
+        stream.get_mut().write_all(&response).await.unwrap();
+    }
+
+    // 被限速时保留连接和样本, 下一次在同一个连接上补发, 而不是重新连接换取新的额度
+    #[tokio::test]
+    async fn rate_limited_samples_wait_on_the_same_connection() {
+        let path = socket_path("rate_limited");
+        let listener = UnixListener::bind(&path).unwrap();
+        let peer = tokio::spawn(async move {
+            let mut stream = BufReader::new(listener.accept().await.unwrap().0);
+            read_line(&mut stream).await;
+            write_json(&mut stream, &HelloResponse::new(protocol::PROTOCOL_VERSION)).await;
+
+            let mut received = Vec::new();
+            received.push(read_line(&mut stream).await);
+            let rejection = ErrorResponse::new(ErrorCode::RateLimited, "Rate limit exceeded, retry in 1.0s");
+            write_json(&mut stream, &rejection).await;
+            for _ in 0..2 {
+                received.push(read_line(&mut stream).await);
+                write_json(&mut stream, &AckResponse::ok()).await;
+            }
+            received
+        });
+
+        let mut connection = connect_to(&path, Duration::from_secs(5)).await.unwrap();
+        let mut pending = VecDeque::from([sample(1.0), sample(2.0)]);
+
+        send_pending_metrics(&mut connection, &mut pending).await.unwrap();
+        assert_eq!(pending.len(), 2);
+
+        send_pending_metrics(&mut connection, &mut pending).await.unwrap();
+        assert!(pending.is_empty());
+
+        let received = peer.await.unwrap();
+        let cpu: Vec<f64> = received
+            .iter()
+            .map(|line| match protocol::parse_message(line) {
+                Ok(Message::Metrics(metrics)) => metrics.cpu_utilization,
+                other => panic!("unexpected request {:?}", other.map_err(|e| e.message)),
+            })
+            .collect();
+        assert_eq!(cpu, [1.0, 1.0, 2.0]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
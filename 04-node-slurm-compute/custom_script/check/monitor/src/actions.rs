@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, Notify};
+use tokio::time;
+
+use crate::state::write_atomic;
+use crate::{SharedConfig, kill_slurm_job};
+
+// ============================================================================
+// 常量定义 (Constants)
+// ============================================================================
+
+// 队列处理间隔
+const ACTION_QUEUE_TICK: Duration = Duration::from_secs(5);
+
+// 已完成的记录保留多久, 方便管理员事后查看
+const COMPLETED_ACTION_RETENTION: chrono::Duration = chrono::Duration::hours(24);
+
+// ============================================================================
+// 数据结构定义 (Data Structures)
+// ============================================================================
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionState {
+    // 还在重试 scancel 或等待任务真正结束
+    Pending,
+    // 已确认任务不再运行
+    Done,
+    // 超过最大重试次数, 需要管理员介入
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KillAction {
+    pub job_id: String,
+    pub reason: String,
+    pub state: ActionState,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+}
+
+// 一次处理中要对任务做的事
+#[derive(Debug, PartialEq, Eq)]
+enum Attempt {
+    // 已确认任务结束, 或已放弃, 不再重试
+    Finished,
+    // 任务还在, scancel 它
+    Cancel,
+    // 等待下一次确认
+    Wait,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ActionQueue {
+    actions: HashMap<String, KillAction>,
+}
+
+pub type SharedActionQueue = Arc<ActionQueueHandle>;
+
+pub struct ActionQueueHandle {
+    queue: Mutex<ActionQueue>,
+    wakeup: Notify,
+}
+
+// ============================================================================
+// 队列操作 (Queue Operations)
+// ============================================================================
+
+impl ActionQueue {
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .with_context(|| format!("Failed to parse action queue {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read action queue {}", path.display())),
+        }
+    }
+
+    pub fn actions(&self) -> impl Iterator<Item = &KillAction> {
+        self.actions.values()
+    }
+
+    // 未完成的动作一直保留; 已完成的保留一段时间, 期间 has_kill 仍拒绝该任务重新注册
+    fn prune(&mut self, now: DateTime<Utc>) {
+        self.actions
+            .retain(|_, a| a.state == ActionState::Pending || now - a.updated_at < COMPLETED_ACTION_RETENTION);
+    }
+}
+
+impl ActionQueueHandle {
+    pub fn new(queue: ActionQueue) -> SharedActionQueue {
+        Arc::new(Self {
+            queue: Mutex::new(queue),
+            wakeup: Notify::new(),
+        })
+    }
+
+    // 守护进程已经决定取消该任务 (无论 scancel 是否完成), 不再接受重新注册
+    pub async fn has_kill(&self, job_id: &str) -> bool {
+        self.queue.lock().await.actions.contains_key(job_id)
+    }
+
+    // 同一任务已有未完成的取消动作时不重复入队
+    pub async fn enqueue_kill(&self, job_id: &str, reason: &str) {
+        let mut queue = self.queue.lock().await;
+        if queue
+            .actions
+            .get(job_id)
+            .is_some_and(|a| a.state == ActionState::Pending)
+        {
+            info!("Kill for job {} is already queued, ignoring duplicate request.", job_id);
+            return;
+        }
+
+        let now = Utc::now();
+        info!("[KILL] Queued cancellation of job {}, Reason: {}", job_id, reason);
+        queue.actions.insert(
+            job_id.to_string(),
+            KillAction {
+                job_id: job_id.to_string(),
+                reason: reason.to_string(),
+                state: ActionState::Pending,
+                created_at: now,
+                updated_at: now,
+                attempts: 0,
+                next_attempt_at: now,
+                last_error: None,
+            },
+        );
+        drop(queue);
+        self.wakeup.notify_one();
+    }
+}
+
+// ============================================================================
+// 队列处理 (Queue Worker)
+// ============================================================================
+
+pub async fn run_action_queue(actions: SharedActionQueue, config: SharedConfig) {
+    loop {
+        tokio::select! {
+            _ = time::sleep(ACTION_QUEUE_TICK) => {}
+            _ = actions.wakeup.notified() => {}
+        }
+
+        let (queue_path, max_attempts, retry_initial, retry_max) = {
+            let config_lock = config.read().await;
+            (
+                config_lock.kill_queue_path.clone(),
+                config_lock.kill_max_attempts,
+                config_lock.kill_retry_initial,
+                config_lock.kill_retry_max,
+            )
+        };
+
+        // 只在锁内挑出到期的动作, scancel/squeue 在锁外执行
+        let now = Utc::now();
+        let due: Vec<KillAction> = actions
+            .queue
+            .lock()
+            .await
+            .actions
+            .values()
+            .filter(|a| a.state == ActionState::Pending && a.next_attempt_at <= now)
+            .cloned()
+            .collect();
+
+        let mut updated = Vec::new();
+        for mut action in due {
+            attempt_kill(&mut action, max_attempts, retry_initial, retry_max).await;
+            updated.push(action);
+        }
+
+        let bytes = {
+            let mut queue = actions.queue.lock().await;
+            for action in updated {
+                // 处理期间可能被重新入队, 只覆盖仍是同一次请求的记录
+                if queue
+                    .actions
+                    .get(&action.job_id)
+                    .is_some_and(|a| a.created_at == action.created_at)
+                {
+                    queue.actions.insert(action.job_id.clone(), action);
+                }
+            }
+            queue.prune(now);
+            serde_json::to_vec_pretty(&*queue)
+        };
+
+        match bytes {
+            Ok(bytes) => {
+                if let Err(e) = write_atomic(&queue_path, &bytes).await {
+                    error!("Failed to persist action queue to {}: {:#}", queue_path.display(), e);
+                }
+            }
+            Err(e) => error!("Failed to serialize action queue: {}", e),
+        }
+    }
+}
+
+// 先确认任务是否还在; 还在就 scancel, 并按指数退避安排下一次确认
+async fn attempt_kill(action: &mut KillAction, max_attempts: u32, retry_initial: Duration, retry_max: Duration) {
+    let now = Utc::now();
+    action.updated_at = now;
+
+    let job_state = query_job_state(&action.job_id).await;
+    if plan_attempt(action, job_state, max_attempts) == Attempt::Cancel {
+        match kill_slurm_job(&action.job_id, &action.reason).await {
+            Ok(()) => action.last_error = None,
+            Err(e) => action.last_error = Some(format!("{:#}", e)),
+        }
+    }
+    if action.state == ActionState::Pending {
+        let backoff = retry_backoff(action.attempts, retry_initial, retry_max);
+        action.next_attempt_at = now + chrono::Duration::from_std(backoff).unwrap_or(chrono::Duration::MAX);
+    }
+}
+
+// 根据 squeue 查到的任务状态更新动作的状态和重试次数, 返回这一次要做什么
+fn plan_attempt(action: &mut KillAction, job_state: Result<Option<String>>, max_attempts: u32) -> Attempt {
+    let job_state = match job_state {
+        Ok(None) => {
+            info!("Job {} is no longer running, kill confirmed.", action.job_id);
+            action.state = ActionState::Done;
+            action.last_error = None;
+            return Attempt::Finished;
+        }
+        Ok(Some(state)) => Some(state),
+        Err(e) => {
+            warn!("Could not query state of job {}: {:#}", action.job_id, e);
+            action.last_error = Some(format!("{:#}", e));
+            None
+        }
+    };
+
+    if action.attempts >= max_attempts {
+        error!(
+            "Giving up on cancelling job {} after {} attempts. Last error: {}",
+            action.job_id,
+            action.attempts,
+            action.last_error.as_deref().unwrap_or("job still running")
+        );
+        action.state = ActionState::Failed;
+        return Attempt::Finished;
+    }
+    action.attempts += 1;
+
+    match job_state.as_deref() {
+        // scancel 已生效, 任务正在清理, 等下次确认即可
+        Some("COMPLETING") if action.attempts > 1 => {
+            action.last_error = Some("Job is still COMPLETING".to_string());
+            Attempt::Wait
+        }
+        Some(_) => Attempt::Cancel,
+        // 无法确认状态 (例如 slurmctld 暂时不可达) 时不盲目 scancel, 等待下次重试
+        None => Attempt::Wait,
+    }
+}
+
+// 第 attempts 次尝试之后的等待时间: 从 retry_initial 起每次翻倍, 不超过 retry_max
+fn retry_backoff(attempts: u32, retry_initial: Duration, retry_max: Duration) -> Duration {
+    retry_initial
+        .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(retry_max)
+}
+
+// 返回任务在 Slurm 中的状态, 任务已不存在时返回 None
+async fn query_job_state(job_id: &str) -> Result<Option<String>> {
+    let mut cmd = tokio::process::Command::new("squeue");
+    cmd.args(["--noheader", "--format=%T", "--jobs", job_id]);
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+    let output = cmd.output().await.context("Failed to execute 'squeue'")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        if stderr.contains("Invalid job id") {
+            return Ok(None);
+        }
+        return Err(anyhow!(
+            "'squeue' failed with status {}: {}",
+            output.status,
+            stderr.trim()
+        ));
+    }
+
+    Ok(running_state(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// squeue 输出的任务状态; 没有输出 (任务已从队列中清除) 或已是终止状态时返回 None
+fn running_state(squeue_output: &str) -> Option<String> {
+    let state = squeue_output.trim();
+    match state {
+        "" | "COMPLETED" | "CANCELLED" | "FAILED" | "TIMEOUT" | "NODE_FAIL" | "PREEMPTED" | "OUT_OF_MEMORY"
+        | "BOOT_FAIL" | "DEADLINE" => None,
+        _ => Some(state.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INITIAL: Duration = Duration::from_secs(30);
+    const MAX: Duration = Duration::from_secs(600);
+
+    fn action(state: ActionState, attempts: u32, updated_at: DateTime<Utc>) -> KillAction {
+        KillAction {
+            job_id: "42".to_string(),
+            reason: "idle".to_string(),
+            state,
+            created_at: updated_at,
+            updated_at,
+            attempts,
+            next_attempt_at: updated_at,
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn retries_back_off_up_to_the_maximum() {
+        let delays: Vec<u64> = (1..=7).map(|n| retry_backoff(n, INITIAL, MAX).as_secs()).collect();
+        assert_eq!(delays, [30, 60, 120, 240, 480, 600, 600]);
+        assert_eq!(retry_backoff(0, INITIAL, MAX), INITIAL);
+        assert_eq!(retry_backoff(u32::MAX, INITIAL, Duration::MAX), INITIAL * (1 << 16));
+    }
+
+    #[test]
+    fn classifies_squeue_states() {
+        assert_eq!(running_state("RUNNING\n"), Some("RUNNING".to_string()));
+        assert_eq!(running_state("  COMPLETING \n"), Some("COMPLETING".to_string()));
+        // 不认识的状态按仍在运行处理, 继续重试
+        assert_eq!(running_state("SOME_NEW_STATE"), Some("SOME_NEW_STATE".to_string()));
+        for state in ["", "\n", "COMPLETED", "CANCELLED\n", "TIMEOUT", "OUT_OF_MEMORY"] {
+            assert_eq!(running_state(state), None, "{:?}", state);
+        }
+    }
+
+    #[test]
+    fn running_jobs_are_cancelled_until_the_attempts_run_out() {
+        let mut kill = action(ActionState::Pending, 0, Utc::now());
+        for attempt in 1..=3 {
+            assert_eq!(
+                plan_attempt(&mut kill, Ok(Some("RUNNING".to_string())), 3),
+                Attempt::Cancel
+            );
+            assert_eq!(kill.attempts, attempt);
+        }
+        assert_eq!(
+            plan_attempt(&mut kill, Ok(Some("RUNNING".to_string())), 3),
+            Attempt::Finished
+        );
+        assert_eq!(kill.state, ActionState::Failed);
+        assert_eq!(kill.attempts, 3);
+    }
+
+    #[test]
+    fn ended_jobs_confirm_the_kill() {
+        let mut kill = action(ActionState::Pending, 2, Utc::now());
+        kill.last_error = Some("Job is still COMPLETING".to_string());
+        assert_eq!(plan_attempt(&mut kill, Ok(None), 3), Attempt::Finished);
+        assert_eq!(kill.state, ActionState::Done);
+        assert_eq!(kill.last_error, None);
+    }
+
+    #[test]
+    fn completing_jobs_and_unknown_states_are_not_cancelled_again() {
+        let mut kill = action(ActionState::Pending, 0, Utc::now());
+        // 第一次看到 COMPLETING 仍要 scancel
+        assert_eq!(
+            plan_attempt(&mut kill, Ok(Some("COMPLETING".to_string())), 5),
+            Attempt::Cancel
+        );
+        assert_eq!(
+            plan_attempt(&mut kill, Ok(Some("COMPLETING".to_string())), 5),
+            Attempt::Wait
+        );
+        assert_eq!(kill.last_error.as_deref(), Some("Job is still COMPLETING"));
+
+        assert_eq!(
+            plan_attempt(&mut kill, Err(anyhow!("slurmctld is down")), 5),
+            Attempt::Wait
+        );
+        assert_eq!(kill.last_error.as_deref(), Some("slurmctld is down"));
+        assert_eq!((kill.state, kill.attempts), (ActionState::Pending, 3));
+    }
+
+    #[tokio::test]
+    async fn finished_kills_block_registration_for_a_day() {
+        let now = Utc::now();
+        let mut queue = ActionQueue::default();
+        for (job_id, state, age) in [
+            ("1", ActionState::Done, chrono::Duration::hours(23)),
+            ("2", ActionState::Failed, chrono::Duration::hours(25)),
+            ("3", ActionState::Pending, chrono::Duration::hours(48)),
+        ] {
+            let mut kill = action(state, 1, now - age);
+            kill.job_id = job_id.to_string();
+            queue.actions.insert(job_id.to_string(), kill);
+        }
+        queue.prune(now);
+
+        let actions = ActionQueueHandle::new(queue);
+        assert!(actions.has_kill("1").await);
+        assert!(!actions.has_kill("2").await);
+        assert!(actions.has_kill("3").await);
+    }
+}
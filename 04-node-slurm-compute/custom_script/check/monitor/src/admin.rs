@@ -0,0 +1,707 @@
+use std::collections::BTreeMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{Local, TimeDelta, Utc};
+use log::{error, info, warn};
+use protocol::{Exemption, MetricStatus, StatusResponse};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::config::parse_duration;
+use crate::escalation::Escalation;
+use crate::exporter::SharedStats;
+use crate::status;
+use crate::tracker::SharedTracker;
+use crate::{SharedConfig, log_to_job_file, reload_config, setup_socket};
+
+// ============================================================================
+// 命令行接口定义 (Command-Line Interface)
+// ============================================================================
+
+#[derive(clap::Subcommand, Debug)]
+pub enum CtlCommand {
+    /// List tracked jobs with their window statistics
+    List,
+    /// Show the full monitoring state of a job
+    Show {
+        #[arg(value_name = "JOB_ID")]
+        job_id: String,
+    },
+    /// Exempt a job from idle cancellation
+    Exempt {
+        #[arg(value_name = "JOB_ID")]
+        job_id: String,
+        /// Only exempt the job for this long (e.g. 90s, 30m, 2h, 1d); defaults to the rest of the job
+        #[arg(long = "for", value_name = "DURATION", value_parser = parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// Stop tracking a job without cancelling it
+    Forget {
+        #[arg(value_name = "JOB_ID")]
+        job_id: String,
+    },
+    /// Pause idle cancellation for all jobs on this node
+    Pause,
+    /// Resume idle cancellation on this node
+    Resume,
+    /// Reload the daemon config file
+    Reload,
+    /// Show client socket counters and open connections per UID
+    Stats,
+}
+
+// ============================================================================
+// 数据结构定义 (Data Structures)
+// ============================================================================
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum AdminRequest {
+    List,
+    Show { job_id: String },
+    Exempt { job_id: String, for_secs: Option<u64> },
+    Forget { job_id: String },
+    Pause,
+    Resume,
+    Reload,
+    Stats,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AdminResponse {
+    status: String,
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    jobs: Vec<StatusResponse>,
+    #[serde(default)]
+    stats: Option<SocketStats>,
+}
+
+// 客户端 socket 的计数器 (与 /metrics 中的同名指标一致) 和每个 UID 当前打开的连接数
+#[derive(Serialize, Deserialize, Debug)]
+struct SocketStats {
+    counters: BTreeMap<String, u64>,
+    connected_clients: i64,
+    connections_by_uid: BTreeMap<u32, usize>,
+}
+
+impl AdminResponse {
+    fn ok(message: impl Into<String>) -> Self {
+        Self {
+            status: "ok".to_string(),
+            message: Some(message.into()),
+            jobs: Vec::new(),
+            stats: None,
+        }
+    }
+
+    fn jobs(jobs: Vec<StatusResponse>) -> Self {
+        Self {
+            status: "ok".to_string(),
+            message: None,
+            jobs,
+            stats: None,
+        }
+    }
+
+    fn stats(stats: SocketStats) -> Self {
+        Self {
+            status: "ok".to_string(),
+            message: None,
+            jobs: Vec::new(),
+            stats: Some(stats),
+        }
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        Self {
+            status: "error".to_string(),
+            message: Some(message.into()),
+            jobs: Vec::new(),
+            stats: None,
+        }
+    }
+}
+
+impl From<CtlCommand> for AdminRequest {
+    fn from(command: CtlCommand) -> Self {
+        match command {
+            CtlCommand::List => AdminRequest::List,
+            CtlCommand::Show { job_id } => AdminRequest::Show { job_id },
+            CtlCommand::Exempt { job_id, duration } => AdminRequest::Exempt {
+                job_id,
+                for_secs: duration.map(|d| d.as_secs()),
+            },
+            CtlCommand::Forget { job_id } => AdminRequest::Forget { job_id },
+            CtlCommand::Pause => AdminRequest::Pause,
+            CtlCommand::Resume => AdminRequest::Resume,
+            CtlCommand::Reload => AdminRequest::Reload,
+            CtlCommand::Stats => AdminRequest::Stats,
+        }
+    }
+}
+
+// ============================================================================
+// 管理 socket 服务端 (Admin Socket Server)
+// ============================================================================
+
+// 管理 socket 只允许 root 访问: 文件权限 0600, 并且再次校验对端 UID
+pub async fn run_admin_server(
+    socket_path: PathBuf,
+    config_path: PathBuf,
+    tracker: SharedTracker,
+    config: SharedConfig,
+    stats: SharedStats,
+) {
+    let listener = match bind_admin_socket(&socket_path).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!(
+                "Failed to set up admin socket, `node_monitor ctl` is unavailable: {:#}",
+                e
+            );
+            return;
+        }
+    };
+    info!("Admin socket listening on {}", socket_path.display());
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _addr)) => {
+                tokio::spawn(handle_admin_connection(
+                    stream,
+                    config_path.clone(),
+                    tracker.clone(),
+                    config.clone(),
+                    stats.clone(),
+                ));
+            }
+            Err(e) => error!("Failed to accept admin connection: {}", e),
+        }
+    }
+}
+
+async fn bind_admin_socket(path: &Path) -> Result<UnixListener> {
+    setup_socket(path).await?;
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("Failed to listen on unix socket {}", path.display()))?;
+    fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await?;
+    Ok(listener)
+}
+
+async fn handle_admin_connection(
+    stream: UnixStream,
+    config_path: PathBuf,
+    tracker: SharedTracker,
+    config: SharedConfig,
+    stats: SharedStats,
+) {
+    let mut reader = BufReader::new(stream);
+    let response = match reader.get_ref().peer_cred() {
+        Ok(cred) if cred.uid() == 0 => {
+            let mut line = String::new();
+            match reader.read_line(&mut line).await {
+                Ok(_) => match serde_json::from_str::<AdminRequest>(line.trim()) {
+                    Ok(request) => {
+                        info!("Admin request: {:?}", request);
+                        handle_admin_request(request, &config_path, &tracker, &config, &stats)
+                            .await
+                            .unwrap_or_else(|e| AdminResponse::error(format!("Admin request failed: {:#}", e)))
+                    }
+                    Err(e) => AdminResponse::error(format!("Invalid admin request: {}", e)),
+                },
+                Err(e) => {
+                    error!("Failed to read admin request: {}", e);
+                    return;
+                }
+            }
+        }
+        Ok(cred) => {
+            warn!("Rejected admin connection from UID {}", cred.uid());
+            AdminResponse::error("The admin socket is restricted to root")
+        }
+        Err(e) => {
+            error!("Failed to read admin peer credentials, closing connection: {}", e);
+            return;
+        }
+    };
+
+    let mut response_bytes = match serde_json::to_vec(&response) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Failed to serialize admin response: {}", e);
+            return;
+        }
+    };
+    response_bytes.push(b'\n');
+    if let Err(e) = reader.get_mut().write_all(&response_bytes).await {
+        error!("Error writing admin response: {}", e);
+    }
+}
+
+async fn handle_admin_request(
+    request: AdminRequest,
+    config_path: &Path,
+    tracker: &SharedTracker,
+    config: &SharedConfig,
+    stats: &SharedStats,
+) -> Result<AdminResponse> {
+    let response = match request {
+        AdminRequest::List => {
+            let config = config.read().await.clone();
+            let mut jobs: Vec<StatusResponse> = tracker
+                .with(move |tracker| {
+                    tracker
+                        .jobs
+                        .iter()
+                        .map(|(job_id, job)| status::job_status(job_id, job, &config, tracker.enforcement_paused))
+                        .collect()
+                })
+                .await?;
+            jobs.sort_by(|a, b| a.job_id.cmp(&b.job_id));
+            AdminResponse::jobs(jobs)
+        }
+        AdminRequest::Show { job_id } => {
+            let config = config.read().await.clone();
+            tracker
+                .with(move |tracker| match tracker.jobs.get(&job_id) {
+                    Some(job) => AdminResponse::jobs(vec![status::job_status(
+                        &job_id,
+                        job,
+                        &config,
+                        tracker.enforcement_paused,
+                    )]),
+                    None => AdminResponse::error(format!("Job {} is not tracked", job_id)),
+                })
+                .await?
+        }
+        AdminRequest::Exempt { job_id, for_secs } => {
+            let until = match for_secs {
+                Some(secs) => {
+                    let until = i64::try_from(secs)
+                        .ok()
+                        .and_then(TimeDelta::try_seconds)
+                        .and_then(|duration| Utc::now().checked_add_signed(duration));
+                    let Some(until) = until else {
+                        return Ok(AdminResponse::error(format!(
+                            "Exemption of {} seconds is too long",
+                            secs
+                        )));
+                    };
+                    Some(until)
+                }
+                None => None,
+            };
+            let exempted = {
+                let job_id = job_id.clone();
+                tracker
+                    .with(move |tracker| {
+                        let job = tracker.jobs.get_mut(&job_id)?;
+                        job.exemption = Some(Exemption { until });
+                        job.escalation = Escalation::Active;
+                        let owner = (job.owner_uid, job.log_path.clone());
+                        tracker.mark_changed();
+                        Some(owner)
+                    })
+                    .await?
+            };
+            let Some((owner_uid, log_path)) = exempted else {
+                return Ok(AdminResponse::error(format!("Job {} is not tracked", job_id)));
+            };
+
+            let message = match until {
+                Some(until) => format!(
+                    "Job {} is exempt from idle cancellation until {}",
+                    job_id,
+                    until.with_timezone(&Local).format("%Y-%m-%dT%H:%M:%S")
+                ),
+                None => format!("Job {} is exempt from idle cancellation", job_id),
+            };
+            info!("{}", message);
+            log_to_job_file(owner_uid, &log_path, &format!("{} (set by administrator)", message)).await;
+            AdminResponse::ok(message)
+        }
+        AdminRequest::Forget { job_id } => {
+            let removed = {
+                let job_id = job_id.clone();
+                tracker
+                    .with(move |tracker| tracker.remove_job(&job_id).is_some())
+                    .await?
+            };
+            if removed {
+                info!("Forgot job {} on admin request, it will not be cancelled.", job_id);
+                AdminResponse::ok(format!("Job {} is no longer tracked", job_id))
+            } else {
+                AdminResponse::error(format!("Job {} is not tracked", job_id))
+            }
+        }
+        AdminRequest::Pause | AdminRequest::Resume => {
+            let paused = matches!(request, AdminRequest::Pause);
+            tracker
+                .with(move |tracker| {
+                    tracker.enforcement_paused = paused;
+                    tracker.mark_changed();
+                })
+                .await?;
+            if paused {
+                warn!("Enforcement paused by administrator, no jobs will be cancelled.");
+                AdminResponse::ok("Enforcement paused on this node")
+            } else {
+                info!("Enforcement resumed by administrator.");
+                AdminResponse::ok("Enforcement resumed on this node")
+            }
+        }
+        AdminRequest::Reload => match reload_config(config_path, config).await {
+            Ok(()) => AdminResponse::ok(format!("Config reloaded from {}", config_path.display())),
+            Err(e) => {
+                error!("Failed to reload config, keeping the current one: {:#}", e);
+                AdminResponse::error(format!("Failed to reload config: {:#}", e))
+            }
+        },
+        AdminRequest::Stats => AdminResponse::stats(SocketStats {
+            counters: stats
+                .counters()
+                .into_iter()
+                .map(|(name, _, value)| (name.to_string(), value))
+                .collect(),
+            connected_clients: stats.connected_clients(),
+            connections_by_uid: stats.connections_by_uid(),
+        }),
+    };
+    Ok(response)
+}
+
+// ============================================================================
+// 管理命令客户端 (ctl Client)
+// ============================================================================
+
+pub async fn run_ctl(socket_path: &Path, command: CtlCommand, json: bool) -> Result<()> {
+    let request = AdminRequest::from(command);
+    let is_list = matches!(request, AdminRequest::List);
+
+    let mut request_bytes = serde_json::to_vec(&request)?;
+    request_bytes.push(b'\n');
+
+    let stream = UnixStream::connect(socket_path)
+        .await
+        .with_context(|| format!("Failed to connect to admin socket {}", socket_path.display()))?;
+    let mut reader = BufReader::new(stream);
+    reader.write_all(&request_bytes).await?;
+
+    let mut response_buf = String::new();
+    reader
+        .read_line(&mut response_buf)
+        .await
+        .context("Failed to read response from daemon")?;
+    if response_buf.trim().is_empty() {
+        bail!("Daemon closed the admin connection without answering");
+    }
+    let response: AdminResponse = serde_json::from_str(&response_buf).context("Failed to decode daemon response")?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&response)?);
+    } else if is_list {
+        print_job_table(&response.jobs);
+    } else if let Some(stats) = &response.stats {
+        print_stats(stats);
+    } else {
+        for job in &response.jobs {
+            print_job(job);
+        }
+    }
+
+    if response.status != "ok" {
+        return Err(anyhow!(
+            "{}",
+            response.message.unwrap_or_else(|| "Admin request failed".to_string())
+        ));
+    }
+    if let Some(message) = response.message.filter(|_| !json) {
+        println!("{}", message);
+    }
+    Ok(())
+}
+
+fn print_job_table(jobs: &[StatusResponse]) {
+    if jobs.is_empty() {
+        println!("No jobs are being tracked.");
+        return;
+    }
+    println!(
+        "{:<10} {:<8} {:<12} {:>8} {:<18} {:<18} {:<18} {:<14} EXEMPT",
+        "JOBID", "UID", "PARTITION", "SAMPLES", "GPU", "GPU_MEM", "CPU", "STAGE"
+    );
+    for job in jobs {
+        let windows: Vec<String> = job.metrics.iter().map(format_window).collect();
+        println!(
+            "{:<10} {:<8} {:<12} {:>8} {:<18} {:<18} {:<18} {:<14} {}",
+            job.job_id,
+            job.owner_uid,
+            job.partition,
+            job.metrics_received,
+            windows.first().map_or("-", String::as_str),
+            windows.get(1).map_or("-", String::as_str),
+            windows.get(2).map_or("-", String::as_str),
+            job.escalation.stage(),
+            format_exemption(job.exemption.as_ref())
+        );
+    }
+    if jobs.first().is_some_and(|j| j.enforcement_paused) {
+        println!("Enforcement is paused on this node.");
+    }
+}
+
+fn print_stats(stats: &SocketStats) {
+    for (name, value) in &stats.counters {
+        println!("{:<52} {}", name, value);
+    }
+    println!("{:<52} {}", "node_monitor_connected_clients", stats.connected_clients);
+    if !stats.connections_by_uid.is_empty() {
+        println!();
+        println!("{:<10} CONNECTIONS", "UID");
+        for (uid, open) in &stats.connections_by_uid {
+            println!("{:<10} {}", uid, open);
+        }
+    }
+}
+
+fn print_job(job: &StatusResponse) {
+    println!(
+        "Job {} (UID {}, partition {})",
+        job.job_id, job.owner_uid, job.partition
+    );
+    println!("  Samples received: {}", job.metrics_received);
+    println!("  Warning stage:    {}", job.escalation.stage());
+    println!("  Exempt:           {}", format_exemption(job.exemption.as_ref()));
+    if let Some(lost) = &job.monitor_lost {
+        println!(
+            "  Monitor lost:     since {} ({}), cancelled at {} unless it reconnects",
+            lost.since.with_timezone(&Local).format("%Y-%m-%dT%H:%M:%S"),
+            if lost.clean {
+                "closed cleanly"
+            } else {
+                "connection broken"
+            },
+            lost.deadline.with_timezone(&Local).format("%Y-%m-%dT%H:%M:%S")
+        );
+    }
+    if let Some(supervised) = &job.supervised_monitor {
+        println!(
+            "  Supervised:       {}, {} restart(s){}",
+            supervised
+                .pid
+                .map_or_else(|| "not running".to_string(), |pid| format!("PID {}", pid)),
+            supervised.restarts,
+            match (&supervised.last_exit, supervised.last_exit_at) {
+                (Some(exit), Some(at)) => format!(
+                    ", last exited with {} at {}",
+                    exit,
+                    at.with_timezone(&Local).format("%Y-%m-%dT%H:%M:%S")
+                ),
+                _ => String::new(),
+            }
+        );
+    }
+    println!(
+        "  Paused:           {}",
+        if job.enforcement_paused { "yes" } else { "no" }
+    );
+    for metric in &job.metrics {
+        let values: Vec<String> = metric.window.iter().map(|v| format!("{:.1}", v)).collect();
+        println!(
+            "  {:<24} {} [{}]",
+            metric.name,
+            format_window(metric),
+            values.join(", ")
+        );
+    }
+    if !job.gpus.is_empty() {
+        println!("  Idle GPUs:        {} of {}", job.idle_gpus, job.gpus.len());
+    }
+    for gpu in &job.gpus {
+        let values: Vec<String> = gpu.window.iter().map(|v| format!("{:.1}", v)).collect();
+        println!("    {} {} [{}]", gpu.name, format_window(gpu), values.join(", "));
+    }
+}
+
+// 显示为 "最大值 (连续覆盖时长/窗口长度)"
+fn format_window(metric: &MetricStatus) -> String {
+    if !metric.enforced {
+        return "off".to_string();
+    }
+    let coverage = format!(
+        "{}/{}",
+        format_secs(metric.covered_secs),
+        format_secs(metric.window_secs)
+    );
+    match metric.max {
+        Some(max) => format!("{:.1}% ({})", max, coverage),
+        None => format!("- ({})", coverage),
+    }
+}
+
+fn format_secs(secs: u64) -> String {
+    match secs {
+        s if s >= 3600 && s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s >= 60 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+fn format_exemption(exemption: Option<&Exemption>) -> String {
+    match exemption {
+        None => "no".to_string(),
+        Some(Exemption { until: None }) => "yes".to_string(),
+        Some(Exemption { until: Some(until) }) if *until > Utc::now() => {
+            format!("until {}", until.with_timezone(&Local).format("%Y-%m-%dT%H:%M:%S"))
+        }
+        Some(Exemption { until: Some(_) }) => "expired".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use chrono::DateTime;
+    use tokio::sync::RwLock;
+
+    use crate::JobInfo;
+    use crate::config::Config;
+    use crate::exporter::Stats;
+    use crate::tracker::{self, JobTracker};
+
+    struct Fixture {
+        tracker: SharedTracker,
+        config: SharedConfig,
+        stats: SharedStats,
+    }
+
+    async fn fixture() -> Fixture {
+        let (tracker, _) = tracker::spawn(JobTracker::default());
+        // 所有者用 nobody, 写任务日志失败只记录日志
+        let job: JobInfo = serde_json::from_value(serde_json::json!({
+            "last_heartbeat_at": Utc::now(),
+            "registered_at": Utc::now(),
+            "owner_uid": 65534,
+            "metrics_received": 0,
+            "log_path": "/nonexistent/info-42.log",
+        }))
+        .unwrap();
+        tracker
+            .with(move |tracker| {
+                tracker.insert_job("42".to_string(), job, Duration::from_secs(60));
+                tracker.jobs.get_mut("42").unwrap().escalation = Escalation::Warned { since: Utc::now() };
+            })
+            .await
+            .unwrap();
+        Fixture {
+            tracker,
+            config: Arc::new(RwLock::new(Config::default())),
+            stats: Arc::new(Stats::default()),
+        }
+    }
+
+    async fn request(fixture: &Fixture, request: AdminRequest) -> AdminResponse {
+        handle_admin_request(
+            request,
+            Path::new("/nonexistent/node_monitor.toml"),
+            &fixture.tracker,
+            &fixture.config,
+            &fixture.stats,
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn exemption_of(fixture: &Fixture) -> (Option<Exemption>, Escalation) {
+        fixture
+            .tracker
+            .with(|tracker| {
+                let job = &tracker.jobs["42"];
+                (job.exemption, job.escalation)
+            })
+            .await
+            .unwrap()
+    }
+
+    fn exempt(job_id: &str, for_secs: Option<u64>) -> AdminRequest {
+        AdminRequest::Exempt {
+            job_id: job_id.to_string(),
+            for_secs,
+        }
+    }
+
+    #[tokio::test]
+    async fn exempt_clears_the_escalation() {
+        let fixture = fixture().await;
+
+        let before = Utc::now();
+        let response = request(&fixture, exempt("42", Some(3600))).await;
+        assert_eq!(response.status, "ok", "{:?}", response.message);
+        let (exemption, escalation) = exemption_of(&fixture).await;
+        let until: DateTime<Utc> = exemption.unwrap().until.unwrap();
+        assert!(until >= before + TimeDelta::hours(1) && until <= Utc::now() + TimeDelta::hours(1));
+        assert_eq!(escalation, Escalation::Active);
+
+        // 不带 --for 时豁免到任务结束
+        let response = request(&fixture, exempt("42", None)).await;
+        assert_eq!(response.status, "ok", "{:?}", response.message);
+        assert_eq!(exemption_of(&fixture).await.0, Some(Exemption { until: None }));
+
+        let response = request(&fixture, exempt("43", None)).await;
+        assert_eq!(response.status, "error");
+        assert_eq!(response.message.as_deref(), Some("Job 43 is not tracked"));
+    }
+
+    #[tokio::test]
+    async fn exempt_rejects_durations_that_do_not_fit() {
+        let fixture = fixture().await;
+
+        for secs in [99_999_999_999_999 * 86400, i64::MAX as u64, u64::MAX] {
+            let response = request(&fixture, exempt("42", Some(secs))).await;
+            assert_eq!(response.status, "error", "{}", secs);
+            assert!(response.message.unwrap().contains("too long"));
+        }
+        assert_eq!(exemption_of(&fixture).await.0, None);
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume_toggle_enforcement() {
+        let fixture = fixture().await;
+        let paused = || fixture.tracker.with(|tracker| tracker.enforcement_paused);
+
+        assert_eq!(request(&fixture, AdminRequest::Pause).await.status, "ok");
+        assert!(paused().await.unwrap());
+        // 重复暂停不是错误
+        assert_eq!(request(&fixture, AdminRequest::Pause).await.status, "ok");
+        assert!(paused().await.unwrap());
+
+        assert_eq!(request(&fixture, AdminRequest::Resume).await.status, "ok");
+        assert!(!paused().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn forget_stops_tracking_the_job() {
+        let fixture = fixture().await;
+        let forget = || AdminRequest::Forget {
+            job_id: "42".to_string(),
+        };
+
+        assert_eq!(request(&fixture, forget()).await.status, "ok");
+        let tracked = fixture.tracker.with(|tracker| tracker.jobs.contains_key("42")).await;
+        assert!(!tracked.unwrap());
+
+        let response = request(&fixture, forget()).await;
+        assert_eq!(response.status, "error");
+        assert_eq!(response.message.as_deref(), Some("Job 42 is not tracked"));
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use crate::JobInfo;
+use crate::window::SlidingWindow;
+
+// ============================================================================
+// 试运行记录 (Dry-Run Records)
+// ============================================================================
+
+// 试运行模式下本应取消任务时写入的记录, 每行一条 JSON
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DryRunRecord {
+    pub timestamp: DateTime<Utc>,
+    pub job_id: String,
+    pub owner_uid: u32,
+    pub partition: String,
+    pub qos: String,
+    pub account: String,
+    pub reason: String,
+    pub metrics_received: usize,
+    pub gpu_utilizations: Vec<f64>,
+    pub gpu_memory_utilizations: Vec<f64>,
+    pub cpu_utilizations: Vec<f64>,
+    #[serde(default)]
+    pub gpu_device_utilizations: BTreeMap<String, Vec<f64>>,
+}
+
+impl DryRunRecord {
+    pub fn new(job_id: &str, job: &JobInfo, reason: &str) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            job_id: job_id.to_string(),
+            owner_uid: job.owner_uid,
+            partition: job.partition.clone(),
+            qos: job.qos.clone(),
+            account: job.account.clone(),
+            reason: reason.to_string(),
+            metrics_received: job.metrics_received,
+            gpu_utilizations: job.gpu_utilizations.iter().flat_map(SlidingWindow::iter).collect(),
+            gpu_memory_utilizations: job
+                .gpu_memory_utilizations
+                .iter()
+                .flat_map(SlidingWindow::iter)
+                .collect(),
+            cpu_utilizations: job.cpu_utilizations.iter().flat_map(SlidingWindow::iter).collect(),
+            gpu_device_utilizations: job
+                .gpu_device_utilizations
+                .iter()
+                .map(|(uuid, window)| (uuid.clone(), window.iter().collect()))
+                .collect(),
+        }
+    }
+}
+
+pub async fn append_record(path: &Path, record: &DryRunRecord) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to open dry-run log {}", path.display()))?;
+    // tokio 的文件在后台线程写入, flush 之后记录才确实写进了文件
+    file.write_all(&line)
+        .await
+        .with_context(|| format!("Failed to write dry-run log {}", path.display()))?;
+    file.flush()
+        .await
+        .with_context(|| format!("Failed to write dry-run log {}", path.display()))?;
+    Ok(())
+}
+
+// ============================================================================
+// 查询 (Query)
+// ============================================================================
+
+pub fn print_records(path: &Path, job_id: Option<&str>, with_outcome: bool) -> Result<()> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read dry-run log {}", path.display())),
+    };
+
+    let mut found = false;
+    for (line_no, line) in content.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let record: DryRunRecord = match serde_json::from_str(line) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("Skipping malformed line {} in {}: {}", line_no + 1, path.display(), e);
+                continue;
+            }
+        };
+        if job_id.is_some_and(|id| id != record.job_id) {
+            continue;
+        }
+        found = true;
+
+        println!(
+            "{} job={} uid={} partition={} account={} samples={} reason=\"{}\"",
+            record.timestamp.with_timezone(&Local).format("%Y-%m-%dT%H:%M:%S"),
+            record.job_id,
+            record.owner_uid,
+            record.partition,
+            record.account,
+            record.metrics_received,
+            record.reason
+        );
+        for (name, window) in [
+            ("gpu", &record.gpu_utilizations),
+            ("gpu_mem", &record.gpu_memory_utilizations),
+            ("cpu", &record.cpu_utilizations),
+        ] {
+            if !window.is_empty() {
+                let values: Vec<String> = window.iter().map(|v| format!("{:.1}", v)).collect();
+                println!("    {} window: [{}]", name, values.join(", "));
+            }
+        }
+        for (uuid, window) in &record.gpu_device_utilizations {
+            let values: Vec<String> = window.iter().map(|v| format!("{:.1}", v)).collect();
+            println!("    {} window: [{}]", uuid, values.join(", "));
+        }
+        if with_outcome {
+            println!("    actual outcome: {}", job_outcome(&record.job_id));
+        }
+    }
+
+    if !found {
+        println!("No dry-run records found.");
+    }
+    Ok(())
+}
+
+// 通过 sacct 查询任务最终状态, 用于和试运行的判断做对比
+fn job_outcome(job_id: &str) -> String {
+    let output = Command::new("sacct")
+        .args([
+            "--noheader",
+            "--allocations",
+            "--parsable2",
+            "--format=State,Elapsed,End",
+            "--jobs",
+            job_id,
+        ])
+        .output();
+    match output {
+        Ok(o) if o.status.success() => {
+            let stdout = String::from_utf8_lossy(&o.stdout);
+            match stdout.lines().next() {
+                Some(line) => {
+                    let fields: Vec<&str> = line.split('|').collect();
+                    format!(
+                        "state={} elapsed={} end={}",
+                        fields.first().unwrap_or(&"?"),
+                        fields.get(1).unwrap_or(&"?"),
+                        fields.get(2).unwrap_or(&"?")
+                    )
+                }
+                None => "unknown (job not found in sacct)".to_string(),
+            }
+        }
+        Ok(o) => format!("unknown (sacct failed: {})", String::from_utf8_lossy(&o.stderr).trim()),
+        Err(e) => format!("unknown (failed to run sacct: {})", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(job_id: &str) -> DryRunRecord {
+        DryRunRecord {
+            timestamp: "2024-05-01T08:30:00Z".parse().unwrap(),
+            job_id: job_id.to_string(),
+            owner_uid: 1500,
+            partition: "gpu".to_string(),
+            qos: "normal".to_string(),
+            account: "lab".to_string(),
+            reason: "GPU Idle".to_string(),
+            metrics_received: 3,
+            gpu_utilizations: vec![0.0, 1.5],
+            gpu_memory_utilizations: vec![2.0],
+            cpu_utilizations: vec![],
+            gpu_device_utilizations: BTreeMap::from([("GPU-0".to_string(), vec![0.0, 1.5])]),
+        }
+    }
+
+    #[tokio::test]
+    async fn appends_one_json_line_per_record() {
+        let dir = std::env::temp_dir().join(format!("node_monitor_audit_{}", std::process::id()));
+        let path = dir.join("logs").join("dry_run.jsonl");
+        append_record(&path, &record("42")).await.unwrap();
+        append_record(&path, &record("43")).await.unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(
+            lines[0],
+            r#"{"timestamp":"2024-05-01T08:30:00Z","job_id":"42","owner_uid":1500,"partition":"gpu","qos":"normal","account":"lab","reason":"GPU Idle","metrics_received":3,"gpu_utilizations":[0.0,1.5],"gpu_memory_utilizations":[2.0],"cpu_utilizations":[],"gpu_device_utilizations":{"GPU-0":[0.0,1.5]}}"#
+        );
+        assert_eq!(lines.len(), 2);
+        assert!(content.ends_with('\n'));
+        let second: DryRunRecord = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second.job_id, "43");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
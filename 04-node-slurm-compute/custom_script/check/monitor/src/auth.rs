@@ -0,0 +1,95 @@
+use std::process::Command;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, anyhow, bail};
+use nix::unistd::User;
+
+// ============================================================================
+// 任务归属查询 (Job Owner Lookup)
+// ============================================================================
+
+// 查询任务所属用户的 UID; 抽象成 trait 以便测试时替换掉 Slurm
+pub trait JobOwnerResolver: Send + Sync {
+    fn job_owner(&self, job_id: &str) -> Result<u32>;
+}
+
+pub type SharedOwnerResolver = Arc<dyn JobOwnerResolver>;
+
+// 通过 squeue 查询任务的 UID
+pub struct SlurmOwnerResolver;
+
+impl JobOwnerResolver for SlurmOwnerResolver {
+    fn job_owner(&self, job_id: &str) -> Result<u32> {
+        let output = Command::new("squeue")
+            .args(["--noheader", "--format=%U", "--jobs", job_id])
+            .output()
+            .context("Failed to execute 'squeue'")?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "'squeue' for job {} failed with status {}: {}",
+                job_id,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let uid = stdout
+            .lines()
+            .next()
+            .ok_or_else(|| anyhow!("Job {} not found in squeue", job_id))?
+            .trim();
+        uid.parse::<u32>()
+            .with_context(|| format!("Unexpected UID '{}' reported for job {}", uid, job_id))
+    }
+}
+
+// ============================================================================
+// 权限判断 (Authorization)
+// ============================================================================
+
+// Slurm 的作业 ID 只包含数字, 其他内容一律拒绝, 防止被当成 squeue 的参数
+pub fn validate_job_id(job_id: &str) -> Result<()> {
+    if job_id.is_empty() || !job_id.chars().all(|c| c.is_ascii_digit()) {
+        bail!("Invalid job id '{}'", job_id);
+    }
+    Ok(())
+}
+
+// root 与 SlurmUser 可以操作任意任务
+pub fn is_privileged(uid: u32, slurm_user: &str) -> bool {
+    if uid == 0 {
+        return true;
+    }
+    match User::from_name(slurm_user) {
+        Ok(Some(user)) => user.uid.as_raw() == uid,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_only_numeric_job_ids() {
+        assert!(validate_job_id("4242").is_ok());
+        for job_id in ["", "-h", "42,43", "42 ", "4242_1", "--jobs=1"] {
+            assert!(validate_job_id(job_id).is_err(), "{:?} accepted", job_id);
+        }
+    }
+
+    #[test]
+    fn root_and_the_slurm_user_are_privileged() {
+        let nobody = User::from_name("nobody")
+            .unwrap()
+            .expect("no 'nobody' user")
+            .uid
+            .as_raw();
+        assert!(is_privileged(0, "slurm"));
+        assert!(is_privileged(nobody, "nobody"));
+        assert!(!is_privileged(nobody, "root"));
+        assert!(!is_privileged(nobody, "no-such-slurm-user"));
+    }
+}
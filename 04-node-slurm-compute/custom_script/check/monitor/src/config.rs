@@ -0,0 +1,602 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use log::warn;
+use sampler::gpu::BackendKind;
+use serde::{Deserialize, Deserializer};
+
+use crate::policy::PolicyConfig;
+
+// 取消模式也出现在 STATUS 答复中, 由共用的协议定义
+pub use protocol::EnforcementMode;
+
+// ============================================================================
+// 默认值 (Defaults)
+// ============================================================================
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/node_monitor.toml";
+
+const DEFAULT_SOCKET_PATH: &str = "/var/run/node_monitor.sock";
+
+// 仅 root 可访问的管理 socket, 供 `node_monitor ctl` 使用
+const DEFAULT_ADMIN_SOCKET_PATH: &str = "/var/run/node_monitor_admin.sock";
+
+// 心跳超时时间
+const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(180);
+
+// job_helper monitor 的连接断开后等待其重新连接的时长
+const DEFAULT_MONITOR_LOST_GRACE: Duration = Duration::from_secs(3 * 60);
+
+// 守护进程启动 job_helper monitor 时使用的路径, 与 task_prolog.sh 中的路径一致
+const DEFAULT_JOB_HELPER_PATH: &str = "/usr/local/bin/job_helper";
+
+// 守护进程看护的 job_helper monitor 退出后的重启退避: 初始间隔和最大间隔;
+// 连续运行超过最大间隔后退避重新从初始间隔开始
+const DEFAULT_MONITOR_RESTART_INITIAL: Duration = Duration::from_secs(10);
+const DEFAULT_MONITOR_RESTART_MAX: Duration = Duration::from_secs(5 * 60);
+
+// 利用率阈值 (百分比)
+const DEFAULT_GPU_UTILIZATION_THRESHOLD: f64 = 5.0;
+const DEFAULT_GPU_MEMORY_UTILIZATION_THRESHOLD: f64 = 5.0;
+const DEFAULT_CPU_UTILIZATION_THRESHOLD: f64 = 5.0;
+
+// Slurm 守护进程的运行用户, 与 root 一样可以操作任意任务
+const DEFAULT_SLURM_USER: &str = "slurm";
+
+// 任务状态快照路径, 以及定期写快照的间隔
+const DEFAULT_STATE_PATH: &str = "/var/lib/node_monitor/state.json";
+const DEFAULT_STATE_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+
+// scancel 重试队列: 持久化路径, 最大尝试次数, 退避的初始/最大间隔
+const DEFAULT_KILL_QUEUE_PATH: &str = "/var/lib/node_monitor/actions.json";
+const DEFAULT_KILL_MAX_ATTEMPTS: u32 = 10;
+const DEFAULT_KILL_RETRY_INITIAL: Duration = Duration::from_secs(10);
+const DEFAULT_KILL_RETRY_MAX: Duration = Duration::from_secs(10 * 60);
+
+// 空闲判定后的逐级警告: 第一次警告到最后警告的间隔, 最后警告到取消的倒计时
+const DEFAULT_IDLE_WARNING: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_IDLE_FINAL_WARNING: Duration = Duration::from_secs(5 * 60);
+
+// 试运行模式下 "本应取消" 的记录, 每行一条 JSON
+const DEFAULT_DRY_RUN_LOG_PATH: &str = "/var/lib/node_monitor/dry_run.jsonl";
+
+// 守护进程采样模式下的采样间隔, 与 job_helper monitor 的发送间隔一致
+const DEFAULT_DAEMON_SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+// 已改为时长字符串的旧配置项 (整数, 单位为秒), 仍作为新配置项的别名读取
+const DEPRECATED_SECS_KEYS: [(&str, &str); 7] = [
+    ("heartbeat_timeout_secs", "heartbeat_timeout"),
+    ("state_snapshot_interval_secs", "state_snapshot_interval"),
+    ("kill_retry_initial_secs", "kill_retry_initial"),
+    ("kill_retry_max_secs", "kill_retry_max"),
+    ("idle_warning_secs", "idle_warning"),
+    ("idle_final_warning_secs", "idle_final_warning"),
+    ("daemon_sample_interval_secs", "daemon_sample_interval"),
+];
+
+// 客户端 socket 的限制: 单条请求的最大字节数, 每个 UID 的最大连接数,
+// 每个连接每分钟的消息数和突发上限 (需容纳 job_helper 重连后补发的缓存样本)
+const DEFAULT_CLIENT_MAX_MESSAGE_BYTES: usize = 64 * 1024;
+const DEFAULT_CLIENT_MAX_CONNECTIONS_PER_UID: usize = 64;
+const DEFAULT_CLIENT_MESSAGES_PER_MINUTE: u32 = 60;
+const DEFAULT_CLIENT_MESSAGE_BURST: u32 = 200;
+
+// 连接上多久没有收到完整的请求就关闭; job_helper monitor 每 60 秒发送一次
+const DEFAULT_CLIENT_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const MIN_CLIENT_IDLE_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+// 注册后多长时间内的数据直接丢弃 (用于给用户加载模型或单纯墨迹的时间)
+const DEFAULT_BUFFER_PERIOD: Duration = Duration::from_secs(30 * 60);
+
+// 相邻样本间隔超过该值视为数据缺失, 缺失的时间段不算作空闲
+const DEFAULT_MAX_SAMPLE_GAP: Duration = Duration::from_secs(3 * 60);
+
+// 所有时长的上限; 时长会与当前时间相加, 过大的值会溢出
+const MAX_DURATION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+// ============================================================================
+// 配置结构 (Config)
+// ============================================================================
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplingMode {
+    // 由任务内的 job_helper monitor 上报指标
+    #[default]
+    Client,
+    // 由 node_monitor 从 Slurm cgroup 中发现任务并直接采样
+    Daemon,
+}
+
+// job_helper monitor 的连接断开 (进程退出或被杀) 而任务仍在跟踪时的处理方式
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorLostAction {
+    // 等待 monitor_lost_grace, 期间没有重新连接则取消任务
+    #[default]
+    Grace,
+    // 立即取消任务
+    Cancel,
+    // 由守护进程 (重新) 启动并看护 job_helper monitor, 再等待 monitor_lost_grace
+    Respawn,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub socket_path: PathBuf,
+    pub admin_socket_path: PathBuf,
+    // Prometheus 指标的监听地址, 不设置则不开启
+    pub metrics_listen: Option<SocketAddr>,
+    #[serde(alias = "heartbeat_timeout_secs", deserialize_with = "deserialize_duration_or_secs")]
+    pub heartbeat_timeout: Duration,
+    // 已废弃: 心跳超时改由每个任务的定时器触发, 保留该项只为兼容旧的配置文件
+    pub heartbeat_check_interval_secs: Option<u64>,
+    pub monitor_lost_action: MonitorLostAction,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub monitor_lost_grace: Duration,
+    pub job_helper_path: PathBuf,
+    // 任务注册后由守护进程以任务所有者的身份启动 job_helper monitor, 并在其退出后重启
+    pub supervise_monitors: bool,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub monitor_restart_initial: Duration,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub monitor_restart_max: Duration,
+    pub gpu_utilization_threshold: f64,
+    pub gpu_memory_utilization_threshold: f64,
+    pub cpu_utilization_threshold: f64,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub buffer_period: Duration,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub max_sample_gap: Duration,
+    pub state_path: PathBuf,
+    #[serde(
+        alias = "state_snapshot_interval_secs",
+        deserialize_with = "deserialize_duration_or_secs"
+    )]
+    pub state_snapshot_interval: Duration,
+    pub slurm_user: String,
+    pub kill_queue_path: PathBuf,
+    pub kill_max_attempts: u32,
+    #[serde(alias = "kill_retry_initial_secs", deserialize_with = "deserialize_duration_or_secs")]
+    pub kill_retry_initial: Duration,
+    #[serde(alias = "kill_retry_max_secs", deserialize_with = "deserialize_duration_or_secs")]
+    pub kill_retry_max: Duration,
+    #[serde(alias = "idle_warning_secs", deserialize_with = "deserialize_duration_or_secs")]
+    pub idle_warning: Duration,
+    #[serde(alias = "idle_final_warning_secs", deserialize_with = "deserialize_duration_or_secs")]
+    pub idle_final_warning: Duration,
+    pub enforcement_mode: EnforcementMode,
+    // 按分区名 (精确匹配) 覆盖 enforcement_mode
+    pub partition_enforcement: HashMap<String, EnforcementMode>,
+    pub dry_run_log_path: PathBuf,
+    pub sampling_mode: SamplingMode,
+    #[serde(
+        alias = "daemon_sample_interval_secs",
+        deserialize_with = "deserialize_duration_or_secs"
+    )]
+    pub daemon_sample_interval: Duration,
+    pub daemon_gpu_backend: BackendKind,
+    // daemon_gpu_backend = "fake" 时读取的伪造数据文件
+    pub daemon_gpu_fake_file: Option<PathBuf>,
+    pub client_max_message_bytes: usize,
+    pub client_max_connections_per_uid: usize,
+    pub client_messages_per_minute: u32,
+    pub client_message_burst: u32,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub client_idle_timeout: Duration,
+    // 除任务所有者的家目录外, 还允许存放任务日志的目录 (例如 Slurm 的 spool 目录)
+    pub job_log_roots: Vec<PathBuf>,
+    pub policy: PolicyConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            socket_path: PathBuf::from(DEFAULT_SOCKET_PATH),
+            admin_socket_path: PathBuf::from(DEFAULT_ADMIN_SOCKET_PATH),
+            metrics_listen: None,
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            heartbeat_check_interval_secs: None,
+            monitor_lost_action: MonitorLostAction::default(),
+            monitor_lost_grace: DEFAULT_MONITOR_LOST_GRACE,
+            job_helper_path: PathBuf::from(DEFAULT_JOB_HELPER_PATH),
+            supervise_monitors: false,
+            monitor_restart_initial: DEFAULT_MONITOR_RESTART_INITIAL,
+            monitor_restart_max: DEFAULT_MONITOR_RESTART_MAX,
+            gpu_utilization_threshold: DEFAULT_GPU_UTILIZATION_THRESHOLD,
+            gpu_memory_utilization_threshold: DEFAULT_GPU_MEMORY_UTILIZATION_THRESHOLD,
+            cpu_utilization_threshold: DEFAULT_CPU_UTILIZATION_THRESHOLD,
+            buffer_period: DEFAULT_BUFFER_PERIOD,
+            max_sample_gap: DEFAULT_MAX_SAMPLE_GAP,
+            state_path: PathBuf::from(DEFAULT_STATE_PATH),
+            state_snapshot_interval: DEFAULT_STATE_SNAPSHOT_INTERVAL,
+            slurm_user: DEFAULT_SLURM_USER.to_string(),
+            kill_queue_path: PathBuf::from(DEFAULT_KILL_QUEUE_PATH),
+            kill_max_attempts: DEFAULT_KILL_MAX_ATTEMPTS,
+            kill_retry_initial: DEFAULT_KILL_RETRY_INITIAL,
+            kill_retry_max: DEFAULT_KILL_RETRY_MAX,
+            idle_warning: DEFAULT_IDLE_WARNING,
+            idle_final_warning: DEFAULT_IDLE_FINAL_WARNING,
+            enforcement_mode: EnforcementMode::default(),
+            partition_enforcement: HashMap::new(),
+            dry_run_log_path: PathBuf::from(DEFAULT_DRY_RUN_LOG_PATH),
+            sampling_mode: SamplingMode::default(),
+            daemon_sample_interval: DEFAULT_DAEMON_SAMPLE_INTERVAL,
+            daemon_gpu_backend: BackendKind::default(),
+            daemon_gpu_fake_file: None,
+            client_max_message_bytes: DEFAULT_CLIENT_MAX_MESSAGE_BYTES,
+            client_max_connections_per_uid: DEFAULT_CLIENT_MAX_CONNECTIONS_PER_UID,
+            client_messages_per_minute: DEFAULT_CLIENT_MESSAGES_PER_MINUTE,
+            client_message_burst: DEFAULT_CLIENT_MESSAGE_BURST,
+            client_idle_timeout: DEFAULT_CLIENT_IDLE_TIMEOUT,
+            job_log_roots: Vec::new(),
+            policy: PolicyConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn enforcement_for(&self, partition: &str) -> EnforcementMode {
+        self.partition_enforcement
+            .get(partition)
+            .copied()
+            .unwrap_or(self.enforcement_mode)
+    }
+
+    // 从文件读取并校验配置
+    pub fn load(path: &Path) -> Result<Self> {
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let config: Config =
+            toml::from_str(&content).with_context(|| format!("Failed to parse config file {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        if config.heartbeat_check_interval_secs.is_some() {
+            warn!(
+                "heartbeat_check_interval_secs in {} is ignored, heartbeats now expire on per-job timers",
+                path.display()
+            );
+        }
+        if let Ok(table) = toml::from_str::<toml::Table>(&content) {
+            for (old, new) in DEPRECATED_SECS_KEYS.iter().filter(|(old, _)| table.contains_key(*old)) {
+                warn!(
+                    "{} in {} is deprecated, use {} with a duration such as \"90s\" or \"10m\" instead",
+                    old,
+                    path.display(),
+                    new
+                );
+            }
+        }
+        Ok(config)
+    }
+
+    // 读取配置；文件不存在时回退到内置默认值，保持与旧部署兼容
+    pub fn load_or_default(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::load(path)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !self.socket_path.is_absolute() {
+            bail!(
+                "socket_path must be an absolute path, got {}",
+                self.socket_path.display()
+            );
+        }
+        if !self.admin_socket_path.is_absolute() {
+            bail!(
+                "admin_socket_path must be an absolute path, got {}",
+                self.admin_socket_path.display()
+            );
+        }
+        if self.admin_socket_path == self.socket_path {
+            bail!("admin_socket_path must differ from socket_path");
+        }
+        if !self.state_path.is_absolute() {
+            bail!("state_path must be an absolute path, got {}", self.state_path.display());
+        }
+        if self.state_snapshot_interval.is_zero() {
+            bail!("state_snapshot_interval must be greater than 0");
+        }
+        if !self.kill_queue_path.is_absolute() {
+            bail!(
+                "kill_queue_path must be an absolute path, got {}",
+                self.kill_queue_path.display()
+            );
+        }
+        if self.kill_max_attempts == 0 {
+            bail!("kill_max_attempts must be greater than 0");
+        }
+        if self.kill_retry_initial.is_zero() || self.kill_retry_max < self.kill_retry_initial {
+            bail!("kill_retry_initial must be greater than 0 and not exceed kill_retry_max");
+        }
+        if !self.dry_run_log_path.is_absolute() {
+            bail!(
+                "dry_run_log_path must be an absolute path, got {}",
+                self.dry_run_log_path.display()
+            );
+        }
+        if self.max_sample_gap.is_zero() {
+            bail!("max_sample_gap must be greater than 0");
+        }
+        if self.buffer_period > self.policy.max_window {
+            bail!("buffer_period must not exceed policy.max_window");
+        }
+        if self.daemon_sample_interval.is_zero() {
+            bail!("daemon_sample_interval must be greater than 0");
+        }
+        if self.client_max_message_bytes == 0 || self.client_max_connections_per_uid == 0 {
+            bail!("client_max_message_bytes and client_max_connections_per_uid must be greater than 0");
+        }
+        if self.client_messages_per_minute == 0 || self.client_message_burst == 0 {
+            bail!("client_messages_per_minute and client_message_burst must be greater than 0");
+        }
+        if self.client_idle_timeout < MIN_CLIENT_IDLE_TIMEOUT {
+            bail!(
+                "client_idle_timeout must be at least {}s, longer than the job_helper send interval",
+                MIN_CLIENT_IDLE_TIMEOUT.as_secs()
+            );
+        }
+        if let Some(root) = self.job_log_roots.iter().find(|r| !r.is_absolute()) {
+            bail!("job_log_roots must contain absolute paths, got {}", root.display());
+        }
+        if self.daemon_gpu_backend == BackendKind::Fake && self.daemon_gpu_fake_file.is_none() {
+            bail!("daemon_gpu_backend = \"fake\" requires daemon_gpu_fake_file");
+        }
+        if self.heartbeat_timeout.is_zero() {
+            bail!("heartbeat_timeout must be greater than 0");
+        }
+        if self.monitor_lost_grace.is_zero() {
+            bail!("monitor_lost_grace must be greater than 0, use monitor_lost_action = \"cancel\" to cancel at once");
+        }
+        if !self.job_helper_path.is_absolute() {
+            bail!(
+                "job_helper_path must be an absolute path, got {}",
+                self.job_helper_path.display()
+            );
+        }
+        if self.monitor_restart_initial.is_zero() || self.monitor_restart_max < self.monitor_restart_initial {
+            bail!("monitor_restart_initial must be greater than 0 and not exceed monitor_restart_max");
+        }
+        for (name, value) in [
+            ("heartbeat_timeout", self.heartbeat_timeout),
+            ("monitor_lost_grace", self.monitor_lost_grace),
+            ("monitor_restart_initial", self.monitor_restart_initial),
+            ("monitor_restart_max", self.monitor_restart_max),
+            ("buffer_period", self.buffer_period),
+            ("max_sample_gap", self.max_sample_gap),
+            ("state_snapshot_interval", self.state_snapshot_interval),
+            ("kill_retry_initial", self.kill_retry_initial),
+            ("kill_retry_max", self.kill_retry_max),
+            ("idle_warning", self.idle_warning),
+            ("idle_final_warning", self.idle_final_warning),
+            ("daemon_sample_interval", self.daemon_sample_interval),
+            ("client_idle_timeout", self.client_idle_timeout),
+        ] {
+            if value > MAX_DURATION {
+                bail!(
+                    "{} must not exceed {}d, got {}s",
+                    name,
+                    MAX_DURATION.as_secs() / 86400,
+                    value.as_secs()
+                );
+            }
+        }
+        for (name, value) in [
+            ("gpu_utilization_threshold", self.gpu_utilization_threshold),
+            (
+                "gpu_memory_utilization_threshold",
+                self.gpu_memory_utilization_threshold,
+            ),
+            ("cpu_utilization_threshold", self.cpu_utilization_threshold),
+        ] {
+            if !(0.0..=100.0).contains(&value) {
+                bail!("{} must be within 0-100, got {}", name, value);
+            }
+        }
+        self.policy.validate()?;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// 时长 (Durations)
+// ============================================================================
+
+// 支持 "90", "90s", "30m", "2h", "1d"
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+    let number: u64 = number.parse().map_err(|_| format!("invalid duration '{}'", value))?;
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(format!("unknown duration unit '{}' (use s, m, h or d)", unit)),
+    };
+    Ok(Duration::from_secs(number.saturating_mul(multiplier)))
+}
+
+// 配置中的时长必须写成带单位的字符串 (例如 "45m"), 避免与旧版按样本个数计的整数混淆
+pub fn deserialize_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_duration(&value).map_err(serde::de::Error::custom)
+}
+
+// 由旧版按秒计的整数项改来的时长: 整数仍按秒解释
+fn deserialize_duration_or_secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum DurationOrSecs {
+        Secs(u64),
+        Duration(String),
+    }
+
+    match DurationOrSecs::deserialize(deserializer)? {
+        DurationOrSecs::Secs(secs) => Ok(Duration::from_secs(secs)),
+        DurationOrSecs::Duration(value) => parse_duration(&value).map_err(serde::de::Error::custom),
+    }
+}
+
+pub fn deserialize_optional_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| parse_duration(&value).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Result<Config> {
+        let config: Config = toml::from_str(content)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn parses_durations_with_units() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration(" 45m "), Ok(Duration::from_secs(45 * 60)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 3600)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert_eq!(parse_duration("0s"), Ok(Duration::ZERO));
+        assert_eq!(parse_duration("99999999999999999999d").ok(), None);
+        assert_eq!(
+            parse_duration(&format!("{}d", u64::MAX)),
+            Ok(Duration::from_secs(u64::MAX))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_durations() {
+        for value in ["", "m", "-5m", "1.5h", "10 m", "10min", "1h30m", "5w"] {
+            assert!(parse_duration(value).is_err(), "{:?} accepted", value);
+        }
+    }
+
+    #[test]
+    fn rejects_durations_longer_than_a_year() {
+        assert!(parse("idle_warning = \"365d\"").is_ok());
+        for key in [
+            "heartbeat_timeout",
+            "monitor_lost_grace",
+            "idle_warning",
+            "idle_final_warning",
+            "kill_retry_max",
+            "monitor_restart_max",
+            "client_idle_timeout",
+        ] {
+            let e = parse(&format!("{} = \"366d\"", key)).unwrap_err();
+            assert!(e.to_string().contains("must not exceed 365d"), "{}: {}", key, e);
+        }
+        // 按秒计的旧写法同样受限, 以及乘以单位后饱和的值
+        let e = parse(&format!("heartbeat_timeout_secs = {}", i64::MAX)).unwrap_err();
+        assert!(e.to_string().contains("heartbeat_timeout must not exceed"), "{}", e);
+        let e = parse("monitor_lost_grace = \"99999999999999d\"").unwrap_err();
+        assert!(e.to_string().contains("monitor_lost_grace must not exceed"), "{}", e);
+    }
+
+    #[test]
+    fn window_durations_must_have_units() {
+        assert!(parse("buffer_period = \"45m\"").is_ok());
+        assert!(parse("buffer_period = 45").is_err());
+        assert!(parse("max_sample_gap = \"5x\"").is_err());
+    }
+
+    #[test]
+    fn reads_durations_and_their_deprecated_secs_aliases() {
+        let config = parse(
+            r#"
+            heartbeat_timeout = "5m"
+            state_snapshot_interval = "1m"
+            kill_retry_initial = "30s"
+            kill_retry_max = "1h"
+            idle_warning = "0s"
+            idle_final_warning = "0s"
+            daemon_sample_interval = "2m"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.heartbeat_timeout, Duration::from_secs(300));
+        assert_eq!(config.state_snapshot_interval, Duration::from_secs(60));
+        assert_eq!(config.kill_retry_initial, Duration::from_secs(30));
+        assert_eq!(config.kill_retry_max, Duration::from_secs(3600));
+        assert_eq!(config.idle_warning, Duration::ZERO);
+        assert_eq!(config.idle_final_warning, Duration::ZERO);
+        assert_eq!(config.daemon_sample_interval, Duration::from_secs(120));
+
+        let legacy = parse(
+            r#"
+            heartbeat_timeout_secs = 300
+            state_snapshot_interval_secs = 60
+            kill_retry_initial_secs = 30
+            kill_retry_max_secs = 3600
+            idle_warning_secs = 0
+            idle_final_warning_secs = 0
+            daemon_sample_interval_secs = 120
+            "#,
+        )
+        .unwrap();
+        assert_eq!(legacy, config);
+    }
+
+    #[test]
+    fn rejects_a_duration_set_under_both_names() {
+        assert!(parse("heartbeat_timeout = \"5m\"\nheartbeat_timeout_secs = 300").is_err());
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Config::default().validate().unwrap();
+        assert_eq!(parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn sample_config_is_valid() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../../monitor_config/node_monitor.toml");
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.heartbeat_timeout, DEFAULT_HEARTBEAT_TIMEOUT);
+        assert_eq!(config.kill_retry_max, DEFAULT_KILL_RETRY_MAX);
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for content in [
+            "socket_path = \"relative.sock\"",
+            "admin_socket_path = \"/var/run/node_monitor.sock\"",
+            "state_path = \"state.json\"",
+            "heartbeat_timeout = \"0s\"",
+            "state_snapshot_interval_secs = 0",
+            "kill_max_attempts = 0",
+            "kill_retry_initial = \"0s\"",
+            "kill_retry_initial = \"20m\"\nkill_retry_max = \"10m\"",
+            "daemon_sample_interval = \"0s\"",
+            "max_sample_gap = \"0s\"",
+            "buffer_period = \"2d\"",
+            "monitor_lost_grace = \"0s\"",
+            "monitor_restart_initial = \"10m\"\nmonitor_restart_max = \"1m\"",
+            "job_helper_path = \"job_helper\"",
+            "client_idle_timeout = \"1m\"",
+            "client_message_burst = 0",
+            "job_log_roots = [\"spool\"]",
+            "daemon_gpu_backend = \"fake\"",
+            "gpu_utilization_threshold = 101.0",
+            "cpu_utilization_threshold = -1.0",
+            "unknown_option = 1",
+        ] {
+            assert!(parse(content).is_err(), "{:?} accepted", content);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+// ============================================================================
+// 取消前的逐级警告 (Escalation Ladder)
+// ============================================================================
+
+// 警告阶段也出现在 STATUS 答复中, 由共用的协议定义; 状态转换在这里实现
+pub use protocol::Escalation;
+
+// 本次空闲判定之后需要执行的动作
+#[derive(Debug, PartialEq)]
+pub enum EscalationStep {
+    Wait,
+    Warn,
+    FinalWarning(DateTime<Utc>),
+    Cancel,
+}
+
+pub trait EscalationExt {
+    fn advance(&mut self, now: DateTime<Utc>, warning: Duration, final_warning: Duration) -> EscalationStep;
+    fn reset(&mut self) -> bool;
+}
+
+impl EscalationExt for Escalation {
+    // 空闲判定成立时调用; 时长为 0 的阶段直接跳过, 两者都为 0 时立即取消
+    fn advance(&mut self, now: DateTime<Utc>, warning: Duration, final_warning: Duration) -> EscalationStep {
+        let warning = chrono::Duration::from_std(warning).unwrap_or(chrono::Duration::MAX);
+        let final_warning = chrono::Duration::from_std(final_warning).unwrap_or(chrono::Duration::MAX);
+
+        match *self {
+            Escalation::Active if !warning.is_zero() => {
+                *self = Escalation::Warned { since: now };
+                EscalationStep::Warn
+            }
+            Escalation::Warned { since } if now - since < warning => EscalationStep::Wait,
+            Escalation::Active | Escalation::Warned { .. } if !final_warning.is_zero() => {
+                let deadline = now + final_warning;
+                *self = Escalation::FinalWarning { deadline };
+                EscalationStep::FinalWarning(deadline)
+            }
+            Escalation::FinalWarning { deadline } if now < deadline => EscalationStep::Wait,
+            _ => EscalationStep::Cancel,
+        }
+    }
+
+    // 任务重新活跃时回到初始阶段, 返回之前是否处于警告中
+    fn reset(&mut self) -> bool {
+        std::mem::take(self) != Escalation::Active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    const WARNING: Duration = Duration::from_secs(10 * 60);
+    const FINAL_WARNING: Duration = Duration::from_secs(5 * 60);
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap() + chrono::Duration::minutes(minutes)
+    }
+
+    #[test]
+    fn warns_then_gives_a_final_warning_then_cancels() {
+        let mut escalation = Escalation::Active;
+
+        assert_eq!(escalation.advance(at(0), WARNING, FINAL_WARNING), EscalationStep::Warn);
+        assert_eq!(escalation, Escalation::Warned { since: at(0) });
+        assert_eq!(escalation.advance(at(9), WARNING, FINAL_WARNING), EscalationStep::Wait);
+        assert_eq!(escalation, Escalation::Warned { since: at(0) });
+
+        assert_eq!(
+            escalation.advance(at(10), WARNING, FINAL_WARNING),
+            EscalationStep::FinalWarning(at(15))
+        );
+        assert_eq!(escalation, Escalation::FinalWarning { deadline: at(15) });
+        assert_eq!(escalation.advance(at(14), WARNING, FINAL_WARNING), EscalationStep::Wait);
+
+        assert_eq!(
+            escalation.advance(at(15), WARNING, FINAL_WARNING),
+            EscalationStep::Cancel
+        );
+    }
+
+    #[test]
+    fn skips_stages_without_a_duration() {
+        // 没有第一次警告: 直接进入最后警告
+        let mut escalation = Escalation::Active;
+        assert_eq!(
+            escalation.advance(at(0), Duration::ZERO, FINAL_WARNING),
+            EscalationStep::FinalWarning(at(5))
+        );
+        assert_eq!(
+            escalation.advance(at(5), Duration::ZERO, FINAL_WARNING),
+            EscalationStep::Cancel
+        );
+
+        // 没有最后警告: 第一次警告到期后直接取消
+        let mut escalation = Escalation::Active;
+        assert_eq!(escalation.advance(at(0), WARNING, Duration::ZERO), EscalationStep::Warn);
+        assert_eq!(escalation.advance(at(9), WARNING, Duration::ZERO), EscalationStep::Wait);
+        assert_eq!(
+            escalation.advance(at(10), WARNING, Duration::ZERO),
+            EscalationStep::Cancel
+        );
+
+        // 两者都为 0: 立即取消
+        let mut escalation = Escalation::Active;
+        assert_eq!(
+            escalation.advance(at(0), Duration::ZERO, Duration::ZERO),
+            EscalationStep::Cancel
+        );
+    }
+
+    #[test]
+    fn reset_returns_to_active_from_every_stage() {
+        let mut escalation = Escalation::Active;
+        assert!(!escalation.reset());
+        assert_eq!(escalation, Escalation::Active);
+
+        let mut escalation = Escalation::Active;
+        escalation.advance(at(0), WARNING, FINAL_WARNING);
+        assert!(escalation.reset());
+        assert_eq!(escalation, Escalation::Active);
+        // 再次空闲时从第一次警告重新开始, 而不是沿用之前的计时
+        assert_eq!(escalation.advance(at(20), WARNING, FINAL_WARNING), EscalationStep::Warn);
+        assert_eq!(escalation.advance(at(29), WARNING, FINAL_WARNING), EscalationStep::Wait);
+
+        let mut escalation = Escalation::Active;
+        escalation.advance(at(0), WARNING, FINAL_WARNING);
+        escalation.advance(at(10), WARNING, FINAL_WARNING);
+        assert!(escalation.reset());
+        assert_eq!(escalation, Escalation::Active);
+        // 原来的截止时间已过也不会直接取消
+        assert_eq!(escalation.advance(at(30), WARNING, FINAL_WARNING), EscalationStep::Warn);
+    }
+}
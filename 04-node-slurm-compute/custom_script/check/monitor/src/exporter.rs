@@ -0,0 +1,588 @@
+use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result, bail};
+use log::{error, info, warn};
+use nix::unistd::{Uid, User};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{self, Duration};
+
+use crate::SharedConfig;
+use crate::escalation::Escalation;
+use crate::tracker::{JobTracker, SharedTracker};
+use crate::window::SlidingWindow;
+
+// ============================================================================
+// 常量定义 (Constants)
+// ============================================================================
+
+// 请求头最大长度, 只需要解析请求行
+const MAX_REQUEST_HEADER_BYTES: usize = 8192;
+
+// 读取请求的超时时间
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// ============================================================================
+// 计数器 (Counters)
+// ============================================================================
+
+// 触发取消的原因分类, 用作指标标签
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillReason {
+    GpuIdle,
+    GpuMemoryIdle,
+    CpuIdle,
+    IdleGpus,
+    HeartbeatTimeout,
+    MonitorLost,
+}
+
+impl KillReason {
+    const ALL: [KillReason; 6] = [
+        KillReason::GpuIdle,
+        KillReason::GpuMemoryIdle,
+        KillReason::CpuIdle,
+        KillReason::IdleGpus,
+        KillReason::HeartbeatTimeout,
+        KillReason::MonitorLost,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            KillReason::GpuIdle => "gpu_idle",
+            KillReason::GpuMemoryIdle => "gpu_memory_idle",
+            KillReason::CpuIdle => "cpu_idle",
+            KillReason::IdleGpus => "idle_gpus",
+            KillReason::HeartbeatTimeout => "heartbeat_timeout",
+            KillReason::MonitorLost => "monitor_lost",
+        }
+    }
+}
+
+pub type SharedStats = Arc<Stats>;
+
+#[derive(Default)]
+pub struct Stats {
+    kills: [AtomicU64; KillReason::ALL.len()],
+    heartbeat_timeouts: AtomicU64,
+    // job_helper monitor 的连接在任务仍被跟踪时断开, 以及守护进程重新启动它的次数
+    monitors_lost: AtomicU64,
+    monitor_respawns: AtomicU64,
+    parse_errors: AtomicU64,
+    connections: AtomicU64,
+    connected_clients: AtomicI64,
+    // 客户端 socket 的限制各自拦下的次数
+    rejected_connections: AtomicU64,
+    oversized_messages: AtomicU64,
+    throttled_messages: AtomicU64,
+    idle_timeouts: AtomicU64,
+    // 每个 UID 当前打开的连接数
+    connections_by_uid: Mutex<HashMap<u32, usize>>,
+}
+
+impl Stats {
+    pub fn record_kill(&self, reason: KillReason) {
+        self.kills[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_heartbeat_timeout(&self) {
+        self.heartbeat_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_monitor_lost(&self) {
+        self.monitors_lost.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_monitor_respawn(&self) {
+        self.monitor_respawns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_parse_error(&self) {
+        self.parse_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_oversized_message(&self) {
+        self.oversized_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_throttled_message(&self) {
+        self.throttled_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_idle_timeout(&self) {
+        self.idle_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // 该 UID 的连接数已达上限时返回 None; 否则连接期间持有返回的 guard, 释放时自动减少在线连接数
+    pub fn client_connected(self: &Arc<Self>, uid: u32, max_per_uid: usize) -> Option<ClientGuard> {
+        {
+            let mut by_uid = self.connections_by_uid.lock().unwrap_or_else(|e| e.into_inner());
+            let open = by_uid.entry(uid).or_default();
+            if *open >= max_per_uid {
+                self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            *open += 1;
+        }
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+        Some(ClientGuard(self.clone(), uid))
+    }
+
+    // 累计计数器: (指标名, 说明, 值), 同时用于 /metrics 和 `node_monitor ctl stats`
+    pub fn counters(&self) -> [(&'static str, &'static str, u64); 9] {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        [
+            (
+                "node_monitor_heartbeat_timeouts_total",
+                "Jobs dropped because their monitor stopped sending heartbeats",
+                load(&self.heartbeat_timeouts),
+            ),
+            (
+                "node_monitor_monitors_lost_total",
+                "Connections of job_helper monitors that closed while their job was still tracked",
+                load(&self.monitors_lost),
+            ),
+            (
+                "node_monitor_monitor_respawns_total",
+                "job_helper monitors restarted by the node monitor after their connection was lost",
+                load(&self.monitor_respawns),
+            ),
+            (
+                "node_monitor_parse_errors_total",
+                "Messages on the client socket that could not be parsed",
+                load(&self.parse_errors),
+            ),
+            (
+                "node_monitor_client_connections_total",
+                "Connections accepted on the client socket",
+                load(&self.connections),
+            ),
+            (
+                "node_monitor_client_connections_rejected_total",
+                "Connections refused because their UID had too many open connections",
+                load(&self.rejected_connections),
+            ),
+            (
+                "node_monitor_client_oversized_messages_total",
+                "Connections closed because a message exceeded client_max_message_bytes",
+                load(&self.oversized_messages),
+            ),
+            (
+                "node_monitor_client_throttled_messages_total",
+                "Messages rejected by the per-connection rate limit",
+                load(&self.throttled_messages),
+            ),
+            (
+                "node_monitor_client_idle_timeouts_total",
+                "Connections closed after client_idle_timeout without a complete message",
+                load(&self.idle_timeouts),
+            ),
+        ]
+    }
+
+    pub fn connected_clients(&self) -> i64 {
+        self.connected_clients.load(Ordering::Relaxed)
+    }
+
+    pub fn connections_by_uid(&self) -> BTreeMap<u32, usize> {
+        let by_uid = self.connections_by_uid.lock().unwrap_or_else(|e| e.into_inner());
+        by_uid.iter().map(|(uid, open)| (*uid, *open)).collect()
+    }
+}
+
+pub struct ClientGuard(SharedStats, u32);
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.0.connected_clients.fetch_sub(1, Ordering::Relaxed);
+        let mut by_uid = self.0.connections_by_uid.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(open) = by_uid.get_mut(&self.1) {
+            *open -= 1;
+            if *open == 0 {
+                by_uid.remove(&self.1);
+            }
+        }
+    }
+}
+
+// ============================================================================
+// HTTP 服务 (HTTP Listener)
+// ============================================================================
+
+// 只实现 `GET /metrics`, 每个请求处理完即关闭连接
+pub async fn run_exporter(addr: SocketAddr, tracker: SharedTracker, config: SharedConfig, stats: SharedStats) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Failed to bind metrics exporter to {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Serving Prometheus metrics on http://{}/metrics", addr);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let tracker = tracker.clone();
+                let config = config.clone();
+                let stats = stats.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_request(stream, tracker, config, stats).await {
+                        warn!("Metrics request from {} failed: {:#}", peer, e);
+                    }
+                });
+            }
+            Err(e) => error!("Failed to accept metrics connection: {}", e),
+        }
+    }
+}
+
+async fn serve_request(
+    mut stream: TcpStream,
+    tracker: SharedTracker,
+    config: SharedConfig,
+    stats: SharedStats,
+) -> Result<()> {
+    let request_line = time::timeout(REQUEST_READ_TIMEOUT, read_request_line(&mut stream))
+        .await
+        .context("Timed out reading request")??;
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let gpu_threshold = config.read().await.gpu_utilization_threshold;
+            // 用户名查询可能经过 NSS (例如 LDAP) 而阻塞, 在状态任务之外的阻塞线程池中完成
+            let owners: HashSet<u32> = tracker
+                .with(|tracker| tracker.jobs.values().map(|job| job.owner_uid).collect())
+                .await?;
+            let users: HashMap<u32, String> =
+                tokio::task::spawn_blocking(move || owners.into_iter().map(|uid| (uid, user_name(uid))).collect())
+                    .await
+                    .context("User name lookup task panicked")?;
+            let body = tracker
+                .with(move |tracker| render(tracker, &stats, gpu_threshold, &users))
+                .await?;
+            ("200 OK", body)
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+async fn read_request_line(stream: &mut TcpStream) -> Result<String> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.len() > MAX_REQUEST_HEADER_BYTES {
+            bail!("Request header too large");
+        }
+    }
+    let head = String::from_utf8_lossy(&buf);
+    Ok(head.lines().next().unwrap_or_default().to_string())
+}
+
+// ============================================================================
+// 指标输出 (Exposition)
+// ============================================================================
+
+fn render(tracker: &JobTracker, stats: &Stats, gpu_threshold: f64, users: &HashMap<u32, String>) -> String {
+    let mut out = String::new();
+    let mut job_ids: Vec<&String> = tracker.jobs.keys().collect();
+    job_ids.sort();
+
+    let job_labels: Vec<(String, &crate::JobInfo)> = job_ids
+        .into_iter()
+        .map(|job_id| {
+            let job = &tracker.jobs[job_id];
+            let user = users
+                .get(&job.owner_uid)
+                .cloned()
+                .unwrap_or_else(|| job.owner_uid.to_string());
+            let labels = format!(
+                "job_id=\"{}\",user=\"{}\",partition=\"{}\",account=\"{}\"",
+                escape(job_id),
+                escape(&user),
+                escape(&job.partition),
+                escape(&job.account)
+            );
+            (labels, job)
+        })
+        .collect();
+
+    header(
+        &mut out,
+        "node_monitor_job_utilization_percent",
+        "gauge",
+        "Most recent utilization reported for the job",
+    );
+    for (labels, job) in &job_labels {
+        if let Some((gpu, gpu_memory, cpu)) = job.last_metrics {
+            for (metric, value) in [("gpu", gpu), ("gpu_memory", gpu_memory), ("cpu", cpu)] {
+                let _ = writeln!(
+                    out,
+                    "node_monitor_job_utilization_percent{{{},metric=\"{}\"}} {}",
+                    labels, metric, value
+                );
+            }
+        }
+    }
+
+    header(
+        &mut out,
+        "node_monitor_job_window_max_percent",
+        "gauge",
+        "Maximum utilization within the job's current idle-check window",
+    );
+    for (labels, job) in &job_labels {
+        for (metric, window) in [
+            ("gpu", &job.gpu_utilizations),
+            ("gpu_memory", &job.gpu_memory_utilizations),
+            ("cpu", &job.cpu_utilizations),
+        ] {
+            if let Some(max) = window.as_ref().and_then(SlidingWindow::max) {
+                let _ = writeln!(
+                    out,
+                    "node_monitor_job_window_max_percent{{{},metric=\"{}\"}} {}",
+                    labels, metric, max
+                );
+            }
+        }
+    }
+
+    header(
+        &mut out,
+        "node_monitor_job_gpu_window_max_percent",
+        "gauge",
+        "Maximum utilization of each GPU within the job's current idle-check window",
+    );
+    for (labels, job) in &job_labels {
+        for (uuid, window) in &job.gpu_device_utilizations {
+            if let Some(max) = window.max() {
+                let _ = writeln!(
+                    out,
+                    "node_monitor_job_gpu_window_max_percent{{{},gpu_uuid=\"{}\"}} {}",
+                    labels,
+                    escape(uuid),
+                    max
+                );
+            }
+        }
+    }
+
+    header(
+        &mut out,
+        "node_monitor_job_idle_gpus",
+        "gauge",
+        "Number of the job's GPUs that stayed idle for a whole window",
+    );
+    for (labels, job) in &job_labels {
+        let _ = writeln!(
+            out,
+            "node_monitor_job_idle_gpus{{{}}} {}",
+            labels,
+            job.idle_gpus(gpu_threshold)
+        );
+    }
+
+    header(
+        &mut out,
+        "node_monitor_job_metrics_received",
+        "gauge",
+        "Number of metric samples received from the job's monitor",
+    );
+    for (labels, job) in &job_labels {
+        let _ = writeln!(
+            out,
+            "node_monitor_job_metrics_received{{{}}} {}",
+            labels, job.metrics_received
+        );
+    }
+
+    header(
+        &mut out,
+        "node_monitor_job_warning_stage",
+        "gauge",
+        "Idle warning stage of the job (0 = active, 1 = warned, 2 = final warning)",
+    );
+    for (labels, job) in &job_labels {
+        let stage = match job.escalation {
+            Escalation::Active => 0,
+            Escalation::Warned { .. } => 1,
+            Escalation::FinalWarning { .. } => 2,
+        };
+        let _ = writeln!(out, "node_monitor_job_warning_stage{{{}}} {}", labels, stage);
+    }
+
+    header(
+        &mut out,
+        "node_monitor_tracked_jobs",
+        "gauge",
+        "Number of jobs being tracked",
+    );
+    let _ = writeln!(out, "node_monitor_tracked_jobs {}", tracker.jobs.len());
+
+    header(
+        &mut out,
+        "node_monitor_enforcement_paused",
+        "gauge",
+        "Whether idle cancellation is paused on this node",
+    );
+    let _ = writeln!(
+        out,
+        "node_monitor_enforcement_paused {}",
+        u8::from(tracker.enforcement_paused)
+    );
+
+    header(
+        &mut out,
+        "node_monitor_kills_total",
+        "counter",
+        "Job cancellations queued by the monitor, by reason",
+    );
+    for reason in KillReason::ALL {
+        let _ = writeln!(
+            out,
+            "node_monitor_kills_total{{reason=\"{}\"}} {}",
+            reason.label(),
+            stats.kills[reason as usize].load(Ordering::Relaxed)
+        );
+    }
+
+    for (name, help, value) in stats.counters() {
+        header(&mut out, name, "counter", help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    header(
+        &mut out,
+        "node_monitor_connected_clients",
+        "gauge",
+        "Currently open connections on the client socket",
+    );
+    let _ = writeln!(out, "node_monitor_connected_clients {}", stats.connected_clients());
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn user_name(uid: u32) -> String {
+    match User::from_uid(Uid::from_raw(uid)) {
+        Ok(Some(user)) => user.name,
+        _ => uid.to_string(),
+    }
+}
+
+// 标签值中的反斜杠、双引号和换行需要转义
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Utc;
+
+    use crate::JobInfo;
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape("gpu"), "gpu");
+        assert_eq!(escape(r#"a"b"#), r#"a\"b"#);
+        assert_eq!(escape(r"C:\tmp"), r"C:\\tmp");
+        assert_eq!(escape("two\nlines"), r"two\nlines");
+        // 反斜杠先转义, 不会把其他转义产生的反斜杠再转义一次
+        assert_eq!(escape("\"\\\n"), r#"\"\\\n"#);
+    }
+
+    #[test]
+    fn renders_a_tracked_job() {
+        let mut job: JobInfo = serde_json::from_value(serde_json::json!({
+            "last_heartbeat_at": Utc::now(),
+            "registered_at": Utc::now(),
+            "owner_uid": 1500,
+            "metrics_received": 7,
+            "log_path": "/home/alice/.slurm/info-42.log",
+        }))
+        .unwrap();
+        job.partition = "gpu".to_string();
+        job.account = "lab \"a\"".to_string();
+        job.last_metrics = Some((12.5, 3.0, 80.0));
+        job.escalation = Escalation::Warned { since: Utc::now() };
+        let mut tracker = JobTracker::default();
+        tracker.jobs.insert("42".to_string(), job);
+        tracker.enforcement_paused = true;
+
+        let stats = Stats::default();
+        stats.record_kill(KillReason::CpuIdle);
+        stats.record_kill(KillReason::CpuIdle);
+        stats.record_heartbeat_timeout();
+        let users = HashMap::from([(1500, "alice".to_string())]);
+
+        let out = render(&tracker, &stats, 5.0, &users);
+        let labels = r#"job_id="42",user="alice",partition="gpu",account="lab \"a\"""#;
+        for line in [
+            "# HELP node_monitor_job_utilization_percent Most recent utilization reported for the job".to_string(),
+            "# TYPE node_monitor_job_utilization_percent gauge".to_string(),
+            format!(
+                r#"node_monitor_job_utilization_percent{{{},metric="gpu"}} 12.5"#,
+                labels
+            ),
+            format!(
+                r#"node_monitor_job_utilization_percent{{{},metric="gpu_memory"}} 3"#,
+                labels
+            ),
+            format!(r#"node_monitor_job_utilization_percent{{{},metric="cpu"}} 80"#, labels),
+            format!("node_monitor_job_idle_gpus{{{}}} 0", labels),
+            format!("node_monitor_job_metrics_received{{{}}} 7", labels),
+            format!("node_monitor_job_warning_stage{{{}}} 1", labels),
+            "node_monitor_tracked_jobs 1".to_string(),
+            "node_monitor_enforcement_paused 1".to_string(),
+            r#"node_monitor_kills_total{reason="cpu_idle"} 2"#.to_string(),
+            r#"node_monitor_kills_total{reason="gpu_idle"} 0"#.to_string(),
+            "# TYPE node_monitor_heartbeat_timeouts_total counter".to_string(),
+            "node_monitor_heartbeat_timeouts_total 1".to_string(),
+            "node_monitor_connected_clients 0".to_string(),
+        ] {
+            assert!(out.lines().any(|l| l == line), "missing {:?} in:\n{}", line, out);
+        }
+        // 没有窗口的指标不输出样本, 只有 HELP/TYPE
+        assert!(
+            !out.lines()
+                .any(|l| l.starts_with("node_monitor_job_window_max_percent{"))
+        );
+
+        // 找不到用户名时用 UID
+        let out = render(&tracker, &stats, 5.0, &HashMap::new());
+        assert!(
+            out.contains(r#"node_monitor_job_metrics_received{job_id="42",user="1500","#),
+            "{}",
+            out
+        );
+    }
+}
@@ -0,0 +1,366 @@
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::Local;
+use log::error;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::libc;
+use nix::unistd::{Gid, Uid, User, getgrouplist, getgroups, setfsgid, setfsuid};
+
+// ============================================================================
+// 任务日志 (Job Logs)
+// ============================================================================
+
+// 任务日志的路径由客户端提供, 因此一律以任务所有者的文件系统身份 (setfsuid/setfsgid 与附加组) 创建和写入:
+// 守护进程的 root 权限不会被借去创建或追加任意文件
+
+// 注册时检查并创建任务日志: 路径必须位于所有者的家目录或 extra_roots 之下, 且不经过符号链接
+pub async fn create(owner_uid: u32, log_path: &Path, extra_roots: &[PathBuf]) -> Result<()> {
+    let log_path = log_path.to_path_buf();
+    let extra_roots = extra_roots.to_vec();
+    run_as_owner(owner_uid, move |owner| {
+        let root = allowed_root(owner, &log_path, &extra_roots)?;
+        create_parent_dirs(root, &log_path)?;
+        open(owner, &log_path).map(drop)
+    })
+    .await
+}
+
+// 追加一行任务日志
+pub async fn append(owner_uid: u32, log_path: &Path, message: &str) -> Result<()> {
+    let line = format!("[{}] JOB-LOG: {}\n", Local::now().format("%Y-%m-%dT%H:%M:%S"), message);
+    let log_path = log_path.to_path_buf();
+    run_as_owner(owner_uid, move |owner| {
+        open(owner, &log_path)?
+            .write_all(line.as_bytes())
+            .with_context(|| format!("Failed to write to {}", log_path.display()))
+    })
+    .await
+}
+
+// 切换的身份只作用于当前线程, 所以放到阻塞线程池里执行, 返回前恢复为原来的身份
+async fn run_as_owner<T, F>(owner_uid: u32, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&User) -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let owner = User::from_uid(Uid::from_raw(owner_uid))
+            .with_context(|| format!("Failed to look up UID {}", owner_uid))?
+            .ok_or_else(|| anyhow!("Unknown user with uid {}", owner_uid))?;
+        let _credentials = FsCredentials::switch_to(&owner)?;
+        f(&owner)
+    })
+    .await
+    .context("Job log task panicked")?
+}
+
+// 切换当前线程的文件系统身份和附加组, drop 时恢复
+struct FsCredentials {
+    uid: Uid,
+    gid: Gid,
+    groups: Vec<Gid>,
+}
+
+impl FsCredentials {
+    fn switch_to(owner: &User) -> Result<Self> {
+        let name = CString::new(owner.name.as_str()).context("User name contains a NUL byte")?;
+        let groups = getgrouplist(&name, owner.gid)
+            .with_context(|| format!("Failed to look up the groups of {}", owner.name))?;
+        let saved_groups = getgroups().context("Failed to read the supplementary groups")?;
+        // 否则守护进程 (root) 的附加组仍然参与权限检查, 所有者借此能写入 root 组可写的文件
+        set_thread_groups(&groups).context("Failed to switch the supplementary groups")?;
+        // 修改 fsgid 与附加组依赖的 CAP_SETGID 不会因 fsuid 不再是 0 而失去 (只有文件相关的能力会被清除),
+        // 所以顺序无关紧要; 构造完成后任何一步失败都由 drop 恢复
+        let saved = Self {
+            groups: saved_groups,
+            gid: setfsgid(owner.gid),
+            uid: setfsuid(owner.uid),
+        };
+        // setfsuid/setfsgid 不报告失败, 再调用一次读回当前的值
+        if setfsgid(owner.gid) != owner.gid || setfsuid(owner.uid) != owner.uid {
+            bail!(
+                "Failed to switch filesystem credentials to UID {} GID {}",
+                owner.uid,
+                owner.gid
+            );
+        }
+        Ok(saved)
+    }
+}
+
+impl Drop for FsCredentials {
+    fn drop(&mut self) {
+        setfsuid(self.uid);
+        setfsgid(self.gid);
+        if let Err(e) = set_thread_groups(&self.groups) {
+            error!("Failed to restore the supplementary groups of a job log thread: {}", e);
+        }
+    }
+}
+
+// glibc 的 setgroups 会把修改广播到进程的所有线程, 这里直接发起系统调用, 只修改当前线程
+fn set_thread_groups(groups: &[Gid]) -> io::Result<()> {
+    let groups: Vec<libc::gid_t> = groups.iter().map(|gid| gid.as_raw()).collect();
+    // SAFETY: 指针和长度来自同一个 Vec, 内核只读取其中的内容
+    let result = unsafe { libc::syscall(libc::SYS_setgroups, groups.len(), groups.as_ptr()) };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// 返回 log_path 所在的允许目录; 路径必须是不含 `.` / `..` 的绝对路径
+fn allowed_root<'a>(owner: &'a User, log_path: &Path, extra_roots: &'a [PathBuf]) -> Result<&'a Path> {
+    let normalized = log_path.is_absolute()
+        && log_path
+            .components()
+            .all(|c| matches!(c, Component::RootDir | Component::Normal(_)));
+    if !normalized {
+        bail!(
+            "Log path {} must be an absolute path without '.' or '..' components",
+            log_path.display()
+        );
+    }
+
+    let roots = std::iter::once(owner.dir.as_path()).chain(extra_roots.iter().map(PathBuf::as_path));
+    for root in roots {
+        if log_path.starts_with(root) && log_path != root {
+            return Ok(root);
+        }
+    }
+    let allowed = std::iter::once(&owner.dir)
+        .chain(extra_roots)
+        .map(|r| r.display().to_string())
+        .collect::<Vec<_>>();
+    bail!(
+        "Log path {} is outside the allowed directories ({})",
+        log_path.display(),
+        allowed.join(", ")
+    )
+}
+
+// 逐级创建 root 与日志文件之间的目录, 已存在的目录不能是符号链接
+fn create_parent_dirs(root: &Path, log_path: &Path) -> Result<()> {
+    let Some(parent) = log_path.parent() else {
+        return Ok(());
+    };
+    let Ok(relative) = parent.strip_prefix(root) else {
+        return Ok(());
+    };
+
+    let mut dir = root.to_path_buf();
+    for component in relative.components() {
+        dir.push(component);
+        match fs::symlink_metadata(&dir) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                bail!("Refusing to use {}: it is a symbolic link", dir.display());
+            }
+            Ok(metadata) if !metadata.is_dir() => bail!("{} is not a directory", dir.display()),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::create_dir(&dir).with_context(|| format!("Failed to create directory {}", dir.display()))?;
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to inspect {}", dir.display())),
+        }
+    }
+    Ok(())
+}
+
+// 以追加方式打开 (必要时创建) 任务日志: 不跟随符号链接, 只接受属于任务所有者的普通文件
+fn open(owner: &User, log_path: &Path) -> Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        // O_NONBLOCK 避免打开 FIFO 时被卡住
+        .custom_flags((OFlag::O_NOFOLLOW | OFlag::O_NONBLOCK).bits())
+        .open(log_path)
+        .map_err(|e| match e.raw_os_error() {
+            Some(code) if code == Errno::ELOOP as i32 => {
+                anyhow!("Refusing to use {}: it is a symbolic link", log_path.display())
+            }
+            // 没有读端的 FIFO 或设备文件
+            Some(code) if code == Errno::ENXIO as i32 => {
+                anyhow!("Refusing to use {}: it is not a regular file", log_path.display())
+            }
+            _ => anyhow!(e).context(format!("Failed to open {}", log_path.display())),
+        })?;
+
+    let metadata = file
+        .metadata()
+        .with_context(|| format!("Failed to inspect {}", log_path.display()))?;
+    if !metadata.is_file() {
+        bail!("Refusing to use {}: it is not a regular file", log_path.display());
+    }
+    // 所有者凭组权限可写的他人文件也不接受, 只追加到所有者自己的文件
+    if metadata.uid() != owner.uid.as_raw() {
+        bail!(
+            "Refusing to use {}: it is owned by UID {}, not the job owner (UID {})",
+            log_path.display(),
+            metadata.uid(),
+            owner.uid
+        );
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::fs::{PermissionsExt, symlink};
+
+    use nix::sys::stat::Mode;
+    use nix::unistd::{chown, mkfifo};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("node_monitor_joblog_{}_{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn user(name: &str, home: &str) -> User {
+        let mut user = User::from_name(name)
+            .expect("user lookup failed")
+            .unwrap_or_else(|| panic!("no '{}' user", name));
+        user.dir = PathBuf::from(home);
+        user
+    }
+
+    fn current_user() -> User {
+        User::from_uid(Uid::effective())
+            .expect("user lookup failed")
+            .expect("current user has no passwd entry")
+    }
+
+    #[test]
+    fn accepts_paths_below_the_home_directory_and_extra_roots() {
+        let owner = user("nobody", "/home/alice");
+        let extra_roots = [PathBuf::from("/var/spool/slurmd")];
+
+        let root = allowed_root(&owner, Path::new("/home/alice/logs/job.log"), &extra_roots).unwrap();
+        assert_eq!(root, Path::new("/home/alice"));
+        let root = allowed_root(&owner, Path::new("/var/spool/slurmd/job_42.log"), &extra_roots).unwrap();
+        assert_eq!(root, Path::new("/var/spool/slurmd"));
+    }
+
+    #[test]
+    fn refuses_paths_outside_the_allowed_roots() {
+        let owner = user("nobody", "/home/alice");
+        let extra_roots = [PathBuf::from("/var/spool/slurmd")];
+
+        for path in [
+            "/etc/cron.d/job",
+            "/home/bob/job.log",
+            // 只按完整的路径分量匹配
+            "/home/alice2/job.log",
+            // 允许的目录本身不是日志文件
+            "/home/alice",
+            "/var/spool/slurmd",
+        ] {
+            let e = allowed_root(&owner, Path::new(path), &extra_roots).unwrap_err();
+            assert!(
+                e.to_string().contains("outside the allowed directories"),
+                "{}: {}",
+                path,
+                e
+            );
+        }
+        for path in ["job.log", "logs/job.log", "/home/alice/../../etc/passwd"] {
+            let e = allowed_root(&owner, Path::new(path), &extra_roots).unwrap_err();
+            assert!(e.to_string().contains("must be an absolute path"), "{}: {}", path, e);
+        }
+    }
+
+    #[test]
+    fn creates_missing_directories_but_refuses_symlinks() {
+        let root = temp_dir("dirs");
+        create_parent_dirs(&root, &root.join("a/b/job.log")).unwrap();
+        assert!(root.join("a/b").is_dir());
+
+        let elsewhere = temp_dir("dirs_elsewhere");
+        symlink(&elsewhere, root.join("link")).unwrap();
+        let e = create_parent_dirs(&root, &root.join("link/sub/job.log")).unwrap_err();
+        assert!(e.to_string().contains("symbolic link"), "{}", e);
+        assert!(!elsewhere.join("sub").exists());
+
+        // 中间一级是符号链接也一样
+        symlink(&elsewhere, root.join("a/inner")).unwrap();
+        let e = create_parent_dirs(&root, &root.join("a/inner/job.log")).unwrap_err();
+        assert!(e.to_string().contains("symbolic link"), "{}", e);
+
+        fs::write(root.join("file"), "").unwrap();
+        let e = create_parent_dirs(&root, &root.join("file/job.log")).unwrap_err();
+        assert!(e.to_string().contains("is not a directory"), "{}", e);
+
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_dir_all(&elsewhere).unwrap();
+    }
+
+    #[test]
+    fn opens_only_regular_files_owned_by_the_owner() {
+        let dir = temp_dir("open");
+        let owner = current_user();
+
+        let log_path = dir.join("job.log");
+        open(&owner, &log_path).unwrap().write_all(b"line\n").unwrap();
+        open(&owner, &log_path).unwrap().write_all(b"line\n").unwrap();
+        assert_eq!(fs::read_to_string(&log_path).unwrap(), "line\nline\n");
+
+        let target = dir.join("target");
+        fs::write(&target, "").unwrap();
+        symlink(&target, dir.join("link.log")).unwrap();
+        let e = open(&owner, &dir.join("link.log")).unwrap_err();
+        assert!(e.to_string().contains("symbolic link"), "{}", e);
+        // 悬空的符号链接也不会被跟随去创建目标文件
+        symlink(dir.join("missing"), dir.join("dangling.log")).unwrap();
+        let e = open(&owner, &dir.join("dangling.log")).unwrap_err();
+        assert!(e.to_string().contains("symbolic link"), "{}", e);
+        assert!(!dir.join("missing").exists());
+
+        mkfifo(&dir.join("fifo.log"), Mode::S_IRUSR | Mode::S_IWUSR).unwrap();
+        let e = open(&owner, &dir.join("fifo.log")).unwrap_err();
+        assert!(e.to_string().contains("not a regular file"), "{}", e);
+
+        if Uid::effective().is_root() {
+            let foreign = dir.join("foreign.log");
+            fs::write(&foreign, "").unwrap();
+            chown(&foreign, Some(Uid::from_raw(65534)), None).unwrap();
+            let e = open(&owner, &foreign).unwrap_err();
+            assert!(e.to_string().contains("not the job owner"), "{}", e);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn switches_and_restores_the_thread_credentials() {
+        // 切换身份需要 root
+        if !Uid::effective().is_root() {
+            return;
+        }
+        let owner = user("nobody", "/nonexistent");
+        let expected = getgrouplist(&CString::new("nobody").unwrap(), owner.gid).unwrap();
+        let dir = temp_dir("credentials");
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        let groups_before = getgroups().unwrap();
+
+        {
+            let _credentials = FsCredentials::switch_to(&owner).unwrap();
+            assert_eq!(getgroups().unwrap(), expected);
+            // 以 nobody 的身份不能在 root 的目录中创建文件
+            let e = File::create(dir.join("job.log")).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        }
+
+        assert_eq!(getgroups().unwrap(), groups_before);
+        File::create(dir.join("job.log")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::time::{self, Instant};
+
+// 连续这么多条请求超出速率上限时关闭连接
+pub const MAX_RATE_VIOLATIONS: u32 = 10;
+
+// ============================================================================
+// 客户端 socket 的限制 (Client Socket Limits)
+// ============================================================================
+
+// 读取一条请求的结果
+pub enum ReadOutcome {
+    // 读到一条完整的请求 (或连接关闭前的最后一段)
+    Line,
+    Closed,
+    // 超过长度上限仍未读到换行
+    TooLarge,
+    // 超时时间内没有读到完整的请求
+    IdleTimeout,
+}
+
+// 读取一行请求, 最多读取 max_bytes 字节, 避免一直不发换行的客户端耗尽内存;
+// 超时覆盖整条请求, 逐字节慢慢发送的客户端同样会被断开
+pub async fn read_request(
+    reader: &mut BufReader<UnixStream>,
+    line: &mut String,
+    max_bytes: usize,
+    idle_timeout: Duration,
+) -> io::Result<ReadOutcome> {
+    let mut limited = reader.take(max_bytes as u64 + 1);
+    match time::timeout(idle_timeout, limited.read_line(line)).await {
+        Err(_) => Ok(ReadOutcome::IdleTimeout),
+        Ok(Ok(0)) => Ok(ReadOutcome::Closed),
+        Ok(Ok(n)) if n > max_bytes => Ok(ReadOutcome::TooLarge),
+        Ok(Ok(_)) => Ok(ReadOutcome::Line),
+        Ok(Err(e)) => Err(e),
+    }
+}
+
+// 每个连接各自的令牌桶: 最多攒 burst 个令牌, 每分钟补充 per_minute 个
+pub struct RateLimiter {
+    burst: f64,
+    per_sec: f64,
+    tokens: f64,
+    refilled_at: Instant,
+    // 连续被拒绝的请求数, 放行一条后清零
+    violations: u32,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        Self {
+            burst: f64::from(burst),
+            per_sec: f64::from(per_minute) / 60.0,
+            tokens: f64::from(burst),
+            refilled_at: Instant::now(),
+            violations: 0,
+        }
+    }
+
+    // 取一个令牌; 令牌不足时拒绝这条请求, 返回攒够一个令牌还需要的时间
+    pub fn acquire(&mut self) -> Result<(), Duration> {
+        self.acquire_at(Instant::now())
+    }
+
+    fn acquire_at(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.burst);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            self.violations = 0;
+            return Ok(());
+        }
+        self.violations += 1;
+        Err(Duration::from_secs_f64((1.0 - self.tokens) / self.per_sec))
+    }
+
+    // 连续超限次数达到上限, 应关闭连接
+    pub fn exhausted(&self) -> bool {
+        self.violations >= MAX_RATE_VIOLATIONS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    #[test]
+    fn allows_a_burst_then_refills_over_time() {
+        let mut limiter = RateLimiter::new(60, 3);
+        let start = limiter.refilled_at;
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire_at(start), Ok(()));
+        }
+        // 每秒补充一个令牌
+        assert_eq!(limiter.acquire_at(start), Err(Duration::from_secs(1)));
+        assert_eq!(
+            limiter.acquire_at(start + Duration::from_millis(500)),
+            Err(Duration::from_millis(500))
+        );
+        assert_eq!(limiter.acquire_at(start + Duration::from_secs(1)), Ok(()));
+
+        // 令牌不会超过 burst
+        let later = start + Duration::from_secs(600);
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire_at(later), Ok(()));
+        }
+        assert!(limiter.acquire_at(later).is_err());
+    }
+
+    #[test]
+    fn repeated_violations_exhaust_the_connection() {
+        let mut limiter = RateLimiter::new(60, 1);
+        let start = limiter.refilled_at;
+        assert_eq!(limiter.acquire_at(start), Ok(()));
+        for _ in 0..MAX_RATE_VIOLATIONS - 1 {
+            assert!(limiter.acquire_at(start).is_err());
+        }
+        assert!(!limiter.exhausted());
+
+        // 中间放行过一条请求时重新计数
+        assert_eq!(limiter.acquire_at(start + Duration::from_secs(1)), Ok(()));
+        for _ in 0..MAX_RATE_VIOLATIONS - 1 {
+            assert!(limiter.acquire_at(start + Duration::from_secs(1)).is_err());
+        }
+        assert!(!limiter.exhausted());
+        assert!(limiter.acquire_at(start + Duration::from_secs(1)).is_err());
+        assert!(limiter.exhausted());
+    }
+
+    async fn read_one(client_sends: &[u8], max_bytes: usize, close: bool) -> (ReadOutcome, String) {
+        let (server, mut client) = UnixStream::pair().unwrap();
+        client.write_all(client_sends).await.unwrap();
+        // close 为 false 时保持连接但不再发送
+        let _client = (!close).then_some(client);
+        let mut reader = BufReader::new(server);
+        let mut line = String::new();
+        let outcome = read_request(&mut reader, &mut line, max_bytes, Duration::from_millis(100))
+            .await
+            .unwrap();
+        (outcome, line)
+    }
+
+    #[tokio::test]
+    async fn reads_one_request_per_line() {
+        let (outcome, line) = read_one(b"{\"type\":\"STATUS\"}\nnext", 64, false).await;
+        assert!(matches!(outcome, ReadOutcome::Line));
+        assert_eq!(line, "{\"type\":\"STATUS\"}\n");
+
+        // 连接关闭前的最后一段没有换行符
+        let (outcome, line) = read_one(b"partial", 64, true).await;
+        assert!(matches!(outcome, ReadOutcome::Line));
+        assert_eq!(line, "partial");
+
+        let (outcome, _) = read_one(b"", 64, true).await;
+        assert!(matches!(outcome, ReadOutcome::Closed));
+    }
+
+    #[tokio::test]
+    async fn stops_reading_oversized_requests() {
+        let (outcome, line) = read_one(&[b'x'; 100], 16, false).await;
+        assert!(matches!(outcome, ReadOutcome::TooLarge));
+        assert_eq!(line.len(), 17);
+
+        // 恰好等于上限 (含换行符) 的请求可以读取
+        let mut request = vec![b'x'; 15];
+        request.push(b'\n');
+        let (outcome, _) = read_one(&request, 16, false).await;
+        assert!(matches!(outcome, ReadOutcome::Line));
+    }
+
+    #[tokio::test]
+    async fn times_out_idle_and_slow_clients() {
+        let (outcome, _) = read_one(b"", 64, false).await;
+        assert!(matches!(outcome, ReadOutcome::IdleTimeout));
+
+        // 只发了半条请求也按超时处理
+        let (outcome, _) = read_one(b"{\"type\":", 64, false).await;
+        assert!(matches!(outcome, ReadOutcome::IdleTimeout));
+    }
+}
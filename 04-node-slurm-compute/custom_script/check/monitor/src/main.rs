@@ -1,104 +1,245 @@
-use std::collections::{HashMap, VecDeque};
+mod actions;
+mod admin;
+mod audit;
+mod auth;
+mod config;
+mod escalation;
+mod exporter;
+mod joblog;
+mod limits;
+mod notify;
+mod policy;
+mod respawn;
+mod sampling;
+mod state;
+mod status;
+mod supervisor;
+mod tracker;
+mod window;
+
+use std::collections::BTreeMap;
 use std::io::Write;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
-use anyhow::{Context, Result};
-use chrono::Local;
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local, Utc};
+use clap::Parser;
 use log::{error, info, warn};
+use protocol::{
+    AckResponse, CancelPayload, CancelResponse, ErrorCode, ErrorResponse, Exemption, HelloPayload, HelloResponse,
+    Message, MetricsPayload, MonitorLost, RegisterPayload, RegisterResponse, StatusPayload, SupervisedMonitor,
+};
 use serde::{Deserialize, Serialize};
-use tokio::fs::{self, OpenOptions};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::fs;
+use tokio::io::{AsyncWriteExt, BufReader};
 use tokio::net::{UnixListener, UnixStream};
-use tokio::sync::Mutex;
-use tokio::time::{self, Instant};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::{RwLock, mpsc};
+use tokio::time::Instant;
+
+use crate::actions::{ActionQueue, ActionQueueHandle, ActionState, SharedActionQueue};
+use crate::admin::CtlCommand;
+use crate::audit::DryRunRecord;
+use crate::auth::{SharedOwnerResolver, SlurmOwnerResolver};
+use crate::config::{Config, DEFAULT_CONFIG_PATH, EnforcementMode, MonitorLostAction, SamplingMode};
+use crate::escalation::{Escalation, EscalationExt, EscalationStep};
+use crate::exporter::{KillReason, SharedStats, Stats};
+use crate::limits::{RateLimiter, ReadOutcome};
+use crate::policy::IdleGpuAction;
+use crate::supervisor::{SharedSupervisor, Supervisor};
+use crate::tracker::{JobTracker, SharedTracker};
+use crate::window::SlidingWindow;
 
 // ============================================================================
-// 常量定义 (Constants)
+// 命令行接口定义 (Command-Line Interface)
 // ============================================================================
-const SOCKET_PATH: &str = "/var/run/node_monitor.sock";
 
-// 心跳超时时间 (秒)
-const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(180);
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Slurm node monitor daemon", long_about = None)]
+struct Cli {
+    #[arg(long, short, value_name = "PATH", default_value = DEFAULT_CONFIG_PATH)]
+    config: PathBuf,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Commands {
+    /// Validate a config file and exit without starting the daemon
+    CheckConfig {
+        #[arg(value_name = "PATH")]
+        path: Option<PathBuf>,
+    },
+    /// Show pending and failed job cancellations from the action queue
+    Actions {
+        /// Also show cancellations that were already confirmed
+        #[arg(long)]
+        all: bool,
+    },
+    /// Inspect and control the running daemon through its admin socket (root only)
+    Ctl {
+        /// Print the raw daemon response as JSON
+        #[arg(long)]
+        json: bool,
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+    /// Show jobs that would have been cancelled while running in dry-run mode
+    DryRunLog {
+        /// Only show records for this job
+        #[arg(long, value_name = "JOB_ID")]
+        job: Option<String>,
+        /// Look up each job's actual final state in sacct for comparison
+        #[arg(long)]
+        outcome: bool,
+    },
+}
 
-// 心跳检测间隔 (秒)
-const HEARTBEAT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+// ============================================================================
+// 常量定义 (Constants)
+// ============================================================================
 
-// 利用率阈值 (百分比)
-const GPU_UTILIZATION_THRESHOLD: f64 = 5.0;
-const GPU_MEMORY_UTILIZATION_THRESHOLD: f64 = 5.0;
-const CPU_UTILIZATION_THRESHOLD: f64 = 5.0;
+// 每个任务最多分别跟踪多少张 GPU, 防止客户端上报大量不同的 UUID
+const MAX_TRACKED_GPUS: usize = 64;
 
-// 丢弃前多少个数据 (个) (用于给用户加载模型或单纯墨迹的时间)
-const BUFFER_PERIOD: usize = 30;
+// 客户端连接的编号, 用于确认断开的连接是否仍是任务当前的监控连接
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
 
 // ============================================================================
 // 数据结构定义 (Data Structures)
 // ============================================================================
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "type", content = "payload")]
-enum Message {
-    #[serde(rename = "REGISTER")]
-    Register(RegisterPayload),
-    #[serde(rename = "METRICS")]
-    Metrics(MetricsPayload),
-    #[serde(rename = "CANCEL")]
-    Cancel(CancelPayload),
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct RegisterPayload {
-    job_id: String,
-    gpu_monitor_count: usize,
-    cpu_monitor_count: usize,
-    log_path: PathBuf,
+// 处理 METRICS 的结果
+enum MetricsOutcome {
+    Accepted,
+    UnknownJob,
+    // 任务被判定为空闲, 需要取消: (任务号, 原因类别, 原因)
+    Kill(String, KillReason, String),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct MetricsPayload {
-    job_id: String,
-    gpu_utilization: f64,
-    gpu_memory_utilization: f64,
-    cpu_utilization: f64,
+// 心跳定时器到期或监控连接断开时的判定结果
+enum ExpiryOutcome {
+    // 任务已不再跟踪, 或仍在等待心跳/重新连接 (定时器已顺延)
+    Alive,
+    // 已停止跟踪, 但因暂停、豁免或试运行不取消
+    Untracked(KillReason),
+    Kill(KillReason, String),
+    // 以任务所有者的身份重新启动 job_helper monitor
+    Respawn { owner_uid: u32, log_path: PathBuf },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct CancelPayload {
-    job_id: String,
+// 更新任务状态后, 在状态任务之外完成的 I/O
+enum JobEffect {
+    Log {
+        owner_uid: u32,
+        log_path: PathBuf,
+        message: String,
+    },
+    // 写入任务日志并推送到用户终端
+    Notify {
+        owner_uid: u32,
+        log_path: PathBuf,
+        job_id: String,
+        message: String,
+    },
+    DryRun {
+        path: PathBuf,
+        record: DryRunRecord,
+    },
 }
 
+#[derive(Serialize, Deserialize)]
 struct JobInfo {
+    // Instant 无法持久化, 恢复时根据 last_heartbeat_at 重新计算
+    #[serde(skip, default = "Instant::now")]
     last_heartbeat: Instant,
-    gpu_monitor_count: usize,
-    cpu_monitor_count: usize,
-    gpu_utilizations: VecDeque<f64>,
-    gpu_memory_utilizations: VecDeque<f64>,
-    cpu_utilizations: VecDeque<f64>,
+    last_heartbeat_at: DateTime<Utc>,
+    registered_at: DateTime<Utc>,
+    owner_uid: u32,
+    #[serde(default)]
+    partition: String,
+    #[serde(default)]
+    qos: String,
+    #[serde(default)]
+    account: String,
+    #[serde(default)]
+    allocated_cpus: usize,
+    // 各指标的时间窗口, None 表示不检查该指标
+    gpu_utilizations: Option<SlidingWindow>,
+    gpu_memory_utilizations: Option<SlidingWindow>,
+    cpu_utilizations: Option<SlidingWindow>,
+    // 按 GPU UUID 分别记录的利用率窗口, 长度与 GPU 窗口相同
+    #[serde(default)]
+    gpu_device_utilizations: BTreeMap<String, SlidingWindow>,
+    // 已就闲置的卡提醒过用户
+    #[serde(default)]
+    idle_gpus_warned: bool,
     metrics_received: usize,
+    // 最近一个样本的采样时间, 早于它的样本 (重复或乱序) 直接丢弃
+    #[serde(default)]
+    last_sample_at: Option<DateTime<Utc>>,
+    // 最近一次上报的 (GPU, GPU 显存, CPU) 利用率, 只用于导出指标
+    #[serde(skip)]
+    last_metrics: Option<(f64, f64, f64)>,
     log_path: PathBuf,
+    #[serde(default)]
+    escalation: Escalation,
+    #[serde(default)]
+    exemption: Option<Exemption>,
+    #[serde(default)]
+    source: MetricsSource,
+    // job_helper monitor 的连接断开后等待其重新连接
+    #[serde(default)]
+    monitor_lost: Option<MonitorLost>,
+    // 最近一次送来 METRICS 的连接, 只有它断开才算监控进程丢失
+    #[serde(skip)]
+    monitor_connection: Option<u64>,
+    // 由守护进程启动并看护的 job_helper monitor; 保存进程号以便守护进程重启后继续看护
+    #[serde(default)]
+    supervised_monitor: Option<SupervisedMonitor>,
 }
 
-struct JobTracker {
-    jobs: HashMap<String, JobInfo>,
+// 指标来源: 任务内的 job_helper monitor 进程, 或守护进程直接从 cgroup 采样
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+enum MetricsSource {
+    #[default]
+    Client,
+    Daemon,
 }
 
-impl JobTracker {
-    fn new() -> Self {
-        Self { jobs: HashMap::new() }
+impl JobInfo {
+    // 心跳定时器的到期时间; monitor lost 状态下改为等待重新连接的截止时间
+    fn expires_at(&self, heartbeat_timeout: Duration) -> Instant {
+        match self.monitor_lost {
+            Some(lost) => Instant::now() + (lost.deadline - Utc::now()).to_std().unwrap_or_default(),
+            None => self.last_heartbeat + heartbeat_timeout,
+        }
     }
-    fn remove_job(&mut self, job_id: &str) -> Option<JobInfo> {
-        self.jobs.remove(job_id)
+
+    fn is_exempt(&self, now: DateTime<Utc>) -> bool {
+        self.exemption.is_some_and(|e| e.until.is_none_or(|until| now < until))
+    }
+
+    // 连续数据已覆盖整个窗口且整个窗口内利用率都低于阈值的卡数
+    fn idle_gpus(&self, threshold: f64) -> usize {
+        self.gpu_device_utilizations
+            .values()
+            .filter(|window| window.is_complete() && window.max().is_some_and(|max| max < threshold))
+            .count()
     }
 }
 
 // ============================================================================
 // 主函数 (Main Function)
 // ============================================================================
-type SharedTracker = Arc<Mutex<JobTracker>>;
+type SharedConfig = Arc<RwLock<Config>>;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -114,27 +255,125 @@ async fn main() -> Result<()> {
         })
         .init();
 
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(Commands::CheckConfig { path }) => {
+            let path = path.unwrap_or(cli.config);
+            let config = Config::load(&path)?;
+            println!("Config file {} is valid:\n{:#?}", path.display(), config);
+            return Ok(());
+        }
+        Some(Commands::Actions { all }) => {
+            let config = Config::load_or_default(&cli.config)?;
+            print_actions(&ActionQueue::load(&config.kill_queue_path)?, all);
+            return Ok(());
+        }
+        Some(Commands::Ctl { json, command }) => {
+            let config = Config::load_or_default(&cli.config)?;
+            admin::run_ctl(&config.admin_socket_path, command, json).await?;
+            return Ok(());
+        }
+        Some(Commands::DryRunLog { job, outcome }) => {
+            let config = Config::load_or_default(&cli.config)?;
+            audit::print_records(&config.dry_run_log_path, job.as_deref(), outcome)?;
+            return Ok(());
+        }
+        None => {}
+    }
+
     info!("Starting Node Monitor Daemon...");
 
-    setup_socket(SOCKET_PATH).await?;
+    let config = Config::load_or_default(&cli.config)?;
+    info!("Loaded config from {}: {:?}", cli.config.display(), config);
+    let socket_path = config.socket_path.clone();
+    let config = Arc::new(RwLock::new(config));
+
+    setup_socket(&socket_path).await?;
+
+    let (removed_jobs_tx, removed_jobs) = mpsc::unbounded_channel();
+    let mut job_tracker = JobTracker::default();
+    job_tracker.removed_jobs = Some(removed_jobs_tx);
+    let (tracker, expired_jobs) = tracker::spawn(job_tracker);
+    let (state_path, heartbeat_timeout) = {
+        let config_lock = config.read().await;
+        (config_lock.state_path.clone(), config_lock.heartbeat_timeout)
+    };
+    state::restore_state(&tracker, &state_path, heartbeat_timeout).await;
+    tokio::spawn(state::run_state_persister(tracker.clone(), config.clone()));
+
+    let owners: SharedOwnerResolver = Arc::new(SlurmOwnerResolver);
+
+    let kill_queue_path = config.read().await.kill_queue_path.clone();
+    let action_queue = ActionQueue::load(&kill_queue_path).unwrap_or_else(|e| {
+        error!("Failed to load action queue, starting with an empty one: {:#}", e);
+        ActionQueue::default()
+    });
+    let actions = ActionQueueHandle::new(action_queue);
+    tokio::spawn(actions::run_action_queue(actions.clone(), config.clone()));
+
+    let stats: SharedStats = Arc::new(Stats::default());
+    if let Some(addr) = config.read().await.metrics_listen {
+        tokio::spawn(exporter::run_exporter(
+            addr,
+            tracker.clone(),
+            config.clone(),
+            stats.clone(),
+        ));
+    }
+
+    let supervisor = Supervisor::new(tracker.clone(), config.clone(), stats.clone());
+    tokio::spawn(supervisor::run_supervisor(supervisor.clone(), removed_jobs));
+    supervisor.resume().await;
 
-    let tracker = Arc::new(Mutex::new(JobTracker::new()));
-    let tracker_clone_for_checker = tracker.clone();
-    tokio::spawn(run_status_checker(tracker_clone_for_checker));
+    if config.read().await.sampling_mode == SamplingMode::Daemon {
+        tokio::spawn(sampling::run_daemon_sampler(
+            tracker.clone(),
+            config.clone(),
+            actions.clone(),
+            stats.clone(),
+        ));
+    }
+
+    tokio::spawn(run_heartbeat_checker(
+        expired_jobs,
+        tracker.clone(),
+        config.clone(),
+        actions.clone(),
+        stats.clone(),
+        supervisor.clone(),
+    ));
+    let admin_socket_path = config.read().await.admin_socket_path.clone();
+    tokio::spawn(admin::run_admin_server(
+        admin_socket_path,
+        cli.config.clone(),
+        tracker.clone(),
+        config.clone(),
+        stats.clone(),
+    ));
+    tokio::spawn(run_config_reloader(cli.config, config.clone()));
 
-    let listener =
-        UnixListener::bind(SOCKET_PATH).with_context(|| format!("Failed to listen on unix socket {}", SOCKET_PATH))?;
+    let listener = UnixListener::bind(&socket_path)
+        .with_context(|| format!("Failed to listen on unix socket {}", socket_path.display()))?;
 
     let perms = PermissionsExt::from_mode(0o777);
-    fs::set_permissions(SOCKET_PATH, perms).await?;
-    info!("Set socket {} permissions to 0777", SOCKET_PATH);
+    fs::set_permissions(&socket_path, perms).await?;
+    info!("Set socket {} permissions to 0777", socket_path.display());
 
-    info!("Listening on {}", SOCKET_PATH);
+    info!("Listening on {}", socket_path.display());
     loop {
         match listener.accept().await {
             Ok((stream, _addr)) => {
                 let tracker_clone_for_handler = tracker.clone();
-                tokio::spawn(handle_connection(stream, tracker_clone_for_handler));
+                tokio::spawn(handle_connection(
+                    stream,
+                    tracker_clone_for_handler,
+                    config.clone(),
+                    owners.clone(),
+                    actions.clone(),
+                    stats.clone(),
+                    supervisor.clone(),
+                ));
             }
             Err(e) => {
                 error!("Failed to accept connection: {}", e);
@@ -143,8 +382,7 @@ async fn main() -> Result<()> {
     }
 }
 
-async fn setup_socket(path_str: &str) -> Result<()> {
-    let path = Path::new(path_str);
+async fn setup_socket(path: &Path) -> Result<()> {
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent)
             .await
@@ -158,49 +396,294 @@ async fn setup_socket(path_str: &str) -> Result<()> {
     Ok(())
 }
 
+// ============================================================================
+// 配置热加载 (Config Reload)
+// ============================================================================
+
+// 收到 SIGHUP 时重新读取配置文件; 已跟踪的任务保存在 JobTracker 中, 不受影响
+async fn run_config_reloader(path: PathBuf, config: SharedConfig) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Failed to install SIGHUP handler, config reload disabled: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading config from {}", path.display());
+        if let Err(e) = reload_config(&path, &config).await {
+            error!("Failed to reload config, keeping the current one: {:#}", e);
+        }
+    }
+}
+
+async fn reload_config(path: &Path, config: &SharedConfig) -> Result<()> {
+    let mut new_config = Config::load(path)?;
+
+    let mut config_lock = config.write().await;
+    for (name, old, new) in [
+        ("socket_path", &config_lock.socket_path, &new_config.socket_path),
+        (
+            "admin_socket_path",
+            &config_lock.admin_socket_path,
+            &new_config.admin_socket_path,
+        ),
+    ] {
+        if old != new {
+            warn!(
+                "{} changed from {} to {}; this only takes effect after a restart",
+                name,
+                old.display(),
+                new.display()
+            );
+        }
+    }
+    if new_config.metrics_listen != config_lock.metrics_listen {
+        warn!("metrics_listen changed; this only takes effect after a restart");
+    }
+    if new_config.sampling_mode != config_lock.sampling_mode {
+        warn!("sampling_mode changed; this only takes effect after a restart");
+        // 继续报告实际使用的采样方式
+        new_config.sampling_mode = config_lock.sampling_mode;
+    }
+    info!("Config reloaded: {:?}", new_config);
+    *config_lock = new_config;
+    Ok(())
+}
+
 // ============================================================================
 // 处理客户端连接函数 (Handle Client Connection Function)
 // ============================================================================
 
-async fn handle_connection(stream: UnixStream, tracker: SharedTracker) {
-    info!("Accepted new connection");
+async fn handle_connection(
+    stream: UnixStream,
+    tracker: SharedTracker,
+    config: SharedConfig,
+    owners: SharedOwnerResolver,
+    actions: SharedActionQueue,
+    stats: SharedStats,
+    supervisor: SharedSupervisor,
+) {
+    // 通过 SO_PEERCRED 获取对端 UID, 后续所有请求都据此鉴权
+    let peer_uid = match stream.peer_cred() {
+        Ok(cred) => cred.uid(),
+        Err(e) => {
+            error!("Failed to read peer credentials, closing connection: {}", e);
+            return;
+        }
+    };
+    let (max_connections, max_message_bytes, idle_timeout, messages_per_minute, mut rate_limiter) = {
+        let config_lock = config.read().await;
+        (
+            config_lock.client_max_connections_per_uid,
+            config_lock.client_max_message_bytes,
+            config_lock.client_idle_timeout,
+            config_lock.client_messages_per_minute,
+            RateLimiter::new(config_lock.client_messages_per_minute, config_lock.client_message_burst),
+        )
+    };
     let mut reader = BufReader::new(stream);
+    let Some(_client) = stats.client_connected(peer_uid, max_connections) else {
+        warn!(
+            "UID {} already has {} open connections, refusing a new one.",
+            peer_uid, max_connections
+        );
+        let response = ErrorResponse::new(
+            ErrorCode::TooManyConnections,
+            format!("UID {} has too many open connections to the node monitor", peer_uid),
+        );
+        write_response(reader.get_mut(), &response).await;
+        return;
+    };
+    info!("Accepted new connection from UID {}", peer_uid);
+    let connection = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
     let mut line = String::new();
+    // 协商出的协议版本; 第一条消息不是 HELLO 时按旧版协议处理
+    let mut version: Option<u32> = None;
+    // 该连接上送来过 METRICS 的任务, 连接断开时据此判断监控进程是否丢失
+    let mut monitored_job: Option<String> = None;
+    // 对端是否在两条请求之间关闭连接; 最后一段数据没有换行符说明请求只发了一半
+    let mut clean_close = false;
+    let mut partial_line = false;
 
     loop {
-        match reader.read_line(&mut line).await {
-            Ok(0) => {
+        match limits::read_request(&mut reader, &mut line, max_message_bytes, idle_timeout).await {
+            Ok(ReadOutcome::Closed) => {
                 info!("Connection closed by peer");
+                clean_close = !partial_line;
+                break;
+            }
+            Ok(ReadOutcome::IdleTimeout) => {
+                info!(
+                    "Closing connection from UID {} after {}s without a complete message",
+                    peer_uid,
+                    idle_timeout.as_secs()
+                );
+                stats.record_idle_timeout();
+                break;
+            }
+            Ok(ReadOutcome::TooLarge) => {
+                warn!(
+                    "Closing connection from UID {}: message exceeds {} bytes",
+                    peer_uid, max_message_bytes
+                );
+                stats.record_oversized_message();
+                let response = ErrorResponse::new(
+                    ErrorCode::MessageTooLarge,
+                    format!("Messages must not exceed {} bytes", max_message_bytes),
+                );
+                write_response(reader.get_mut(), &response).await;
                 break;
             }
-            Ok(_) => {
+            Ok(ReadOutcome::Line) => {
+                partial_line = !line.ends_with('\n');
+                // 超出速率的请求不处理, 答复错误让对端稍后重试; 连续超限时关闭连接.
+                // 旧版 job_helper 不读取 METRICS 的答复, 只在关闭连接前答复错误
+                if let Err(retry_after) = rate_limiter.acquire() {
+                    stats.record_throttled_message();
+                    let exhausted = rate_limiter.exhausted();
+                    let response = ErrorResponse::new(
+                        ErrorCode::RateLimited,
+                        if exhausted {
+                            format!(
+                                "{} messages in a row exceeded the rate limit, closing the connection",
+                                limits::MAX_RATE_VIOLATIONS
+                            )
+                        } else {
+                            format!(
+                                "Rate limit of {} messages per minute exceeded, retry in {:.1}s",
+                                messages_per_minute,
+                                retry_after.as_secs_f64()
+                            )
+                        },
+                    );
+                    if exhausted || version.is_some_and(|v| v > protocol::LEGACY_VERSION) {
+                        write_response(reader.get_mut(), &response).await;
+                    }
+                    if exhausted {
+                        warn!("Closing connection from UID {}: {}", peer_uid, response.message);
+                        break;
+                    }
+                    line.clear();
+                    continue;
+                }
+
                 let trimmed_line = line.trim();
                 if trimmed_line.is_empty() {
                     line.clear();
                     continue;
                 }
 
-                let should_break = match serde_json::from_str::<Message>(trimmed_line) {
-                    Ok(Message::Register(payload)) => {
-                        handle_register(payload, tracker.clone(), &mut reader.get_mut()).await;
-                        false // Continue connection
+                let message = match protocol::parse_message(trimmed_line) {
+                    Ok(m) => m,
+                    Err(response) => {
+                        error!("Failed to parse message: {}. Raw: '{}'", response.message, trimmed_line);
+                        stats.record_parse_error();
+                        write_response(reader.get_mut(), &response).await;
+                        line.clear();
+                        continue; // Continue, wait for next message
                     }
-                    Ok(Message::Metrics(payload)) => {
-                        if let Some((job_id, reason)) = handle_metrics(payload, tracker.clone()).await {
-                            kill_slurm_job(&job_id, &reason).await;
-                            info!("Killed job {} and closing its connection.", job_id);
-                            true // Break connection
-                        } else {
-                            false // Continue connection
+                };
+
+                let message = match message {
+                    Message::Hello(hello) => {
+                        let result = handle_hello(&hello, version, &*config.read().await);
+                        write_result(reader.get_mut(), &result).await;
+                        match result {
+                            Ok(response) => {
+                                info!("Negotiated protocol version {} with UID {}", response.version, peer_uid);
+                                version = Some(response.version);
+                            }
+                            Err(response) => {
+                                warn!("Rejected HELLO from UID {}: {}", peer_uid, response.message);
+                                if response.code == ErrorCode::UnsupportedVersion {
+                                    break; // Close connection, the peer cannot talk to us
+                                }
+                            }
+                        }
+                        line.clear();
+                        continue;
+                    }
+                    message => message,
+                };
+                let negotiated = *version.get_or_insert(protocol::LEGACY_VERSION);
+                // 旧版 job_helper 不读取 METRICS 的答复, 只在关闭连接前答复错误
+                let reply = protocol::has_reply(&message, negotiated);
+                let job_id = message.job_id().unwrap_or_default();
+
+                let owner_uid = match authorize_peer(peer_uid, job_id, &tracker, &config, &owners).await {
+                    Ok(uid) => uid,
+                    Err(response) => {
+                        warn!(
+                            "Rejected request for job {} from UID {}: {}",
+                            job_id, peer_uid, response.message
+                        );
+                        write_response(reader.get_mut(), &response).await;
+                        break; // Close connection of unauthorized peers
+                    }
+                };
+
+                let stream = reader.get_mut();
+                let should_break = match message {
+                    Message::Hello(_) => unreachable!("HELLO is answered before authorization"),
+                    Message::Register(payload) => {
+                        let result = handle_register(
+                            payload,
+                            owner_uid,
+                            tracker.clone(),
+                            config.clone(),
+                            &actions,
+                            &supervisor,
+                        )
+                        .await;
+                        write_result(stream, &result).await;
+                        result.is_err() // Break connection if registration was refused
+                    }
+                    Message::Metrics(payload) => {
+                        let job_id = payload.job_id.clone();
+                        match handle_metrics(payload, Some(connection), tracker.clone(), config.clone()).await {
+                            Err(e) => {
+                                error!("Failed to record metrics of job {}: {:#}", job_id, e);
+                                if reply {
+                                    write_response(stream, &internal_error(e)).await;
+                                }
+                                false // Continue connection
+                            }
+                            Ok(MetricsOutcome::Accepted) => {
+                                monitored_job = Some(job_id);
+                                if reply {
+                                    write_response(stream, &AckResponse::ok()).await;
+                                }
+                                false // Continue connection
+                            }
+                            Ok(MetricsOutcome::UnknownJob) => {
+                                let message = format!("Job {} is not registered with the node monitor", job_id);
+                                if reply {
+                                    write_response(stream, &ErrorResponse::new(ErrorCode::UnknownJob, message)).await;
+                                } else {
+                                    warn!("{}", message);
+                                }
+                                false // Continue connection
+                            }
+                            Ok(MetricsOutcome::Kill(job_id, kind, reason)) => {
+                                stats.record_kill(kind);
+                                actions.enqueue_kill(&job_id, &reason).await;
+                                write_response(stream, &ErrorResponse::new(ErrorCode::JobCancelled, reason)).await;
+                                info!("Queued kill of job {} and closing its connection.", job_id);
+                                true // Break connection
+                            }
                         }
                     }
-                    Ok(Message::Cancel(payload)) => {
-                        handle_cancel(payload, tracker.clone()).await;
+                    Message::Cancel(payload) => {
+                        let result = handle_cancel(payload, tracker.clone()).await;
+                        write_result(stream, &result).await;
                         true // Break connection after cancel
                     }
-                    Err(e) => {
-                        error!("Failed to parse message: {}. Raw: '{}'", e, trimmed_line);
-                        false // Continue, wait for next message
+                    Message::Status(payload) => {
+                        let result = handle_status(payload, peer_uid, tracker.clone(), config.clone()).await;
+                        write_result(stream, &result).await;
+                        false // Continue connection
                     }
                 };
 
@@ -216,59 +699,222 @@ async fn handle_connection(stream: UnixStream, tracker: SharedTracker) {
             }
         }
     }
+    if let Some(job_id) = monitored_job {
+        let outcome = monitor_disconnected(&job_id, connection, clean_close, &tracker, &config, &stats).await;
+        apply_expiry(&job_id, outcome, &actions, &stats, &supervisor).await;
+    }
     info!("Connection handler finished.");
 }
 
-async fn handle_register(payload: RegisterPayload, tracker: SharedTracker, stream: &mut UnixStream) {
-    let job_id = payload.job_id;
-    info!(
-        "Registering job {} with GPU-Count: {}, CPU-Count: {}",
-        job_id, payload.gpu_monitor_count, payload.cpu_monitor_count
-    );
+// HELLO 只能是连接上的第一条消息. 答复中说明 job_helper monitor 由谁启动, 供 task_prolog.sh 判断
+fn handle_hello(hello: &HelloPayload, version: Option<u32>, config: &Config) -> Result<HelloResponse, ErrorResponse> {
+    if version.is_some() {
+        return Err(ErrorResponse::new(
+            ErrorCode::InvalidRequest,
+            "HELLO must be the first message on a connection",
+        ));
+    }
+    protocol::negotiate(hello).map(|version| HelloResponse {
+        daemon_sampling: config.sampling_mode == SamplingMode::Daemon,
+        supervise_monitors: config.supervise_monitors,
+        ..HelloResponse::new(version)
+    })
+}
 
-    if let Some(parent) = payload.log_path.parent() {
-        if let Err(e) = fs::create_dir_all(parent).await {
-            error!("Failed to create log directory for job {}: {}", job_id, e);
-            return;
+// 确认对端是任务所有者 (或 root/SlurmUser), 返回任务所有者的 UID
+async fn authorize_peer(
+    peer_uid: u32,
+    job_id: &str,
+    tracker: &SharedTracker,
+    config: &SharedConfig,
+    owners: &SharedOwnerResolver,
+) -> Result<u32, ErrorResponse> {
+    auth::validate_job_id(job_id).map_err(|e| ErrorResponse::new(ErrorCode::InvalidRequest, format!("{:#}", e)))?;
+
+    let tracked_owner = {
+        let job_id = job_id.to_string();
+        tracker
+            .with(move |tracker| tracker.jobs.get(&job_id).map(|job| job.owner_uid))
+            .await
+            .map_err(internal_error)?
+    };
+    let owner_uid = match tracked_owner {
+        Some(uid) => uid,
+        None => {
+            let owners = owners.clone();
+            let job_id = job_id.to_string();
+            tokio::task::spawn_blocking(move || owners.job_owner(&job_id))
+                .await
+                .context("Job owner lookup task panicked")
+                .and_then(|owner| owner)
+                .map_err(internal_error)?
         }
+    };
+
+    if owner_uid != peer_uid && !auth::is_privileged(peer_uid, &config.read().await.slurm_user) {
+        return Err(ErrorResponse::new(
+            ErrorCode::Unauthorized,
+            format!("UID {} does not own job {} (owner UID {})", peer_uid, job_id, owner_uid),
+        ));
+    }
+    Ok(owner_uid)
+}
+
+async fn handle_register(
+    payload: RegisterPayload,
+    owner_uid: u32,
+    tracker: SharedTracker,
+    config: SharedConfig,
+    actions: &SharedActionQueue,
+    supervisor: &SharedSupervisor,
+) -> Result<RegisterResponse, ErrorResponse> {
+    let job_id = payload.job_id.clone();
+    // 被取消的任务上的 job_helper 重连时不能让任务重新被跟踪
+    if actions.has_kill(&job_id).await {
+        warn!("Rejecting registration of job {}, which was already cancelled.", job_id);
+        return Err(ErrorResponse::new(
+            ErrorCode::JobCancelled,
+            format!("Job {} was cancelled by the node monitor", job_id),
+        ));
     }
-    if OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&payload.log_path)
+
+    let log_path = payload.log_path.clone();
+    let registration = register_job(payload, owner_uid, MetricsSource::Client, &tracker, &config)
         .await
-        .is_err()
-    {
-        error!("Failed to open or create log file for job {}", job_id);
-        return;
+        .map_err(|e| {
+            ErrorResponse::new(
+                ErrorCode::LogFileUnavailable,
+                format!("Cannot use the log file of job {}: {:#}", job_id, e),
+            )
+        })?;
+    // 由守护进程在任务的 cgroup 中启动 job_helper monitor, task_prolog.sh 不再自行启动
+    if registration.created && config.read().await.supervise_monitors {
+        supervisor.start(&job_id, owner_uid, &log_path);
+    }
+    Ok(RegisterResponse {
+        status: "ok".to_string(),
+        gpu_window_secs: registration.gpu_window.map(|w| w.as_secs()),
+        cpu_window_secs: registration.cpu_window.map(|w| w.as_secs()),
+    })
+}
+
+// register_job 的结果
+struct Registration {
+    gpu_window: Option<Duration>,
+    cpu_window: Option<Duration>,
+    // 是否是第一次注册 (重复注册时为 false)
+    created: bool,
+}
+
+// 按策略计算监控窗口并开始跟踪任务; 无法以所有者身份创建任务日志时返回原因.
+// 重复注册 (job_helper 重连, 或守护进程从快照恢复后) 只刷新心跳, 保留已有的窗口和警告状态
+async fn register_job(
+    payload: RegisterPayload,
+    owner_uid: u32,
+    source: MetricsSource,
+    tracker: &SharedTracker,
+    config: &SharedConfig,
+) -> Result<Registration> {
+    let job_id = payload.job_id.clone();
+    let existing = tracker
+        .with(move |tracker| {
+            let job = tracker.jobs.get_mut(&job_id)?;
+            info!("Job {} is already registered, keeping its existing state.", job_id);
+            job.last_heartbeat = Instant::now();
+            job.last_heartbeat_at = Utc::now();
+            Some((
+                job.gpu_utilizations.as_ref().map(SlidingWindow::length),
+                job.cpu_utilizations.as_ref().map(SlidingWindow::length),
+            ))
+        })
+        .await?;
+    if let Some((gpu_window, cpu_window)) = existing {
+        return Ok(Registration {
+            gpu_window,
+            cpu_window,
+            created: false,
+        });
+    }
+
+    let (gpu_window, cpu_window, log_roots, heartbeat_timeout) = {
+        let config = config.read().await;
+        let (gpu_window, cpu_window) = config.policy.monitor_windows(&payload);
+        (
+            gpu_window,
+            cpu_window,
+            config.job_log_roots.clone(),
+            config.heartbeat_timeout,
+        )
+    };
+    let job_id = payload.job_id;
+    info!(
+        "Registering job {} ({:?}, Partition: {}, QOS: {}, Account: {}, GPUs: {:?}, CPUs: {}) with GPU window: {:?}, CPU window: {:?}",
+        job_id,
+        source,
+        payload.partition,
+        payload.qos,
+        payload.account,
+        payload.gpus.iter().map(|g| g.name.as_str()).collect::<Vec<_>>(),
+        payload.allocated_cpus,
+        gpu_window,
+        cpu_window
+    );
+
+    if let Err(e) = joblog::create(owner_uid, &payload.log_path, &log_roots).await {
+        error!("Failed to create the log file of job {}: {:#}", job_id, e);
+        return Err(e);
     }
 
+    let now = Utc::now();
     let job_info = JobInfo {
         last_heartbeat: Instant::now(),
-        gpu_monitor_count: payload.gpu_monitor_count,
-        cpu_monitor_count: payload.cpu_monitor_count,
-        gpu_utilizations: VecDeque::with_capacity(payload.gpu_monitor_count),
-        gpu_memory_utilizations: VecDeque::with_capacity(payload.gpu_monitor_count),
-        cpu_utilizations: VecDeque::with_capacity(payload.cpu_monitor_count),
+        last_heartbeat_at: now,
+        registered_at: now,
+        owner_uid,
+        partition: payload.partition,
+        qos: payload.qos,
+        account: payload.account,
+        allocated_cpus: payload.allocated_cpus,
+        gpu_utilizations: gpu_window.map(SlidingWindow::new),
+        gpu_memory_utilizations: gpu_window.map(SlidingWindow::new),
+        cpu_utilizations: cpu_window.map(SlidingWindow::new),
+        gpu_device_utilizations: BTreeMap::new(),
+        idle_gpus_warned: false,
         metrics_received: 0,
+        last_sample_at: None,
+        last_metrics: None,
         log_path: payload.log_path,
+        escalation: Escalation::Active,
+        exemption: None,
+        source,
+        monitor_lost: None,
+        monitor_connection: None,
+        supervised_monitor: None,
     };
 
-    tracker.lock().await.jobs.insert(job_id.clone(), job_info);
-
-    if let Err(e) = stream.write_all(b"{\"status\": \"ok\"}\n").await {
-        error!("Error writing OK status to client for job {}: {}", job_id, e);
-    }
+    tracker
+        .with(move |tracker| tracker.insert_job(job_id, job_info, heartbeat_timeout))
+        .await?;
+    Ok(Registration {
+        gpu_window,
+        cpu_window,
+        created: true,
+    })
 }
 
-async fn handle_cancel(payload: CancelPayload, tracker: SharedTracker) {
+async fn handle_cancel(payload: CancelPayload, tracker: SharedTracker) -> Result<CancelResponse, ErrorResponse> {
     let job_id = payload.job_id;
     info!("Received cancellation request for job {}", &job_id);
-    let mut tracker_lock = tracker.lock().await;
-
-    if let Some(removed_job) = tracker_lock.remove_job(&job_id) {
+    let removed_job = {
+        let job_id = job_id.clone();
+        tracker
+            .with(move |tracker| tracker.remove_job(&job_id))
+            .await
+            .map_err(internal_error)?
+    };
+    if let Some(removed_job) = &removed_job {
         let reason = "Job cancelled by user request";
-        log_to_job_file(&removed_job.log_path, reason).await;
+        log_to_job_file(removed_job.owner_uid, &removed_job.log_path, reason).await;
         info!("Successfully cancelled and removed job {}.", &job_id);
     } else {
         warn!(
@@ -276,197 +922,986 @@ async fn handle_cancel(payload: CancelPayload, tracker: SharedTracker) {
             &job_id
         );
     }
+    Ok(CancelResponse {
+        status: "ok".to_string(),
+        removed: removed_job.is_some(),
+    })
 }
 
-async fn handle_metrics(payload: MetricsPayload, tracker: SharedTracker) -> Option<(String, String)> {
+// 任务状态只告诉任务所有者和 root/SlurmUser; 读取任务时按跟踪记录中的所有者再检查一次
+async fn handle_status(
+    payload: StatusPayload,
+    peer_uid: u32,
+    tracker: SharedTracker,
+    config: SharedConfig,
+) -> Result<protocol::StatusResponse, ErrorResponse> {
     let job_id = payload.job_id;
-    let mut tracker_lock = tracker.lock().await;
+    let config = config.read().await.clone();
+    let privileged = auth::is_privileged(peer_uid, &config.slurm_user);
+    tracker
+        .with(move |tracker| {
+            let Some(job) = tracker.jobs.get(&job_id) else {
+                return Err(ErrorResponse::new(
+                    ErrorCode::UnknownJob,
+                    format!("Job {} is not registered with the node monitor", job_id),
+                ));
+            };
+            if job.owner_uid != peer_uid && !privileged {
+                return Err(ErrorResponse::new(
+                    ErrorCode::Unauthorized,
+                    format!(
+                        "UID {} does not own job {} (owner UID {})",
+                        peer_uid, job_id, job.owner_uid
+                    ),
+                ));
+            }
+            Ok(status::job_status(&job_id, job, &config, tracker.enforcement_paused))
+        })
+        .await
+        .map_err(internal_error)?
+}
+
+// 序列化为一行 JSON 写回客户端
+async fn write_response<T: Serialize>(stream: &mut UnixStream, response: &T) {
+    let mut response_bytes = serde_json::to_vec(response).unwrap_or_else(|e| {
+        error!("Failed to serialize response: {}", e);
+        let fallback = ErrorResponse::new(ErrorCode::Internal, "Failed to serialize response");
+        serde_json::to_vec(&fallback).unwrap_or_default()
+    });
+    response_bytes.push(b'\n');
+    if let Err(e) = stream.write_all(&response_bytes).await {
+        error!("Error writing response to client: {}", e);
+    }
+}
 
-    let job = if let Some(j) = tracker_lock.jobs.get_mut(&job_id) {
+fn internal_error(e: anyhow::Error) -> ErrorResponse {
+    ErrorResponse::new(ErrorCode::Internal, format!("{:#}", e))
+}
+
+async fn write_result<T: Serialize>(stream: &mut UnixStream, result: &Result<T, ErrorResponse>) {
+    match result {
+        Ok(response) => write_response(stream, response).await,
+        Err(response) => write_response(stream, response).await,
+    }
+}
+
+// connection 为送来 METRICS 的客户端连接, 守护进程自行采样时为 None
+async fn handle_metrics(
+    payload: MetricsPayload,
+    connection: Option<u64>,
+    tracker: SharedTracker,
+    config: SharedConfig,
+) -> Result<MetricsOutcome> {
+    let config = config.read().await.clone();
+    let (outcome, effects) = tracker
+        .with(move |tracker| {
+            let mut effects = Vec::new();
+            if let Some(connection) = connection {
+                monitor_connected(tracker, &payload.job_id, connection, &config, &mut effects);
+            }
+            let outcome = apply_metrics(tracker, payload, &config, &mut effects);
+            (outcome, effects)
+        })
+        .await?;
+    run_effects(effects).await;
+    Ok(outcome)
+}
+
+// 在状态任务中更新任务的窗口并判定是否空闲; 需要写的日志和记录放入 effects
+fn apply_metrics(
+    tracker: &mut JobTracker,
+    payload: MetricsPayload,
+    config: &Config,
+    effects: &mut Vec<JobEffect>,
+) -> MetricsOutcome {
+    let job_id = payload.job_id;
+    let enforcement_paused = tracker.enforcement_paused;
+
+    let job = if let Some(j) = tracker.jobs.get_mut(&job_id) {
         j
     } else {
         warn!("Received metrics for unknown or already removed job: {}", job_id);
-        return None;
+        return MetricsOutcome::UnknownJob;
     };
 
+    let now = Utc::now();
     job.last_heartbeat = Instant::now();
+    job.last_heartbeat_at = now;
     job.metrics_received += 1;
+    job.last_metrics = Some((
+        payload.gpu_utilization,
+        payload.gpu_memory_utilization,
+        payload.cpu_utilization,
+    ));
     info!(
         "Metrics received: JobID={}, CPU={:.1}%, GPU_Util={:.1}%, GPU_Mem={:.1}%",
         job_id, payload.cpu_utilization, payload.gpu_utilization, payload.gpu_memory_utilization
     );
 
-    if job.metrics_received <= BUFFER_PERIOD {
+    // 时钟不一致时不接受来自未来的采样时间
+    let sampled_at = payload.sampled_at.map_or(now, |at| at.min(now));
+    if job.last_sample_at.is_some_and(|last| sampled_at <= last) {
+        warn!(
+            "Discarding out-of-order sample for job {} (Sampled at: {}, Last sample: {:?})",
+            job_id, sampled_at, job.last_sample_at
+        );
+        return MetricsOutcome::Accepted;
+    }
+    job.last_sample_at = Some(sampled_at);
+
+    let buffer_end = job.registered_at + config.buffer_period;
+    if sampled_at < buffer_end {
         info!(
-            "Discarding metrics during buffer period for job {} (Received: {}, Buffer: {})",
-            job_id, job.metrics_received, BUFFER_PERIOD
+            "Discarding metrics during buffer period for job {} (Buffer ends at: {})",
+            job_id, buffer_end
         );
-        return None;
+        return MetricsOutcome::Accepted;
     }
 
-    let mut reason: Option<String> = None;
+    let mut reason: Option<(KillReason, String)> = None;
+    // 无法判断 GPU 进程归属的样本不计入 GPU 窗口, 连续缺失超过 max_sample_gap 后窗口重新积累
+    let gpu_known = !payload.gpu_unknown;
 
-    if job.gpu_monitor_count > 0 && reason.is_none() {
-        job.gpu_utilizations.push_back(payload.gpu_utilization);
-        if job.gpu_utilizations.len() > job.gpu_monitor_count {
-            job.gpu_utilizations.pop_front();
-        }
-        if job.gpu_utilizations.len() == job.gpu_monitor_count {
-            let max_val = calculate_max(&job.gpu_utilizations);
+    if let Some(window) = job.gpu_utilizations.as_mut().filter(|_| gpu_known && reason.is_none()) {
+        window.push(sampled_at, payload.gpu_utilization, config.max_sample_gap);
+        if let Some(max_val) = window.max().filter(|_| window.is_complete()) {
             info!("Job {}, Max GPU Utilization: {:.2}%", job_id, max_val);
-            if max_val < GPU_UTILIZATION_THRESHOLD {
-                reason = Some(format!(
-                    "Max GPU utilization {:.2}% is below threshold {:.0}%",
-                    max_val, GPU_UTILIZATION_THRESHOLD
+            if max_val < config.gpu_utilization_threshold {
+                reason = Some((
+                    KillReason::GpuIdle,
+                    format!(
+                        "Max GPU utilization {:.2}% is below threshold {:.0}%",
+                        max_val, config.gpu_utilization_threshold
+                    ),
                 ));
             }
         }
     }
 
-    if job.gpu_monitor_count > 0 && reason.is_none() {
-        job.gpu_memory_utilizations.push_back(payload.gpu_memory_utilization);
-        if job.gpu_memory_utilizations.len() > job.gpu_monitor_count {
-            job.gpu_memory_utilizations.pop_front();
-        }
-        if job.gpu_memory_utilizations.len() == job.gpu_monitor_count {
-            let max_val = calculate_max(&job.gpu_memory_utilizations);
+    if let Some(window) = job
+        .gpu_memory_utilizations
+        .as_mut()
+        .filter(|_| gpu_known && reason.is_none())
+    {
+        window.push(sampled_at, payload.gpu_memory_utilization, config.max_sample_gap);
+        if let Some(max_val) = window.max().filter(|_| window.is_complete()) {
             info!("Job {}, Max GPU Memory Utilization: {:.2}%", job_id, max_val);
-            if max_val < GPU_MEMORY_UTILIZATION_THRESHOLD {
-                reason = Some(format!(
-                    "Max GPU Memory utilization {:.2}% is below threshold {:.0}%",
-                    max_val, GPU_MEMORY_UTILIZATION_THRESHOLD
+            if max_val < config.gpu_memory_utilization_threshold {
+                reason = Some((
+                    KillReason::GpuMemoryIdle,
+                    format!(
+                        "Max GPU Memory utilization {:.2}% is below threshold {:.0}%",
+                        max_val, config.gpu_memory_utilization_threshold
+                    ),
                 ));
             }
         }
     }
 
-    if job.cpu_monitor_count > 0 && reason.is_none() {
-        job.cpu_utilizations.push_back(payload.cpu_utilization);
-        if job.cpu_utilizations.len() > job.cpu_monitor_count {
-            job.cpu_utilizations.pop_front();
-        }
-        if job.cpu_utilizations.len() == job.cpu_monitor_count {
-            let max_val = calculate_max(&job.cpu_utilizations);
+    if let Some(window) = job.cpu_utilizations.as_mut().filter(|_| reason.is_none()) {
+        window.push(sampled_at, payload.cpu_utilization, config.max_sample_gap);
+        if let Some(max_val) = window.max().filter(|_| window.is_complete()) {
             info!("Job {}, Max CPU Utilization: {:.2}%", job_id, max_val);
-            if max_val < CPU_UTILIZATION_THRESHOLD {
-                reason = Some(format!(
-                    "Max CPU utilization {:.2}% is below threshold {:.0}%",
-                    max_val, CPU_UTILIZATION_THRESHOLD
+            if max_val < config.cpu_utilization_threshold {
+                reason = Some((
+                    KillReason::CpuIdle,
+                    format!(
+                        "Max CPU utilization {:.2}% is below threshold {:.0}%",
+                        max_val, config.cpu_utilization_threshold
+                    ),
                 ));
             }
         }
     }
 
-    if let Some(r) = reason {
-        if let Some(removed_job) = tracker_lock.remove_job(&job_id) {
-            log_to_job_file(
-                &removed_job.log_path,
-                &format!("Removing job {}. Reason: {}", job_id, r),
-            )
-            .await;
+    // 逐卡窗口: 多卡任务只用其中几张卡时, 平均利用率仍可能高于阈值
+    if let Some(length) = job.gpu_utilizations.as_ref().map(SlidingWindow::length) {
+        for gpu in &payload.gpus {
+            let devices = &mut job.gpu_device_utilizations;
+            if !devices.contains_key(&gpu.uuid) && devices.len() >= MAX_TRACKED_GPUS {
+                continue;
+            }
+            devices
+                .entry(gpu.uuid.clone())
+                .or_insert_with(|| SlidingWindow::new(length))
+                .push(sampled_at, gpu.utilization, config.max_sample_gap);
+        }
+    }
+
+    let idle_gpu_policy = &config.policy.idle_gpus;
+    let mut idle_gpus_warning = None;
+    if idle_gpu_policy.min_idle_gpus > 0 && reason.is_none() {
+        let idle_gpus = job.idle_gpus(config.gpu_utilization_threshold);
+        if idle_gpus >= idle_gpu_policy.min_idle_gpus {
+            let r = format!(
+                "{} of {} GPUs stayed below {:.0}% utilization for the whole window",
+                idle_gpus,
+                job.gpu_device_utilizations.len(),
+                config.gpu_utilization_threshold
+            );
+            info!("Job {}, {}", job_id, r);
+            match idle_gpu_policy.action {
+                IdleGpuAction::Cancel => reason = Some((KillReason::IdleGpus, r)),
+                IdleGpuAction::Warn => {
+                    if !job.idle_gpus_warned && !enforcement_paused && !job.is_exempt(now) {
+                        job.idle_gpus_warned = true;
+                        idle_gpus_warning = Some(r);
+                    }
+                }
+            }
+        } else {
+            job.idle_gpus_warned = false;
+        }
+    }
+
+    let Some((kind, r)) = reason else {
+        let was_warned = job.escalation.reset();
+        let (owner_uid, log_path) = (job.owner_uid, job.log_path.clone());
+        if was_warned || idle_gpus_warning.is_some() {
+            tracker.mark_changed();
+        }
+        if was_warned {
+            let message = format!("Job {} is active again, idle warnings cleared.", job_id);
+            info!("{}", message);
+            effects.push(JobEffect::Log {
+                owner_uid,
+                log_path: log_path.clone(),
+                message,
+            });
+        }
+        if let Some(r) = idle_gpus_warning {
+            let message = format!(
+                "WARNING: job {} is not using all of its GPUs ({}). Please release the GPUs you do not need.",
+                job_id, r
+            );
+            effects.push(JobEffect::Notify {
+                owner_uid,
+                log_path,
+                job_id,
+                message,
+            });
+        }
+        return MetricsOutcome::Accepted;
+    };
+
+    if enforcement_paused || job.is_exempt(now) {
+        info!(
+            "Job {} is idle ({}), but enforcement is paused or the job is exempt.",
+            job_id, r
+        );
+        // 豁免结束后重新从第一次警告开始
+        if job.escalation.reset() {
+            tracker.mark_changed();
         }
-        return Some((job_id.clone(), r));
+        return MetricsOutcome::Accepted;
     }
 
-    None
+    if config.enforcement_for(&job.partition) == EnforcementMode::DryRun {
+        let record = DryRunRecord::new(&job_id, job, &r);
+        // 清空窗口, 需要重新积满一个完整窗口才会产生下一条记录
+        for window in [
+            &mut job.gpu_utilizations,
+            &mut job.gpu_memory_utilizations,
+            &mut job.cpu_utilizations,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            window.clear();
+        }
+        job.gpu_device_utilizations.clear();
+
+        info!("[DRY-RUN] Would cancel job {}, Reason: {}", job_id, r);
+        effects.push(JobEffect::DryRun {
+            path: config.dry_run_log_path.clone(),
+            record,
+        });
+        return MetricsOutcome::Accepted;
+    }
+
+    let (owner_uid, log_path) = (job.owner_uid, job.log_path.clone());
+    let message = match job
+        .escalation
+        .advance(Utc::now(), config.idle_warning, config.idle_final_warning)
+    {
+        EscalationStep::Wait => return MetricsOutcome::Accepted,
+        EscalationStep::Warn => format!(
+            "WARNING: job {} appears idle ({}). It will get a final warning in {} seconds and be cancelled {} seconds after that unless it becomes active.",
+            job_id,
+            r,
+            config.idle_warning.as_secs(),
+            config.idle_final_warning.as_secs()
+        ),
+        EscalationStep::FinalWarning(deadline) => format!(
+            "FINAL WARNING: job {} is still idle ({}). It will be cancelled at {} (in {} seconds) unless it becomes active.",
+            job_id,
+            r,
+            deadline.with_timezone(&Local).format("%Y-%m-%dT%H:%M:%S"),
+            config.idle_final_warning.as_secs()
+        ),
+        EscalationStep::Cancel => {
+            tracker.remove_job(&job_id);
+            effects.push(JobEffect::Notify {
+                owner_uid,
+                log_path,
+                job_id: job_id.clone(),
+                message: format!("Removing job {}. Reason: {}", job_id, r),
+            });
+            return MetricsOutcome::Kill(job_id, kind, r);
+        }
+    };
+
+    // 警告阶段需要持久化, 守护进程重启后不会从头开始
+    tracker.mark_changed();
+    effects.push(JobEffect::Notify {
+        owner_uid,
+        log_path,
+        job_id,
+        message,
+    });
+    MetricsOutcome::Accepted
 }
 
 // ============================================================================
+
 // 心跳检测 (Heartbeat Check)
 // ============================================================================
 
-async fn run_status_checker(tracker: SharedTracker) {
-    let mut interval = time::interval(HEARTBEAT_CHECK_INTERVAL);
-    loop {
-        interval.tick().await;
+// 状态任务在任务的心跳定时器到期时发来任务号, 取代定期扫描所有任务
+async fn run_heartbeat_checker(
+    mut expired_jobs: mpsc::UnboundedReceiver<String>,
+    tracker: SharedTracker,
+    config: SharedConfig,
+    actions: SharedActionQueue,
+    stats: SharedStats,
+    supervisor: SharedSupervisor,
+) {
+    while let Some(job_id) = expired_jobs.recv().await {
+        // 每次重新读取配置, 使 SIGHUP 后的新超时立即生效
+        let config_snapshot = config.read().await.clone();
+        let expired = {
+            let job_id = job_id.clone();
+            tracker
+                .with(move |tracker| {
+                    let mut effects = Vec::new();
+                    let outcome = expire_heartbeat(tracker, &job_id, &config_snapshot, &mut effects);
+                    (outcome, effects)
+                })
+                .await
+        };
+        let (outcome, effects) = match expired {
+            Ok(expired) => expired,
+            Err(e) => {
+                error!("Failed to check the heartbeat of job {}: {:#}", job_id, e);
+                continue;
+            }
+        };
+        run_effects(effects).await;
+        apply_expiry(&job_id, outcome, &actions, &stats, &supervisor).await;
+    }
+}
 
-        let mut jobs_to_kill = Vec::new();
-        let mut tracker_lock = tracker.lock().await;
+// 定时器期间收到过心跳时按最后一次心跳顺延, 否则停止跟踪该任务.
+// monitor lost 状态下定时器是等待重新连接的截止时间, 到期说明监控进程没有回来
+fn expire_heartbeat(
+    tracker: &mut JobTracker,
+    job_id: &str,
+    config: &Config,
+    effects: &mut Vec<JobEffect>,
+) -> ExpiryOutcome {
+    let Some(job) = tracker.jobs.get(job_id) else {
+        return ExpiryOutcome::Alive;
+    };
+    let deadline = job.expires_at(config.heartbeat_timeout);
+    if deadline > Instant::now() {
+        tracker.rearm_heartbeat(job_id, deadline);
+        return ExpiryOutcome::Alive;
+    }
 
-        tracker_lock.jobs.retain(|job_id, job| {
-            if job.last_heartbeat.elapsed() > HEARTBEAT_TIMEOUT {
-                let reason = format!(
-                    "Heartbeat Timeout. Last heartbeat was {:.0} seconds ago.",
-                    job.last_heartbeat.elapsed().as_secs_f64()
-                );
-                jobs_to_kill.push((job_id.clone(), reason, job.log_path.clone()));
-                return false; // Remove from map
-            }
-            true // Keep in map
+    let (kind, reason) = match job.monitor_lost {
+        Some(lost) => (
+            KillReason::MonitorLost,
+            format!(
+                "Monitor Lost. The job_helper monitor {} at {} and did not reconnect.",
+                describe_close(lost.clean),
+                lost.since.with_timezone(&Local).format("%Y-%m-%dT%H:%M:%S")
+            ),
+        ),
+        None => (
+            KillReason::HeartbeatTimeout,
+            format!(
+                "Heartbeat Timeout. Last heartbeat was {:.0} seconds ago.",
+                job.last_heartbeat.elapsed().as_secs_f64()
+            ),
+        ),
+    };
+    stop_tracking(tracker, job_id, kind, reason, config, effects)
+}
+
+// 停止跟踪不再上报的任务; 暂停或豁免时只停止跟踪, 试运行模式下只记录不取消
+fn stop_tracking(
+    tracker: &mut JobTracker,
+    job_id: &str,
+    kind: KillReason,
+    reason: String,
+    config: &Config,
+    effects: &mut Vec<JobEffect>,
+) -> ExpiryOutcome {
+    let enforcement_paused = tracker.enforcement_paused;
+    let Some(job) = tracker.jobs.get(job_id) else {
+        return ExpiryOutcome::Alive;
+    };
+    let outcome = if enforcement_paused || job.is_exempt(Utc::now()) {
+        info!(
+            "{} Not cancelling job {}: enforcement is paused or the job is exempt.",
+            reason, job_id
+        );
+        ExpiryOutcome::Untracked(kind)
+    } else if config.enforcement_for(&job.partition) == EnforcementMode::DryRun {
+        info!("[DRY-RUN] Would cancel job {}, Reason: {}", job_id, reason);
+        effects.push(JobEffect::DryRun {
+            path: config.dry_run_log_path.clone(),
+            record: DryRunRecord::new(job_id, job, &reason),
         });
+        ExpiryOutcome::Untracked(kind)
+    } else {
+        info!("Job {} is no longer monitored, queueing a kill. {}", job_id, reason);
+        effects.push(JobEffect::Log {
+            owner_uid: job.owner_uid,
+            log_path: job.log_path.clone(),
+            message: reason.clone(),
+        });
+        ExpiryOutcome::Kill(kind, reason)
+    };
+    tracker.remove_job(job_id);
+    outcome
+}
 
-        for (_, reason, log_path) in &jobs_to_kill {
-            log_to_job_file(log_path, reason).await;
+// 在状态任务之外执行判定结果: 取消任务或重新启动监控进程
+async fn apply_expiry(
+    job_id: &str,
+    outcome: ExpiryOutcome,
+    actions: &SharedActionQueue,
+    stats: &SharedStats,
+    supervisor: &SharedSupervisor,
+) {
+    match outcome {
+        ExpiryOutcome::Alive => {}
+        ExpiryOutcome::Untracked(kind) => {
+            if kind == KillReason::HeartbeatTimeout {
+                stats.record_heartbeat_timeout();
+            }
         }
-        drop(tracker_lock);
-
-        if !jobs_to_kill.is_empty() {
-            info!("Found {} jobs to kill due to timeout.", jobs_to_kill.len());
-            for (job_id, reason, _) in jobs_to_kill {
-                kill_slurm_job(&job_id, &reason).await;
+        ExpiryOutcome::Kill(kind, reason) => {
+            if kind == KillReason::HeartbeatTimeout {
+                stats.record_heartbeat_timeout();
             }
+            stats.record_kill(kind);
+            actions.enqueue_kill(job_id, &reason).await;
+        }
+        ExpiryOutcome::Respawn { owner_uid, log_path } => {
+            supervisor.respawn(
+                job_id,
+                owner_uid,
+                &log_path,
+                "it lost its connection to the node monitor",
+            );
         }
     }
 }
 
+// ============================================================================
+// 监控进程丢失 (Monitor Lost)
+// ============================================================================
+
+// job_helper monitor 送来 METRICS: 记下它的连接, 之前处于 monitor lost 状态时恢复
+fn monitor_connected(
+    tracker: &mut JobTracker,
+    job_id: &str,
+    connection: u64,
+    config: &Config,
+    effects: &mut Vec<JobEffect>,
+) {
+    let Some(job) = tracker
+        .jobs
+        .get_mut(job_id)
+        .filter(|job| job.source == MetricsSource::Client)
+    else {
+        return;
+    };
+    job.monitor_connection = Some(connection);
+    let Some(lost) = job.monitor_lost.take() else {
+        return;
+    };
+    let message = format!(
+        "The job_helper monitor of job {} reconnected after {} seconds.",
+        job_id,
+        (Utc::now() - lost.since).num_seconds().max(0)
+    );
+    info!("{}", message);
+    effects.push(JobEffect::Log {
+        owner_uid: job.owner_uid,
+        log_path: job.log_path.clone(),
+        message,
+    });
+    // 恢复按心跳超时检测
+    tracker.rearm_heartbeat(job_id, Instant::now() + config.heartbeat_timeout);
+    tracker.mark_changed();
+}
+
+// 任务当前的监控连接断开: 进入 monitor lost 状态, 按 monitor_lost_action 立即取消、等待重新连接或重新启动监控进程.
+// 返回 None 表示该连接已不是任务当前的监控连接 (已被新的连接取代, 或任务已不再跟踪)
+fn lose_monitor(
+    tracker: &mut JobTracker,
+    job_id: &str,
+    connection: u64,
+    clean: bool,
+    config: &Config,
+    effects: &mut Vec<JobEffect>,
+) -> Option<ExpiryOutcome> {
+    let job = tracker.jobs.get_mut(job_id)?;
+    if job.monitor_connection != Some(connection) {
+        return None;
+    }
+    job.monitor_connection = None;
+    if config.monitor_lost_action == MonitorLostAction::Cancel {
+        let reason = format!("Monitor Lost. The job_helper monitor {}.", describe_close(clean));
+        return Some(stop_tracking(
+            tracker,
+            job_id,
+            KillReason::MonitorLost,
+            reason,
+            config,
+            effects,
+        ));
+    }
+
+    let now = Utc::now();
+    let deadline = now + config.monitor_lost_grace;
+    job.monitor_lost = Some(MonitorLost {
+        since: now,
+        clean,
+        deadline,
+    });
+    // 已由守护进程看护的监控进程退出后由看护任务按退避重启, 这里不再另行启动
+    let respawn = job.supervised_monitor.is_none()
+        && (config.monitor_lost_action == MonitorLostAction::Respawn || config.supervise_monitors);
+    let message = format!(
+        "WARNING: the job_helper monitor of job {} {}.{} The job will be cancelled at {} unless the monitor reconnects.",
+        job_id,
+        describe_close(clean),
+        if respawn { " Restarting it." } else { "" },
+        deadline.with_timezone(&Local).format("%Y-%m-%dT%H:%M:%S")
+    );
+    warn!("{}", message);
+    let (owner_uid, log_path) = (job.owner_uid, job.log_path.clone());
+    let expires_at = job.expires_at(config.heartbeat_timeout);
+    effects.push(JobEffect::Log {
+        owner_uid,
+        log_path: log_path.clone(),
+        message,
+    });
+    tracker.rearm_heartbeat(job_id, expires_at);
+    tracker.mark_changed();
+    Some(if respawn {
+        ExpiryOutcome::Respawn { owner_uid, log_path }
+    } else {
+        ExpiryOutcome::Alive
+    })
+}
+
+// 返回需要在状态任务之外执行的判定结果
+async fn monitor_disconnected(
+    job_id: &str,
+    connection: u64,
+    clean: bool,
+    tracker: &SharedTracker,
+    config: &SharedConfig,
+    stats: &SharedStats,
+) -> ExpiryOutcome {
+    let config_snapshot = config.read().await.clone();
+    let lost = {
+        let job_id = job_id.to_string();
+        tracker
+            .with(move |tracker| {
+                let mut effects = Vec::new();
+                let outcome = lose_monitor(tracker, &job_id, connection, clean, &config_snapshot, &mut effects);
+                (outcome, effects)
+            })
+            .await
+    };
+    let (outcome, effects) = match lost {
+        Ok(lost) => lost,
+        Err(e) => {
+            error!(
+                "Failed to handle the lost job_helper monitor of job {}: {:#}",
+                job_id, e
+            );
+            return ExpiryOutcome::Alive;
+        }
+    };
+    run_effects(effects).await;
+    match outcome {
+        Some(outcome) => {
+            stats.record_monitor_lost();
+            outcome
+        }
+        None => ExpiryOutcome::Alive,
+    }
+}
+
+fn describe_close(clean: bool) -> &'static str {
+    if clean {
+        "closed its connection"
+    } else {
+        "lost its connection unexpectedly"
+    }
+}
+
 // ============================================================================
 // 辅助函数 (Helper Functions)
 // ============================================================================
 
-async fn kill_slurm_job(job_id: &str, reason: &str) {
+async fn kill_slurm_job(job_id: &str, reason: &str) -> Result<()> {
     info!("[KILL] Executing 'scancel' for job {}, Reason: {}", job_id, reason);
 
     let mut cmd = tokio::process::Command::new("scancel");
     cmd.arg(job_id);
     cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
 
-    match cmd.spawn() {
-        // **FIX**: Removed `mut` from `child` as it's not needed.
-        Ok(child) => match child.wait_with_output().await {
-            Ok(output) => {
-                if output.status.success() {
-                    info!("Successfully ran scancel for job {}.", job_id);
-                } else {
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    error!(
-                        "'scancel' for job {} failed with status {}: {}",
-                        job_id, output.status, stderr
-                    );
+    let child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to spawn 'scancel' for job {}", job_id))?;
+    let output = child
+        .wait_with_output()
+        .await
+        .with_context(|| format!("Error waiting for 'scancel' command for job {}", job_id))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!(
+            "'scancel' for job {} failed with status {}: {}",
+            job_id, output.status, stderr
+        );
+        bail!("'scancel' failed with status {}: {}", output.status, stderr.trim());
+    }
+    info!("Successfully ran scancel for job {}.", job_id);
+    Ok(())
+}
+
+fn print_actions(queue: &ActionQueue, all: bool) {
+    let mut actions: Vec<_> = queue
+        .actions()
+        .filter(|a| all || a.state != ActionState::Done)
+        .collect();
+    actions.sort_by_key(|a| a.created_at);
+    if actions.is_empty() {
+        println!("No pending or failed actions.");
+        return;
+    }
+    for a in actions {
+        println!(
+            "job={} state={:?} attempts={} created={} next_attempt={} reason=\"{}\" last_error={}",
+            a.job_id,
+            a.state,
+            a.attempts,
+            a.created_at.with_timezone(&Local).format("%Y-%m-%dT%H:%M:%S"),
+            a.next_attempt_at.with_timezone(&Local).format("%Y-%m-%dT%H:%M:%S"),
+            a.reason,
+            a.last_error.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+// 依次执行状态任务返回的 I/O, 同一任务的日志保持先后顺序
+async fn run_effects(effects: Vec<JobEffect>) {
+    for effect in effects {
+        match effect {
+            JobEffect::Log {
+                owner_uid,
+                log_path,
+                message,
+            } => log_to_job_file(owner_uid, &log_path, &message).await,
+            JobEffect::Notify {
+                owner_uid,
+                log_path,
+                job_id,
+                message,
+            } => notify::notify_user(owner_uid, &log_path, &job_id, &message).await,
+            JobEffect::DryRun { path, record } => {
+                if let Err(e) = audit::append_record(&path, &record).await {
+                    error!("Failed to write dry-run record for job {}: {:#}", record.job_id, e);
                 }
             }
-            Err(e) => error!("Error waiting for 'scancel' command for job {}: {}", job_id, e),
-        },
-        Err(e) => error!("Failed to spawn 'scancel' for job {}: {}", job_id, e),
+        }
     }
 }
 
-#[allow(dead_code)]
-fn calculate_average(data: &VecDeque<f64>) -> f64 {
-    if data.is_empty() {
-        return 0.0;
+// 以任务所有者的身份追加任务日志, 失败只记录错误
+async fn log_to_job_file(owner_uid: u32, log_path: &Path, message: &str) {
+    if let Err(e) = joblog::append(owner_uid, log_path, message).await {
+        error!("Failed to write to job log file {:?}: {:#}", log_path, e);
     }
-    let sum: f64 = data.iter().sum();
-    sum / data.len() as f64
 }
 
-fn calculate_max(data: &VecDeque<f64>) -> f64 {
-    if data.is_empty() {
-        return 0.0;
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anyhow::anyhow;
+    use nix::unistd::User;
+
+    use super::*;
+    use crate::auth::JobOwnerResolver;
+
+    const OWNER: u32 = 1500;
+    const OTHER_USER: u32 = 1501;
+
+    // 代替 squeue 的任务归属表
+    struct FakeOwners(HashMap<String, u32>);
+
+    impl JobOwnerResolver for FakeOwners {
+        fn job_owner(&self, job_id: &str) -> Result<u32> {
+            self.0
+                .get(job_id)
+                .copied()
+                .ok_or_else(|| anyhow!("Job {} not found in squeue", job_id))
+        }
     }
-    data.iter().max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)).cloned().unwrap_or(0.0)
-}
 
-async fn log_to_job_file(log_path: &Path, message: &str) {
-    match OpenOptions::new().append(true).create(true).open(log_path).await {
-        Ok(mut file) => {
-            let timestamp = Local::now().format("%Y-%m-%dT%H:%M:%S");
-            let log_line = format!("[{}] JOB-LOG: {}\n", timestamp, message);
-            if let Err(e) = file.write_all(log_line.as_bytes()).await {
-                error!("Failed to write to job log file {:?}: {}", log_path, e);
-            }
+    struct Fixture {
+        tracker: SharedTracker,
+        config: SharedConfig,
+        owners: SharedOwnerResolver,
+    }
+
+    fn fixture(slurm_user: &str) -> Fixture {
+        let (tracker, _) = tracker::spawn(JobTracker::default());
+        let config = Config {
+            slurm_user: slurm_user.to_string(),
+            ..Config::default()
+        };
+        Fixture {
+            tracker,
+            config: Arc::new(RwLock::new(config)),
+            owners: Arc::new(FakeOwners(HashMap::from([("42".to_string(), OWNER)]))),
         }
-        Err(e) => {
-            error!("Failed to open job log file {:?} for writing: {}", log_path, e);
+    }
+
+    // 各种针对任务的请求, 与 job_helper 发送的一致
+    fn job_requests(job_id: &str) -> Vec<Message> {
+        [
+            format!(
+                r#"{{"type": "REGISTER", "payload": {{"job_id": "{}", "log_path": "/home/alice/.slurm/info-{}.log", "partition": "gpu", "qos": "normal", "account": "lab", "gpus": [], "allocated_cpus": 4}}}}"#,
+                job_id, job_id
+            ),
+            format!(
+                r#"{{"type": "METRICS", "payload": {{"job_id": "{}", "gpu_utilization": 0.0, "gpu_memory_utilization": 0.0, "cpu_utilization": 1.5}}}}"#,
+                job_id
+            ),
+            format!(r#"{{"type": "CANCEL", "payload": {{"job_id": "{}"}}}}"#, job_id),
+            format!(r#"{{"type": "STATUS", "payload": {{"job_id": "{}"}}}}"#, job_id),
+        ]
+        .iter()
+        .map(|line| protocol::parse_message(line).unwrap_or_else(|e| panic!("{} rejected: {}", line, e.message)))
+        .collect()
+    }
+
+    async fn authorize(fixture: &Fixture, peer_uid: u32, message: &Message) -> Result<u32, ErrorResponse> {
+        let job_id = message.job_id().unwrap_or_default();
+        authorize_peer(peer_uid, job_id, &fixture.tracker, &fixture.config, &fixture.owners).await
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_from_other_users() {
+        let fixture = fixture("slurm");
+        for message in job_requests("42") {
+            let rejection = authorize(&fixture, OTHER_USER, &message).await.unwrap_err();
+            assert_eq!(rejection.code, ErrorCode::Unauthorized, "{:?}", message);
+        }
+    }
+
+    #[tokio::test]
+    async fn accepts_requests_from_the_owner() {
+        let fixture = fixture("slurm");
+        for message in job_requests("42") {
+            assert_eq!(authorize(&fixture, OWNER, &message).await, Ok(OWNER), "{:?}", message);
         }
     }
+
+    #[tokio::test]
+    async fn accepts_requests_from_root_and_the_slurm_user() {
+        let nobody = User::from_name("nobody")
+            .unwrap()
+            .expect("no 'nobody' user")
+            .uid
+            .as_raw();
+        let fixture = fixture("nobody");
+        for message in job_requests("42") {
+            assert_eq!(authorize(&fixture, 0, &message).await, Ok(OWNER), "{:?}", message);
+            assert_eq!(authorize(&fixture, nobody, &message).await, Ok(OWNER), "{:?}", message);
+        }
+    }
+
+    // 由 job_helper monitor 上报的任务
+    fn client_job(job_id: &str, owner_uid: u32) -> JobInfo {
+        serde_json::from_value(serde_json::json!({
+            "last_heartbeat_at": Utc::now(),
+            "registered_at": Utc::now(),
+            "owner_uid": owner_uid,
+            "metrics_received": 0,
+            "log_path": format!("/home/alice/.slurm/info-{}.log", job_id),
+        }))
+        .unwrap()
+    }
+
+    // 直接放入跟踪表的任务, 不经过 REGISTER (不需要创建任务日志)
+    async fn track_job(fixture: &Fixture, job_id: &str, owner_uid: u32) {
+        let job = client_job(job_id, owner_uid);
+        let job_id = job_id.to_string();
+        fixture
+            .tracker
+            .with(move |tracker| tracker.insert_job(job_id, job, Duration::from_secs(60)))
+            .await
+            .unwrap();
+    }
+
+    async fn status_of(
+        fixture: &Fixture,
+        peer_uid: u32,
+        job_id: &str,
+    ) -> Result<protocol::StatusResponse, ErrorResponse> {
+        let payload = StatusPayload {
+            job_id: job_id.to_string(),
+        };
+        handle_status(payload, peer_uid, fixture.tracker.clone(), fixture.config.clone()).await
+    }
+
+    #[tokio::test]
+    async fn status_is_only_reported_to_the_owner_and_privileged_users() {
+        let nobody = User::from_name("nobody")
+            .unwrap()
+            .expect("no 'nobody' user")
+            .uid
+            .as_raw();
+        let fixture = fixture("nobody");
+        track_job(&fixture, "42", OWNER).await;
+
+        let rejection = status_of(&fixture, OTHER_USER, "42").await.unwrap_err();
+        assert_eq!(rejection.code, ErrorCode::Unauthorized);
+        for peer_uid in [OWNER, 0, nobody] {
+            let status = status_of(&fixture, peer_uid, "42").await.unwrap();
+            assert_eq!(status.job_id, "42", "UID {}", peer_uid);
+        }
+        let rejection = status_of(&fixture, 0, "43").await.unwrap_err();
+        assert_eq!(rejection.code, ErrorCode::UnknownJob);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_and_unknown_jobs() {
+        let fixture = fixture("slurm");
+        for message in job_requests("42 --me") {
+            let rejection = authorize(&fixture, OWNER, &message).await.unwrap_err();
+            assert_eq!(rejection.code, ErrorCode::InvalidRequest, "{:?}", message);
+        }
+        for message in job_requests("43") {
+            let rejection = authorize(&fixture, 0, &message).await.unwrap_err();
+            assert_eq!(rejection.code, ErrorCode::Internal, "{:?}", message);
+        }
+    }
+
+    // 跟踪任务 42, 其监控进程通过连接 7 上报
+    fn connected_job(config: &Config) -> JobTracker {
+        let mut tracker = JobTracker::default();
+        tracker.insert_job("42".to_string(), client_job("42", OWNER), config.heartbeat_timeout);
+        monitor_connected(&mut tracker, "42", 7, config, &mut Vec::new());
+        tracker
+    }
+
+    fn lost_monitor_config(action: MonitorLostAction, grace: Duration) -> Config {
+        Config {
+            monitor_lost_action: action,
+            monitor_lost_grace: grace,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn lost_monitors_are_waited_for_until_the_grace_expires() {
+        let config = lost_monitor_config(MonitorLostAction::Grace, Duration::from_secs(60));
+        let mut tracker = connected_job(&config);
+        let mut effects = Vec::new();
+        let outcome = lose_monitor(&mut tracker, "42", 7, false, &config, &mut effects);
+        assert!(matches!(outcome, Some(ExpiryOutcome::Alive)));
+        assert!(tracker.jobs["42"].monitor_lost.is_some());
+        assert!(matches!(&effects[..], [JobEffect::Log { owner_uid: OWNER, .. }]));
+
+        // 截止时间之前到期的定时器只会顺延
+        let outcome = expire_heartbeat(&mut tracker, "42", &config, &mut effects);
+        assert!(matches!(outcome, ExpiryOutcome::Alive));
+        assert!(tracker.jobs.contains_key("42"));
+    }
+
+    #[test]
+    fn lost_monitors_that_do_not_reconnect_get_the_job_cancelled() {
+        let config = lost_monitor_config(MonitorLostAction::Grace, Duration::ZERO);
+        let mut tracker = connected_job(&config);
+        let mut effects = Vec::new();
+        lose_monitor(&mut tracker, "42", 7, true, &config, &mut effects);
+
+        let outcome = expire_heartbeat(&mut tracker, "42", &config, &mut effects);
+        let ExpiryOutcome::Kill(KillReason::MonitorLost, reason) = outcome else {
+            panic!("the job was not cancelled");
+        };
+        assert!(reason.contains("closed its connection"), "{}", reason);
+        assert!(!tracker.jobs.contains_key("42"));
+    }
+
+    #[test]
+    fn reconnecting_within_the_grace_clears_the_deadline() {
+        let config = lost_monitor_config(MonitorLostAction::Grace, Duration::from_secs(60));
+        let mut tracker = connected_job(&config);
+        let mut effects = Vec::new();
+        lose_monitor(&mut tracker, "42", 7, false, &config, &mut effects);
+
+        effects.clear();
+        monitor_connected(&mut tracker, "42", 8, &config, &mut effects);
+        let job = &tracker.jobs["42"];
+        assert!(job.monitor_lost.is_none());
+        assert_eq!(job.monitor_connection, Some(8));
+        assert!(matches!(&effects[..], [JobEffect::Log { message, .. }] if message.contains("reconnected")));
+
+        // 旧连接的断开不再影响任务
+        assert!(lose_monitor(&mut tracker, "42", 7, false, &config, &mut effects).is_none());
+        assert!(tracker.jobs["42"].monitor_lost.is_none());
+    }
+
+    #[test]
+    fn cancel_stops_tracking_as_soon_as_the_monitor_is_lost() {
+        let config = lost_monitor_config(MonitorLostAction::Cancel, Duration::from_secs(60));
+        let mut tracker = connected_job(&config);
+        let outcome = lose_monitor(&mut tracker, "42", 7, false, &config, &mut Vec::new());
+        assert!(matches!(outcome, Some(ExpiryOutcome::Kill(KillReason::MonitorLost, _))));
+        assert!(!tracker.jobs.contains_key("42"));
+    }
+
+    #[test]
+    fn respawn_restarts_monitors_that_are_not_supervised_yet() {
+        let config = lost_monitor_config(MonitorLostAction::Respawn, Duration::from_secs(60));
+        let mut tracker = connected_job(&config);
+        let outcome = lose_monitor(&mut tracker, "42", 7, false, &config, &mut Vec::new());
+        assert!(matches!(outcome, Some(ExpiryOutcome::Respawn { owner_uid: OWNER, .. })));
+        assert!(tracker.jobs["42"].monitor_lost.is_some());
+
+        // 已在看护的监控进程由看护任务重启
+        let mut tracker = connected_job(&config);
+        tracker.jobs.get_mut("42").unwrap().supervised_monitor = Some(SupervisedMonitor {
+            pid: Some(100),
+            restarts: 0,
+            last_exit: None,
+            last_exit_at: None,
+        });
+        let outcome = lose_monitor(&mut tracker, "42", 7, false, &config, &mut Vec::new());
+        assert!(matches!(outcome, Some(ExpiryOutcome::Alive)));
+    }
 }
@@ -0,0 +1,70 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use chrono::Local;
+use log::{info, warn};
+use nix::fcntl::OFlag;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use crate::log_to_job_file;
+
+// ============================================================================
+// 用户通知 (User Notification)
+// ============================================================================
+
+// 伪终端所在目录
+const PTS_DIR: &str = "/dev/pts";
+
+// 写入任务日志, 并尽量推送到任务所有者在本节点上的终端
+pub async fn notify_user(owner_uid: u32, log_path: &Path, job_id: &str, message: &str) {
+    log_to_job_file(owner_uid, log_path, message).await;
+
+    let banner = format!(
+        "\r\n\x07*** node_monitor [{}] job {} ***\r\n{}\r\n",
+        Local::now().format("%Y-%m-%dT%H:%M:%S"),
+        job_id,
+        message
+    );
+    let written = write_to_terminals(owner_uid, banner.as_bytes()).await;
+    info!(
+        "Notified owner (UID {}) of job {} on {} terminal(s): {}",
+        owner_uid, job_id, written, message
+    );
+}
+
+// 和 write(1) 一样, 只写入属于该用户且未 `mesg n` (组可写) 的终端, 返回写入的终端数
+async fn write_to_terminals(uid: u32, bytes: &[u8]) -> usize {
+    let mut entries = match fs::read_dir(PTS_DIR).await {
+        Ok(e) => e,
+        Err(e) => {
+            warn!("Failed to list {}: {}", PTS_DIR, e);
+            return 0;
+        }
+    };
+
+    let mut written = 0;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let Ok(metadata) = entry.metadata().await else {
+            continue;
+        };
+        if metadata.uid() != uid || metadata.mode() & 0o020 == 0 {
+            continue;
+        }
+
+        // O_NOCTTY 避免成为守护进程的控制终端, O_NONBLOCK 避免被卡住的终端阻塞
+        let open = OpenOptions::new()
+            .write(true)
+            .custom_flags((OFlag::O_NOCTTY | OFlag::O_NONBLOCK).bits())
+            .open(entry.path())
+            .await;
+        match open {
+            Ok(mut tty) => match tty.write_all(bytes).await {
+                Ok(()) => written += 1,
+                Err(e) => warn!("Failed to write to {}: {}", entry.path().display(), e),
+            },
+            Err(e) => warn!("Failed to open {}: {}", entry.path().display(), e),
+        }
+    }
+    written
+}
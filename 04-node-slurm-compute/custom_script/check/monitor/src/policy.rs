@@ -0,0 +1,403 @@
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use protocol::RegisterPayload;
+use serde::Deserialize;
+
+use crate::config::{deserialize_duration, deserialize_optional_duration};
+
+// ============================================================================
+// 常量定义 (Constants)
+// ============================================================================
+
+// 默认窗口长度: 整个窗口内都低于阈值才判定为空闲
+const DEFAULT_GPU_WINDOW: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_CPU_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+// 窗口长度上限, 限制每个任务占用的内存
+const DEFAULT_MAX_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+// max_window 本身允许的最大值
+const MAX_WINDOW_LIMIT: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+// ============================================================================
+// 策略配置 (Policy Config)
+// ============================================================================
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyConfig {
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub default_gpu_window: Duration,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub default_cpu_window: Duration,
+    // 所有窗口长度 (以及 buffer_period) 的上限
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub max_window: Duration,
+    // 按 GPU 型号设置 GPU 窗口, 任务有多张卡时取最小值
+    pub gpu_models: Vec<GpuModelRule>,
+    // 按分区/QOS/账户覆盖默认值, 按顺序匹配, 第一条匹配的规则生效
+    pub rules: Vec<PolicyRule>,
+    // 多卡任务中持续闲置的卡
+    pub idle_gpus: IdleGpuPolicy,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdleGpuPolicy {
+    // 整个 GPU 窗口内利用率都低于 gpu_utilization_threshold 的卡数达到该值时触发, 0 表示不检查
+    pub min_idle_gpus: usize,
+    pub action: IdleGpuAction,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IdleGpuAction {
+    // 只提醒用户一次, 直到闲置的卡数重新低于阈值
+    #[default]
+    Warn,
+    // 与整体空闲一样走逐级警告后取消
+    Cancel,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GpuModelRule {
+    // GPU 名称中包含该字符串即匹配 (不区分大小写)
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub window: Duration,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyRule {
+    // 分区名包含该字符串即匹配 (不区分大小写)
+    pub partition: Option<String>,
+    pub qos: Option<String>,
+    pub account: Option<String>,
+    #[serde(default = "default_true")]
+    pub monitor_gpu: bool,
+    #[serde(default = "default_true")]
+    pub monitor_cpu: bool,
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub gpu_window: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub cpu_window: Option<Duration>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// 与此前 job_helper 中写死的规则保持一致
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        let model = |name: &str, minutes: u64| GpuModelRule {
+            name: name.to_string(),
+            window: Duration::from_secs(minutes * 60),
+        };
+        let partition = |name: &str, monitor_gpu, monitor_cpu| PolicyRule {
+            partition: Some(name.to_string()),
+            qos: None,
+            account: None,
+            monitor_gpu,
+            monitor_cpu,
+            gpu_window: None,
+            cpu_window: None,
+        };
+        Self {
+            default_gpu_window: DEFAULT_GPU_WINDOW,
+            default_cpu_window: DEFAULT_CPU_WINDOW,
+            max_window: DEFAULT_MAX_WINDOW,
+            gpu_models: vec![
+                model("5090", 20),
+                model("A6000", 20),
+                model("4090", 20),
+                model("3090", 60),
+                model("A10", 60),
+            ],
+            rules: vec![partition("debug", false, false), partition("gpu", true, false)],
+            idle_gpus: IdleGpuPolicy::default(),
+        }
+    }
+}
+
+impl PolicyRule {
+    fn matches(&self, payload: &RegisterPayload) -> bool {
+        let partition_matches = self
+            .partition
+            .as_ref()
+            .is_none_or(|p| payload.partition.to_lowercase().contains(&p.to_lowercase()));
+        let qos_matches = self.qos.as_ref().is_none_or(|q| *q == payload.qos);
+        let account_matches = self.account.as_ref().is_none_or(|a| *a == payload.account);
+        partition_matches && qos_matches && account_matches
+    }
+}
+
+impl PolicyConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_window.is_zero() || self.max_window > MAX_WINDOW_LIMIT {
+            bail!(
+                "policy.max_window must be between 1s and {}d",
+                MAX_WINDOW_LIMIT.as_secs() / 86400
+            );
+        }
+        let check = |name: &str, window: Duration| {
+            if window.is_zero() || window > self.max_window {
+                bail!(
+                    "{} must be between 1s and policy.max_window ({}s), got {}s",
+                    name,
+                    self.max_window.as_secs(),
+                    window.as_secs()
+                );
+            }
+            Ok(())
+        };
+        check("policy.default_gpu_window", self.default_gpu_window)?;
+        check("policy.default_cpu_window", self.default_cpu_window)?;
+        for model in &self.gpu_models {
+            if model.name.is_empty() {
+                bail!("policy.gpu_models entries need a non-empty name");
+            }
+            check("policy.gpu_models window", model.window)?;
+        }
+        for rule in &self.rules {
+            for window in [rule.gpu_window, rule.cpu_window].into_iter().flatten() {
+                check("policy.rules window", window)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 根据任务注册时上报的信息计算 (GPU 窗口, CPU 窗口), None 表示不检查该指标
+    pub fn monitor_windows(&self, payload: &RegisterPayload) -> (Option<Duration>, Option<Duration>) {
+        let rule = self.rules.iter().find(|r| r.matches(payload));
+
+        let gpu_window = match rule {
+            Some(r) if !r.monitor_gpu => None,
+            _ if payload.gpus.is_empty() => None,
+            Some(PolicyRule {
+                gpu_window: Some(window),
+                ..
+            }) => Some(*window),
+            _ => payload.gpus.iter().map(|gpu| self.gpu_model_window(&gpu.name)).min(),
+        };
+
+        let cpu_window = match rule {
+            Some(r) if !r.monitor_cpu => None,
+            Some(PolicyRule {
+                cpu_window: Some(window),
+                ..
+            }) => Some(*window),
+            _ => Some(self.default_cpu_window),
+        };
+
+        // 配置校验已保证不超过上限, 这里再截断一次以防万一
+        (
+            gpu_window.map(|w| w.min(self.max_window)),
+            cpu_window.map(|w| w.min(self.max_window)),
+        )
+    }
+
+    fn gpu_model_window(&self, gpu_name: &str) -> Duration {
+        let upper_name = gpu_name.to_uppercase();
+        self.gpu_models
+            .iter()
+            .find(|m| upper_name.contains(&m.name.to_uppercase()))
+            .map(|m| m.window)
+            .unwrap_or(self.default_gpu_window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use protocol::GpuInfo;
+
+    fn minutes(m: u64) -> Duration {
+        Duration::from_secs(m * 60)
+    }
+
+    fn job(partition: &str, qos: &str, account: &str, gpus: &[&str]) -> RegisterPayload {
+        RegisterPayload {
+            job_id: "42".to_string(),
+            log_path: "/home/alice/job.log".into(),
+            partition: partition.to_string(),
+            qos: qos.to_string(),
+            account: account.to_string(),
+            gpus: gpus
+                .iter()
+                .enumerate()
+                .map(|(i, name)| GpuInfo {
+                    uuid: format!("GPU-{}", i),
+                    name: name.to_string(),
+                })
+                .collect(),
+            allocated_cpus: 4,
+            gpu_monitor_count: None,
+            cpu_monitor_count: None,
+        }
+    }
+
+    fn rule(partition: Option<&str>, qos: Option<&str>, account: Option<&str>) -> PolicyRule {
+        PolicyRule {
+            partition: partition.map(str::to_string),
+            qos: qos.map(str::to_string),
+            account: account.map(str::to_string),
+            monitor_gpu: true,
+            monitor_cpu: true,
+            gpu_window: None,
+            cpu_window: None,
+        }
+    }
+
+    #[test]
+    fn default_policy_matches_the_old_job_helper_rules() {
+        let policy = PolicyConfig::default();
+
+        assert_eq!(
+            policy.monitor_windows(&job("compute", "normal", "lab", &["NVIDIA GeForce RTX 4090"])),
+            (Some(minutes(20)), Some(minutes(60)))
+        );
+        // 分区名包含匹配且不区分大小写
+        assert_eq!(
+            policy.monitor_windows(&job("Debug-Long", "normal", "lab", &["NVIDIA GeForce RTX 4090"])),
+            (None, None)
+        );
+        assert_eq!(
+            policy.monitor_windows(&job("gpu", "normal", "lab", &["NVIDIA GeForce RTX 4090"])),
+            (Some(minutes(20)), None)
+        );
+        // 没有 GPU 时不检查 GPU
+        assert_eq!(
+            policy.monitor_windows(&job("compute", "normal", "lab", &[])),
+            (None, Some(minutes(60)))
+        );
+    }
+
+    #[test]
+    fn gpu_window_is_the_smallest_model_window() {
+        let policy = PolicyConfig::default();
+
+        assert_eq!(
+            policy
+                .monitor_windows(&job(
+                    "compute",
+                    "",
+                    "",
+                    &["NVIDIA RTX A6000", "NVIDIA GeForce RTX 3090"]
+                ))
+                .0,
+            Some(minutes(20))
+        );
+        // 未列出的型号使用默认窗口
+        assert_eq!(
+            policy.monitor_windows(&job("compute", "", "", &["Tesla V100"])).0,
+            Some(minutes(60))
+        );
+        // 型号名称不区分大小写
+        assert_eq!(
+            policy
+                .monitor_windows(&job("compute", "", "", &["nvidia geforce rtx 5090"]))
+                .0,
+            Some(minutes(20))
+        );
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let policy = PolicyConfig {
+            rules: vec![
+                PolicyRule {
+                    gpu_window: Some(minutes(30)),
+                    cpu_window: Some(minutes(90)),
+                    ..rule(Some("gpu"), Some("long"), None)
+                },
+                PolicyRule {
+                    monitor_cpu: false,
+                    gpu_window: Some(minutes(45)),
+                    ..rule(Some("gpu"), None, None)
+                },
+                PolicyRule {
+                    monitor_gpu: false,
+                    ..rule(None, None, Some("vip"))
+                },
+            ],
+            ..PolicyConfig::default()
+        };
+
+        // 分区和 QOS 都匹配第一条
+        assert_eq!(
+            policy.monitor_windows(&job("gpu", "long", "vip", &["NVIDIA GeForce RTX 4090"])),
+            (Some(minutes(30)), Some(minutes(90)))
+        );
+        // QOS 不匹配第一条, 落到第二条; 后面同样匹配的第三条不生效
+        assert_eq!(
+            policy.monitor_windows(&job("gpu", "normal", "vip", &["NVIDIA GeForce RTX 4090"])),
+            (Some(minutes(45)), None)
+        );
+        // QOS 和账户按完整名称匹配
+        assert_eq!(
+            policy.monitor_windows(&job("compute", "normal", "vip", &["NVIDIA GeForce RTX 4090"])),
+            (None, Some(minutes(60)))
+        );
+        assert_eq!(
+            policy.monitor_windows(&job("gpu-dev", "longer", "vip2", &["NVIDIA GeForce RTX 4090"])),
+            (Some(minutes(45)), None)
+        );
+        // 没有规则匹配时使用型号窗口和默认 CPU 窗口
+        assert_eq!(
+            policy.monitor_windows(&job("compute", "normal", "lab", &["NVIDIA GeForce RTX 4090"])),
+            (Some(minutes(20)), Some(minutes(60)))
+        );
+    }
+
+    #[test]
+    fn rule_windows_override_models_unless_the_metric_is_not_checked() {
+        let policy = PolicyConfig {
+            rules: vec![
+                PolicyRule {
+                    monitor_gpu: false,
+                    gpu_window: Some(minutes(30)),
+                    ..rule(Some("nogpu"), None, None)
+                },
+                PolicyRule {
+                    gpu_window: Some(minutes(120)),
+                    ..rule(Some("long"), None, None)
+                },
+            ],
+            ..PolicyConfig::default()
+        };
+
+        // monitor_gpu = false 优先于 gpu_window
+        assert_eq!(
+            policy
+                .monitor_windows(&job("nogpu", "", "", &["NVIDIA GeForce RTX 4090"]))
+                .0,
+            None
+        );
+        // 规则的 GPU 窗口优先于型号窗口, 即使更长
+        assert_eq!(
+            policy
+                .monitor_windows(&job("long", "", "", &["NVIDIA GeForce RTX 4090"]))
+                .0,
+            Some(minutes(120))
+        );
+        // 但任务没有 GPU 时仍然不检查 GPU
+        assert_eq!(policy.monitor_windows(&job("long", "", "", &[])).0, None);
+    }
+
+    #[test]
+    fn windows_are_capped_at_max_window() {
+        let policy = PolicyConfig {
+            max_window: minutes(40),
+            ..PolicyConfig::default()
+        };
+        assert_eq!(
+            policy.monitor_windows(&job("compute", "", "", &["Tesla V100"])),
+            (Some(minutes(40)), Some(minutes(40)))
+        );
+    }
+}
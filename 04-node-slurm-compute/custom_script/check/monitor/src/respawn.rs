@@ -0,0 +1,297 @@
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use log::warn;
+use nix::sys::signal::{Signal, killpg};
+use nix::unistd::{Gid, Pid, Uid, User, getgrouplist, setgid, setgroups, setuid};
+use sampler::cgroup::{self, ProcessMembership};
+use sampler::gpu::{self, BackendKind};
+use tokio::process::{Child, Command};
+use tokio::time;
+
+use crate::sampling::JobFacts;
+
+// ============================================================================
+// 常量定义 (Constants)
+// ============================================================================
+
+// 停止监控进程时等待它自行退出的时间, 超时后发送 SIGKILL
+const MONITOR_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+// 检查接管的监控进程是否仍在运行的间隔
+const ADOPTED_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// ============================================================================
+// 数据结构定义 (Data Structures)
+// ============================================================================
+
+// 启动监控进程前在阻塞线程中准备好的信息
+enum Launch {
+    Ready {
+        owner: User,
+        groups: Vec<Gid>,
+        // 与 task prolog 中任务自身环境一致的 Slurm 变量和 CUDA_VISIBLE_DEVICES
+        job_env: Vec<(&'static str, String)>,
+        // 任务 cgroup 的 cgroup.procs, 由 root 打开, 子进程在降权前写入
+        cgroup_procs: Vec<File>,
+    },
+    // 任务已不在运行 (例如 COMPLETING), 不再启动
+    JobEnded(String),
+}
+
+// 正在看护的监控进程
+pub enum MonitorProcess {
+    Child(Child),
+    // 守护进程重启之前启动、仍在任务 cgroup 中运行的监控进程; 它不是当前进程的子进程, 只能轮询是否退出
+    Adopted { pid: u32, job_id: String },
+}
+
+// ============================================================================
+// 重新启动监控进程 (Monitor Respawn)
+// ============================================================================
+
+// 与 task_prolog.sh 一样启动 job_helper monitor, 但以任务所有者的身份运行, 并在 exec 之前加入任务的 cgroup,
+// 使其与任务一起统计资源, 任务结束时由 Slurm 一起清理. 任务已不在运行时返回任务状态.
+// GPU 由守护进程自己的后端按 scontrol 报告的绝对编号换算成 UUID, 不受任务 cgroup 内相对编号的影响
+pub async fn spawn_monitor(
+    job_helper: &Path,
+    gpu_backend: BackendKind,
+    gpu_fake_file: Option<&Path>,
+    job_id: &str,
+    owner_uid: u32,
+    log_path: &Path,
+) -> Result<Result<Child, String>> {
+    let launch = {
+        let job_id = job_id.to_string();
+        let gpu_fake_file = gpu_fake_file.map(Path::to_path_buf);
+        tokio::task::spawn_blocking(move || prepare_launch(&job_id, owner_uid, gpu_backend, gpu_fake_file.as_deref()))
+            .await
+            .context("Monitor setup task panicked")??
+    };
+    let (owner, groups, job_env, cgroup_procs) = match launch {
+        Launch::Ready {
+            owner,
+            groups,
+            job_env,
+            cgroup_procs,
+        } => (owner, groups, job_env, cgroup_procs),
+        Launch::JobEnded(state) => return Ok(Err(state)),
+    };
+
+    let mut command = Command::new(job_helper);
+    command
+        .arg("monitor")
+        .arg("--log-path")
+        .arg(log_path)
+        .env_clear()
+        .env("HOME", &owner.dir)
+        .env("USER", &owner.name)
+        .env("LOGNAME", &owner.name)
+        .env("PATH", "/usr/local/bin:/usr/bin:/bin")
+        .env("SLURM_JOB_ID", job_id)
+        .envs(job_env)
+        // root 不一定能进入 (root squash 的) 家目录
+        .current_dir("/")
+        // 单独的进程组, 不随守护进程收到的信号一起退出
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let (uid, gid) = (owner.uid, owner.gid);
+    // SAFETY: 闭包在 fork 之后、exec 之前执行, 只调用 write/setgroups/setgid/setuid 系统调用, 不分配内存也不获取锁
+    unsafe {
+        command.pre_exec(move || {
+            // 写入 "0" 把当前进程移入该 cgroup; 需要 root 权限, 因此在降权之前完成
+            for procs in &cgroup_procs {
+                let mut procs: &File = procs;
+                procs.write_all(b"0")?;
+            }
+            setgroups(&groups)?;
+            setgid(gid)?;
+            setuid(uid)?;
+            Ok(())
+        });
+    }
+    let child = command
+        .spawn()
+        .with_context(|| format!("Failed to start {}", job_helper.display()))?;
+    Ok(Ok(child))
+}
+
+fn prepare_launch(
+    job_id: &str,
+    owner_uid: u32,
+    gpu_backend: BackendKind,
+    gpu_fake_file: Option<&Path>,
+) -> Result<Launch> {
+    let facts = JobFacts::query(job_id)?;
+    if facts.owner_uid != owner_uid {
+        bail!(
+            "Job {} is owned by UID {}, not UID {}",
+            job_id,
+            facts.owner_uid,
+            owner_uid
+        );
+    }
+    if facts.job_state != "RUNNING" {
+        return Ok(Launch::JobEnded(facts.job_state));
+    }
+
+    let owner = User::from_uid(Uid::from_raw(owner_uid))
+        .with_context(|| format!("Failed to look up UID {}", owner_uid))?
+        .ok_or_else(|| anyhow!("Unknown user with uid {}", owner_uid))?;
+    let name = CString::new(owner.name.as_str()).context("User name contains a NUL byte")?;
+    let groups =
+        getgrouplist(&name, owner.gid).with_context(|| format!("Failed to look up the groups of {}", owner.name))?;
+
+    // 任务 cgroup 的叶子层级以任务中已有的进程为准
+    let dirs = job_pids(job_id)?
+        .into_iter()
+        .find_map(|pid| cgroup::job_cgroup_dirs(pid, job_id).ok())
+        .ok_or_else(|| anyhow!("No process of job {} was found in its cgroup", job_id))?;
+    let cgroup_procs = dirs
+        .iter()
+        .map(|dir| {
+            let path = dir.join("cgroup.procs");
+            OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))
+        })
+        .collect::<Result<_>>()?;
+
+    let gpu_uuids = if facts.gpu_indices.is_empty() {
+        Vec::new()
+    } else {
+        let devices = gpu::create_backend(gpu_backend, gpu_fake_file)?.devices(&facts.gpu_indices)?;
+        if devices.is_empty() {
+            bail!("No GPUs found for indices {}", facts.gpu_indices);
+        }
+        devices.into_iter().map(|d| d.uuid).collect()
+    };
+
+    Ok(Launch::Ready {
+        owner,
+        groups,
+        job_env: job_env(&facts, &gpu_uuids),
+        cgroup_procs,
+    })
+}
+
+// job_helper 注册时读取的任务环境变量 (见 registration_payload); GPU 以 UUID 指定
+fn job_env(facts: &JobFacts, gpu_uuids: &[String]) -> Vec<(&'static str, String)> {
+    vec![
+        ("SLURM_CPUS_ON_NODE", facts.cpus_on_node.to_string()),
+        ("SLURM_JOB_PARTITION", facts.partition.clone()),
+        ("SLURM_JOB_QOS", facts.qos.clone()),
+        ("SLURM_JOB_ACCOUNT", facts.account.clone()),
+        ("CUDA_VISIBLE_DEVICES", gpu_uuids.join(",")),
+    ]
+}
+
+// 任务在本节点上的进程号
+fn job_pids(job_id: &str) -> Result<Vec<u32>> {
+    let output = std::process::Command::new("scontrol")
+        .args(["listpids", job_id])
+        .output()
+        .context("Failed to execute 'scontrol listpids'")?;
+    if !output.status.success() {
+        bail!(
+            "'scontrol listpids {}' failed: {}",
+            job_id,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    // 第一行是表头: PID JOBID STEPID LOCALID GLOBALID
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next()?.parse().ok())
+        .collect())
+}
+
+impl MonitorProcess {
+    pub fn id(&self) -> Option<u32> {
+        match self {
+            MonitorProcess::Child(child) => child.id(),
+            MonitorProcess::Adopted { pid, .. } => Some(*pid),
+        }
+    }
+
+    // 等待监控进程退出; 接管的进程拿不到退出状态
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        match self {
+            MonitorProcess::Child(child) => child.wait().await,
+            MonitorProcess::Adopted { pid, job_id } => {
+                let mut interval = time::interval(ADOPTED_POLL_INTERVAL);
+                while is_job_process(*pid, job_id) {
+                    interval.tick().await;
+                }
+                Err(io::Error::other("it was started before the daemon restarted"))
+            }
+        }
+    }
+
+    // 先向监控进程所在的进程组发送 SIGTERM, 超时后 SIGKILL
+    pub async fn stop(&mut self) {
+        if let Some(pid) = self.id() {
+            let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGTERM);
+        }
+        if time::timeout(MONITOR_STOP_TIMEOUT, self.wait()).await.is_ok() {
+            return;
+        }
+        let result = match self {
+            MonitorProcess::Child(child) => child.kill().await,
+            MonitorProcess::Adopted { pid, .. } => {
+                killpg(Pid::from_raw(*pid as i32), Signal::SIGKILL).map_err(io::Error::from)
+            }
+        };
+        if let Err(e) = result {
+            warn!("Failed to kill job_helper monitor: {}", e);
+        }
+    }
+}
+
+// 进程仍在任务的 cgroup 中运行; 进程已退出 (或进程号被其他进程复用) 时返回 false
+pub fn is_job_process(pid: u32, job_id: &str) -> bool {
+    cgroup::process_in_job(pid, job_id) == ProcessMembership::InJob
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monitors_get_the_job_environment() {
+        let facts = JobFacts {
+            owner_uid: 1500,
+            partition: "gpu".to_string(),
+            qos: "normal".to_string(),
+            account: "lab".to_string(),
+            job_state: "RUNNING".to_string(),
+            gpu_indices: "2,3".to_string(),
+            cpus_on_node: 8,
+        };
+        let env = job_env(&facts, &["GPU-2".to_string(), "GPU-3".to_string()]);
+        assert_eq!(
+            env,
+            [
+                ("SLURM_CPUS_ON_NODE", "8".to_string()),
+                ("SLURM_JOB_PARTITION", "gpu".to_string()),
+                ("SLURM_JOB_QOS", "normal".to_string()),
+                ("SLURM_JOB_ACCOUNT", "lab".to_string()),
+                ("CUDA_VISIBLE_DEVICES", "GPU-2,GPU-3".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn cpu_only_jobs_see_no_gpus() {
+        let env = job_env(&JobFacts::default(), &[]);
+        assert!(env.contains(&("CUDA_VISIBLE_DEVICES", String::new())));
+    }
+}
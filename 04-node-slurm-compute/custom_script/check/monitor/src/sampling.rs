@@ -0,0 +1,572 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::process::Command;
+
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::Utc;
+use log::{error, info, warn};
+use nix::unistd::{Uid, User};
+use protocol::{GpuInfo, GpuMetric, MetricsPayload, RegisterPayload};
+use sampler::cgroup::{self, JobCgroup, JobCpuSampler, ProcessMembership};
+use sampler::gpu::{self, GpuBackend, GpuDevice};
+use tokio::time;
+
+use crate::config::Config;
+use crate::exporter::SharedStats;
+use crate::tracker::SharedTracker;
+use crate::{MetricsOutcome, MetricsSource, SharedActionQueue, SharedConfig, handle_metrics, register_job};
+
+// ============================================================================
+// 守护进程采样 (Daemon-Side Sampling)
+// ============================================================================
+
+// 守护进程直接采样的任务, 跨轮次保留 CPU 计数器和 GPU 列表
+struct SampledJob {
+    cpu: JobCpuSampler,
+    devices: Vec<GpuDevice>,
+}
+
+// 在阻塞线程中使用的采样状态, 每轮移入再移出
+#[derive(Default)]
+struct SamplerState {
+    backend: Option<Box<dyn GpuBackend>>,
+    jobs: HashMap<String, SampledJob>,
+    // 已排队取消的任务, cgroup 消失前不再重新发现
+    cancelled: HashSet<String>,
+}
+
+// 新发现的任务, 需要先注册才能开始判定
+struct DiscoveredJob {
+    owner_uid: u32,
+    payload: RegisterPayload,
+}
+
+// 一轮采样的结果
+#[derive(Default)]
+struct Round {
+    discovered: Vec<DiscoveredJob>,
+    metrics: Vec<MetricsPayload>,
+    // 本轮在 cgroup 中看到的所有任务
+    present: HashSet<String>,
+}
+
+// 从 Slurm cgroup 中发现本节点上的任务并采样, 之后与客户端上报的指标走同样的判定逻辑
+pub async fn run_daemon_sampler(
+    tracker: SharedTracker,
+    config: SharedConfig,
+    actions: SharedActionQueue,
+    stats: SharedStats,
+) {
+    info!("Sampling jobs directly from the Slurm cgroup tree.");
+    let mut state = Some(SamplerState::default());
+
+    loop {
+        let config_snapshot = config.read().await.clone();
+        time::sleep(config_snapshot.daemon_sample_interval).await;
+
+        let tracked: HashMap<String, MetricsSource> = match tracker
+            .with(|tracker| {
+                tracker
+                    .jobs
+                    .iter()
+                    .map(|(job_id, job)| (job_id.clone(), job.source))
+                    .collect()
+            })
+            .await
+        {
+            Ok(tracked) => tracked,
+            Err(e) => {
+                error!("Failed to read the tracked jobs, skipping this sampling round: {:#}", e);
+                continue;
+            }
+        };
+
+        let Some(mut sampler_state) = state.take() else {
+            return;
+        };
+        let result = tokio::task::spawn_blocking(move || {
+            let round = sample_round(&mut sampler_state, &tracked, &config_snapshot);
+            (sampler_state, round)
+        })
+        .await;
+        let round = match result {
+            Ok((sampler_state, round)) => {
+                state = Some(sampler_state);
+                round
+            }
+            Err(e) => {
+                error!("Daemon sampling task failed, restarting with fresh state: {}", e);
+                state = Some(SamplerState::default());
+                continue;
+            }
+        };
+        let round = match round {
+            Ok(round) => round,
+            Err(e) => {
+                warn!("Failed to discover jobs from the cgroup tree: {:#}", e);
+                continue;
+            }
+        };
+
+        for job in round.discovered {
+            let job_id = job.payload.job_id.clone();
+            if register_job(job.payload, job.owner_uid, MetricsSource::Daemon, &tracker, &config)
+                .await
+                .is_err()
+            {
+                // 注册失败时下一轮重新发现
+                if let Some(sampler_state) = state.as_mut() {
+                    sampler_state.jobs.remove(&job_id);
+                }
+            }
+        }
+
+        for payload in round.metrics {
+            let job_id = payload.job_id.clone();
+            let outcome = match handle_metrics(payload, None, tracker.clone(), config.clone()).await {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    error!("Failed to record metrics of job {}: {:#}", job_id, e);
+                    continue;
+                }
+            };
+            if let MetricsOutcome::Kill(job_id, kind, reason) = outcome {
+                stats.record_kill(kind);
+                actions.enqueue_kill(&job_id, &reason).await;
+                if let Some(sampler_state) = state.as_mut() {
+                    sampler_state.jobs.remove(&job_id);
+                    sampler_state.cancelled.insert(job_id);
+                }
+            }
+        }
+
+        // cgroup 消失说明任务已结束, 只停止跟踪 (经 remove_job, 一并撤销定时器并通知看护任务)
+        let present = round.present;
+        let untracked = tracker
+            .with(move |tracker| {
+                let gone: Vec<String> = tracker
+                    .jobs
+                    .iter()
+                    .filter(|(job_id, job)| job.source == MetricsSource::Daemon && !present.contains(*job_id))
+                    .map(|(job_id, _)| job_id.clone())
+                    .collect();
+                for job_id in gone {
+                    info!("Cgroup of job {} is gone. Untracking it.", job_id);
+                    tracker.remove_job(&job_id);
+                }
+            })
+            .await;
+        if let Err(e) = untracked {
+            error!("Failed to untrack jobs whose cgroup is gone: {:#}", e);
+        }
+    }
+}
+
+fn sample_round(state: &mut SamplerState, tracked: &HashMap<String, MetricsSource>, config: &Config) -> Result<Round> {
+    let mut round = Round::default();
+    let cgroups = JobCgroup::discover_all()?;
+    round.present = cgroups.iter().map(|(job_id, _)| job_id.clone()).collect();
+    // 已由 job_helper monitor 上报的任务不再重复采样; 被管理员 forget 的任务下次重新发现
+    state
+        .jobs
+        .retain(|job_id, _| round.present.contains(job_id) && tracked.get(job_id) == Some(&MetricsSource::Daemon));
+    state.cancelled.retain(|job_id| round.present.contains(job_id));
+
+    for (job_id, job_cgroup) in cgroups {
+        if tracked.get(&job_id) == Some(&MetricsSource::Client) || state.cancelled.contains(&job_id) {
+            continue;
+        }
+
+        let Some(job) = state.jobs.get_mut(&job_id) else {
+            match discover_job(state, &job_id, job_cgroup, config) {
+                Ok(discovered) => round.discovered.push(discovered),
+                Err(e) => warn!("Skipping job {} for now: {:#}", job_id, e),
+            }
+            continue;
+        };
+
+        let backend = state.backend.as_mut();
+        if let Some(metrics) = sample_job(&job_id, job, backend, |pid| cgroup::process_in_job(pid, &job_id)) {
+            round.metrics.push(metrics);
+        }
+    }
+
+    Ok(round)
+}
+
+// 采样一个已发现的任务; 本轮没有可用的读数时返回 None
+fn sample_job(
+    job_id: &str,
+    job: &mut SampledJob,
+    backend: Option<&mut Box<dyn GpuBackend>>,
+    membership: impl Fn(u32) -> ProcessMembership,
+) -> Option<MetricsPayload> {
+    // 第一次采样只记录 CPU 计数器, 下一轮才有利用率
+    let cpu_utilization = match job.cpu.sample_since_last() {
+        Ok(Some(value)) => value,
+        Ok(None) => return None,
+        Err(e) => {
+            warn!("Could not sample CPU usage of job {}: {:#}", job_id, e);
+            return None;
+        }
+    };
+
+    let usage = if job.devices.is_empty() {
+        gpu::JobGpuUsage::default()
+    } else {
+        match backend?.process_samples(&job.devices) {
+            Ok(samples) => gpu::job_gpu_usage(&job.devices, &samples, membership),
+            Err(e) => {
+                // 采不到 GPU 数据时跳过本轮, 避免把任务误判为空闲
+                warn!("Could not sample GPU usage of job {}: {:#}", job_id, e);
+                return None;
+            }
+        }
+    };
+
+    Some(MetricsPayload {
+        job_id: job_id.to_string(),
+        gpu_utilization: usage.utilization,
+        gpu_memory_utilization: usage.memory_utilization,
+        cpu_utilization,
+        gpus: usage
+            .devices
+            .into_iter()
+            .map(|d| GpuMetric {
+                uuid: d.uuid,
+                utilization: d.utilization,
+                memory_utilization: d.memory_utilization,
+            })
+            .collect(),
+        sampled_at: Some(Utc::now()),
+        gpu_unknown: usage.unknown,
+    })
+}
+
+// 通过 scontrol 查询新任务的属主/分区/GPU, 并开始跟踪它的 CPU 计数器
+fn discover_job(
+    state: &mut SamplerState,
+    job_id: &str,
+    job_cgroup: JobCgroup,
+    config: &Config,
+) -> Result<DiscoveredJob> {
+    let facts = JobFacts::query(job_id)?;
+    let user = User::from_uid(Uid::from_raw(facts.owner_uid))
+        .ok()
+        .flatten()
+        .ok_or_else(|| anyhow!("Unknown user with uid {}", facts.owner_uid))?;
+
+    let devices = if facts.gpu_indices.is_empty() {
+        Vec::new()
+    } else {
+        if state.backend.is_none() {
+            state.backend = Some(gpu::create_backend(
+                config.daemon_gpu_backend,
+                config.daemon_gpu_fake_file.as_deref(),
+            )?);
+        }
+        let backend = state.backend.as_mut().context("GPU backend is not available")?;
+        let devices = backend.devices(&facts.gpu_indices)?;
+        if devices.is_empty() {
+            bail!("No GPUs found for indices {}", facts.gpu_indices);
+        }
+        devices
+    };
+
+    let cpu = JobCpuSampler::from_cgroup(job_cgroup)?;
+    let payload = RegisterPayload {
+        job_id: job_id.to_string(),
+        // 与 task_prolog.sh 中 job_helper register 使用的路径一致
+        log_path: PathBuf::from(&user.dir)
+            .join(".slurm")
+            .join(format!("info-{}.log", job_id)),
+        partition: facts.partition,
+        qos: facts.qos,
+        account: facts.account,
+        gpus: devices
+            .iter()
+            .map(|d| GpuInfo {
+                uuid: d.uuid.clone(),
+                name: d.name.clone(),
+            })
+            .collect(),
+        allocated_cpus: cpu.allocated_cpus(),
+        gpu_monitor_count: None,
+        cpu_monitor_count: None,
+    };
+    info!(
+        "Discovered job {} of user {} in cgroup {:?}.",
+        job_id,
+        user.name,
+        cpu.cgroup()
+    );
+    state.jobs.insert(job_id.to_string(), SampledJob { cpu, devices });
+
+    Ok(DiscoveredJob {
+        owner_uid: facts.owner_uid,
+        payload,
+    })
+}
+
+// ============================================================================
+// Slurm 任务信息 (Job Facts)
+// ============================================================================
+
+#[derive(Debug, Default)]
+pub struct JobFacts {
+    pub owner_uid: u32,
+    pub partition: String,
+    pub qos: String,
+    pub account: String,
+    // 例如 RUNNING / COMPLETING / CANCELLED
+    pub job_state: String,
+    // 本节点上分配给任务的 GPU 编号, 逗号分隔 (与 CUDA_VISIBLE_DEVICES 格式一致)
+    pub gpu_indices: String,
+    // 本节点上分配给任务的 CPU 个数 (与 SLURM_CPUS_ON_NODE 一致)
+    pub cpus_on_node: usize,
+}
+
+impl JobFacts {
+    pub fn query(job_id: &str) -> Result<Self> {
+        let output = Command::new("scontrol")
+            .args(["show", "job", "-d", "-o", job_id])
+            .output()
+            .context("Failed to execute 'scontrol show job'")?;
+        if !output.status.success() {
+            bail!(
+                "'scontrol show job {}' failed: {}",
+                job_id,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+        Self::parse(&stdout, hostname.trim())
+    }
+
+    fn parse(line: &str, hostname: &str) -> Result<Self> {
+        let mut facts = JobFacts::default();
+        let mut owner = None;
+        // -d 输出中每个节点一组 "Nodes=... CPU_IDs=... GRES=gpu:N(IDX:...)"
+        let mut on_this_node = false;
+
+        for field in line.split_whitespace() {
+            let Some((key, value)) = field.split_once('=') else {
+                continue;
+            };
+            match key {
+                "UserId" => {
+                    owner = value
+                        .split_once('(')
+                        .and_then(|(_, uid)| uid.trim_end_matches(')').parse().ok())
+                }
+                "Partition" => facts.partition = value.to_string(),
+                "QOS" => facts.qos = value.to_string(),
+                "Account" => facts.account = value.to_string(),
+                "JobState" => facts.job_state = value.to_string(),
+                "Nodes" => on_this_node = node_list_contains(value, hostname),
+                "CPU_IDs" if on_this_node => facts.cpus_on_node = cgroup::parse_cpu_list(value).unwrap_or(0),
+                "GRES" if on_this_node => {
+                    if let Some(indices) = gres_gpu_indices(value) {
+                        facts.gpu_indices = indices;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        facts.owner_uid = owner.ok_or_else(|| anyhow!("No UserId in scontrol output"))?;
+        Ok(facts)
+    }
+}
+
+// 节点列表可能是 "node[01-04]" 这样的压缩形式, 交给 scontrol 展开
+fn node_list_contains(node_list: &str, hostname: &str) -> bool {
+    if node_list == hostname {
+        return true;
+    }
+    if !node_list.contains('[') && !node_list.contains(',') {
+        return false;
+    }
+    match Command::new("scontrol").args(["show", "hostnames", node_list]).output() {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|h| h.trim() == hostname),
+        _ => false,
+    }
+}
+
+// 解析 "gpu:a100:2(IDX:0-1)" 或 "gpu:1(IDX:3),gpu:v100:1(IDX:5)" 中的 GPU 编号, 展开为 "0,1"
+fn gres_gpu_indices(gres: &str) -> Option<String> {
+    let mut indices = Vec::new();
+    let mut rest = gres;
+    while let Some(start) = rest.find("gpu") {
+        rest = &rest[start..];
+        let open = rest.find("(IDX:")?;
+        let close = open + rest[open..].find(')')?;
+        for part in rest[open + "(IDX:".len()..close].split(',') {
+            match part.split_once('-') {
+                Some((lo, hi)) => {
+                    let (lo, hi): (u32, u32) = (lo.parse().ok()?, hi.parse().ok()?);
+                    indices.extend((lo..=hi).map(|i| i.to_string()));
+                }
+                None if part.is_empty() || part == "N/A" => {}
+                None => indices.push(part.parse::<u32>().ok()?.to_string()),
+            }
+        }
+        rest = &rest[close..];
+    }
+    (!indices.is_empty()).then(|| indices.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use sampler::gpu::BackendKind;
+
+    use super::*;
+
+    const FAKE_GPUS: &str = r#"{
+        "devices": [
+            { "index": "0", "uuid": "GPU-0", "name": "NVIDIA A10", "memory_total_mib": 1000 },
+            { "index": "1", "uuid": "GPU-1", "name": "NVIDIA A10", "memory_total_mib": 1000 }
+        ],
+        "frames": [
+            [
+                { "gpu_uuid": "GPU-0", "pid": 100, "sm_utilization": 80, "used_memory_mib": 500 },
+                { "gpu_uuid": "GPU-1", "pid": 200, "sm_utilization": 90, "used_memory_mib": 900 }
+            ],
+            [
+                { "gpu_uuid": "GPU-0", "pid": 100, "sm_utilization": 50, "used_memory_mib": 500 },
+                { "gpu_uuid": "GPU-1", "pid": 300, "sm_utilization": 90, "used_memory_mib": 900 }
+            ],
+            []
+        ]
+    }"#;
+
+    fn membership(pid: u32) -> ProcessMembership {
+        match pid {
+            100 => ProcessMembership::InJob,
+            200 => ProcessMembership::NotInJob,
+            _ => ProcessMembership::Unknown,
+        }
+    }
+
+    // 在临时目录中伪造一个 cgroup v2 任务目录和 fake 后端的数据文件
+    fn fake_job(name: &str) -> (PathBuf, SampledJob, Box<dyn GpuBackend>) {
+        let dir = std::env::temp_dir().join(format!("node_monitor_sampling_{}_{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("cpu.stat"), "usage_usec 1000\n").unwrap();
+        fs::write(dir.join("cpuset.cpus.effective"), "0-1\n").unwrap();
+        fs::write(dir.join("gpus.json"), FAKE_GPUS).unwrap();
+
+        let mut backend = gpu::create_backend(BackendKind::Fake, Some(&dir.join("gpus.json"))).unwrap();
+        let devices = backend.devices("0,1").unwrap();
+        let cpu = JobCpuSampler::from_cgroup(JobCgroup::V2(dir.clone())).unwrap();
+        (dir, SampledJob { cpu, devices }, backend)
+    }
+
+    fn advance_cpu(dir: &Path, usage_usec: u64) {
+        fs::write(dir.join("cpu.stat"), format!("usage_usec {}\n", usage_usec)).unwrap();
+    }
+
+    #[test]
+    fn samples_fake_gpus_through_the_daemon_path() {
+        let (dir, mut job, mut backend) = fake_job("gpus");
+
+        // 第一轮只记录 CPU 计数器
+        assert!(sample_job("42", &mut job, Some(&mut backend), membership).is_none());
+
+        advance_cpu(&dir, 2000);
+        let metrics = sample_job("42", &mut job, Some(&mut backend), membership).unwrap();
+        assert_eq!(metrics.job_id, "42");
+        assert!(!metrics.gpu_unknown);
+        assert_eq!((metrics.gpu_utilization, metrics.gpu_memory_utilization), (40.0, 25.0));
+        let per_gpu: Vec<(&str, f64)> = metrics.gpus.iter().map(|g| (g.uuid.as_str(), g.utilization)).collect();
+        assert_eq!(per_gpu, [("GPU-0", 80.0), ("GPU-1", 0.0)]);
+
+        // GPU-1 上只有无法判断归属的进程
+        advance_cpu(&dir, 3000);
+        let metrics = sample_job("42", &mut job, Some(&mut backend), membership).unwrap();
+        assert!(metrics.gpu_unknown);
+        let per_gpu: Vec<&str> = metrics.gpus.iter().map(|g| g.uuid.as_str()).collect();
+        assert_eq!(per_gpu, ["GPU-0"]);
+
+        advance_cpu(&dir, 4000);
+        let metrics = sample_job("42", &mut job, Some(&mut backend), membership).unwrap();
+        assert!(!metrics.gpu_unknown);
+        assert_eq!((metrics.gpu_utilization, metrics.gpu_memory_utilization), (0.0, 0.0));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skips_gpu_jobs_without_a_backend() {
+        let (dir, mut job, _) = fake_job("no_backend");
+        assert!(sample_job("42", &mut job, None, membership).is_none());
+        advance_cpu(&dir, 2000);
+        assert!(sample_job("42", &mut job, None, membership).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expands_gres_gpu_indices() {
+        assert_eq!(gres_gpu_indices("gpu:a100:2(IDX:0-1)").as_deref(), Some("0,1"));
+        assert_eq!(gres_gpu_indices("gpu:4(IDX:0,2-3,7)").as_deref(), Some("0,2,3,7"));
+        assert_eq!(
+            gres_gpu_indices("gpu:1(IDX:3),gpu:v100:1(IDX:5)").as_deref(),
+            Some("3,5")
+        );
+        // 其他 GRES 不影响 GPU 编号
+        assert_eq!(gres_gpu_indices("gpu:1(IDX:2),shard:0(0/8,0/8)").as_deref(), Some("2"));
+        assert_eq!(gres_gpu_indices("gpu:0(IDX:N/A)"), None);
+        assert_eq!(gres_gpu_indices("(null)"), None);
+        assert_eq!(gres_gpu_indices(""), None);
+        // 格式不认识时不猜测
+        assert_eq!(gres_gpu_indices("gpu:2"), None);
+        assert_eq!(gres_gpu_indices("gpu:2(IDX:x-1)"), None);
+    }
+
+    #[test]
+    fn parses_scontrol_job_details() {
+        let line = "JobId=77 JobName=train UserId=alice(1500) GroupId=alice(1500) JobState=RUNNING \
+                    Partition=gpu QOS=normal Account=lab NumNodes=2 \
+                    Nodes=node01 CPU_IDs=0-3 Mem=0 GRES=gpu:a100:2(IDX:0-1) \
+                    Nodes=node02 CPU_IDs=4-7 Mem=0 GRES=gpu:a100:2(IDX:2-3)";
+        let facts = JobFacts::parse(line, "node02").unwrap();
+        assert_eq!(facts.owner_uid, 1500);
+        assert_eq!(
+            (facts.partition.as_str(), facts.qos.as_str(), facts.account.as_str()),
+            ("gpu", "normal", "lab")
+        );
+        assert_eq!(facts.job_state, "RUNNING");
+        // 只取本节点那一组的 GPU
+        assert_eq!(facts.gpu_indices, "2,3");
+        assert_eq!(facts.cpus_on_node, 4);
+
+        let facts = JobFacts::parse(line, "node03").unwrap();
+        assert_eq!(facts.gpu_indices, "");
+        assert_eq!(facts.cpus_on_node, 0);
+    }
+
+    #[test]
+    fn job_details_need_an_owner() {
+        assert!(JobFacts::parse("JobId=77 JobState=RUNNING Partition=gpu", "node01").is_err());
+        assert!(JobFacts::parse("JobId=77 UserId=alice JobState=RUNNING", "node01").is_err());
+        assert_eq!(JobFacts::parse("UserId=root(0)", "node01").unwrap().job_state, "");
+    }
+
+    #[test]
+    fn cpu_only_jobs_report_zero_gpu_usage() {
+        let (dir, mut job, _) = fake_job("cpu_only");
+        job.devices.clear();
+        assert!(sample_job("42", &mut job, None, membership).is_none());
+        advance_cpu(&dir, 2000);
+        let metrics = sample_job("42", &mut job, None, membership).unwrap();
+        assert!(metrics.gpus.is_empty() && !metrics.gpu_unknown);
+        assert_eq!(metrics.gpu_utilization, 0.0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::fs;
+use tokio::time::{self, Instant};
+
+use crate::tracker::SharedTracker;
+use crate::{JobInfo, SharedConfig, log_to_job_file};
+
+// ============================================================================
+// 状态快照 (State Snapshot)
+// ============================================================================
+
+// 快照格式版本, 格式不兼容地修改时递增; 其他版本的快照不恢复
+const STATE_VERSION: u64 = 1;
+
+// 任务逐个解析, 无法恢复的任务单独丢弃
+#[derive(Deserialize)]
+struct Snapshot {
+    version: u64,
+    saved_at: DateTime<Utc>,
+    #[serde(default)]
+    enforcement_paused: bool,
+    jobs: HashMap<String, Value>,
+}
+
+// 守护进程定期 (以及任务注册/移除时) 把 JobTracker 写入快照文件,
+// 写入时先写临时文件再 rename, 避免崩溃时留下半个文件
+pub async fn run_state_persister(tracker: SharedTracker, config: SharedConfig) {
+    let changed = match tracker.with(|tracker| tracker.state_changed.clone()).await {
+        Ok(changed) => changed,
+        Err(e) => {
+            error!("Job state will not be persisted: {:#}", e);
+            return;
+        }
+    };
+    loop {
+        let (state_path, snapshot_interval) = {
+            let config_lock = config.read().await;
+            (config_lock.state_path.clone(), config_lock.state_snapshot_interval)
+        };
+
+        tokio::select! {
+            _ = time::sleep(snapshot_interval) => {}
+            _ = changed.notified() => {}
+        }
+
+        let bytes = tracker
+            .with(|tracker| encode_snapshot(tracker.enforcement_paused, &tracker.jobs))
+            .await;
+        let bytes = match bytes {
+            Ok(Ok(b)) => b,
+            Ok(Err(e)) => {
+                error!("Failed to serialize job state: {}", e);
+                continue;
+            }
+            Err(e) => {
+                error!("Failed to read job state for the snapshot: {:#}", e);
+                continue;
+            }
+        };
+
+        if let Err(e) = write_atomic(&state_path, &bytes).await {
+            error!("Failed to persist job state to {}: {:#}", state_path.display(), e);
+        }
+    }
+}
+
+// 与 Snapshot 字段一致, 只借用任务表以免在状态任务中克隆
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u64,
+    saved_at: DateTime<Utc>,
+    enforcement_paused: bool,
+    jobs: &'a HashMap<String, JobInfo>,
+}
+
+fn encode_snapshot(enforcement_paused: bool, jobs: &HashMap<String, JobInfo>) -> serde_json::Result<Vec<u8>> {
+    serde_json::to_vec(&SnapshotRef {
+        version: STATE_VERSION,
+        saved_at: Utc::now(),
+        enforcement_paused,
+        jobs,
+    })
+}
+
+fn parse_snapshot(content: &[u8]) -> Result<Snapshot> {
+    let snapshot: Snapshot = serde_json::from_slice(content)?;
+    if snapshot.version != STATE_VERSION {
+        bail!(
+            "unsupported version {}, this node monitor reads version {}",
+            snapshot.version,
+            STATE_VERSION
+        );
+    }
+    Ok(snapshot)
+}
+
+pub async fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create state directory {}", parent.display()))?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, bytes)
+        .await
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path)
+        .await
+        .with_context(|| format!("Failed to rename {} to {}", tmp_path.display(), path.display()))?;
+    Ok(())
+}
+
+// ============================================================================
+// 启动时恢复 (Restore on Startup)
+// ============================================================================
+
+// 读取快照并与节点上实际运行的任务对账, 已结束的任务直接丢弃
+pub async fn restore_state(tracker: &SharedTracker, state_path: &Path, heartbeat_timeout: Duration) {
+    let content = match fs::read(state_path).await {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("No saved job state at {}, starting empty.", state_path.display());
+            return;
+        }
+        Err(e) => {
+            error!("Failed to read saved job state {}: {}", state_path.display(), e);
+            return;
+        }
+    };
+    let snapshot = match parse_snapshot(&content) {
+        Ok(s) => s,
+        Err(e) => {
+            error!(
+                "Cannot restore saved job state {}, ignoring it: {:#}",
+                state_path.display(),
+                e
+            );
+            return;
+        }
+    };
+
+    let running_jobs = match list_running_jobs().await {
+        Ok(jobs) => Some(jobs),
+        Err(e) => {
+            warn!(
+                "Could not query running jobs, restoring all saved jobs and relying on heartbeat timeout: {:#}",
+                e
+            );
+            None
+        }
+    };
+
+    if snapshot.enforcement_paused {
+        warn!("Enforcement was paused before the restart and stays paused.");
+    }
+    let mut restored = Vec::new();
+    for (job_id, job) in snapshot.jobs {
+        let mut job: JobInfo = match serde_json::from_value(job) {
+            Ok(job) => job,
+            Err(e) => {
+                error!("Dropping unreadable saved state for job {}: {}", job_id, e);
+                continue;
+            }
+        };
+        if running_jobs.as_ref().is_some_and(|running| !running.contains(&job_id)) {
+            info!(
+                "Dropping saved state for job {}: no longer running on this node.",
+                job_id
+            );
+            continue;
+        }
+
+        // 守护进程停机期间不计入心跳间隔, 只保留停机前已经过去的时间
+        let heartbeat_age = (snapshot.saved_at - job.last_heartbeat_at).to_std().unwrap_or_default();
+        job.last_heartbeat = Instant::now().checked_sub(heartbeat_age).unwrap_or_else(Instant::now);
+
+        info!(
+            "Restored job {} (registered at {}, {} metrics received, last heartbeat at {}).",
+            job_id, job.registered_at, job.metrics_received, job.last_heartbeat_at
+        );
+        restored.push((job_id, job));
+    }
+
+    let log_paths: Vec<(u32, PathBuf)> = restored
+        .iter()
+        .map(|(_, job)| (job.owner_uid, job.log_path.clone()))
+        .collect();
+    let enforcement_paused = snapshot.enforcement_paused;
+    let inserted = tracker
+        .with(move |tracker| {
+            tracker.enforcement_paused = enforcement_paused;
+            for (job_id, job) in restored {
+                tracker.insert_job(job_id, job, heartbeat_timeout);
+            }
+            tracker.mark_changed();
+        })
+        .await;
+    if let Err(e) = inserted {
+        error!("Failed to restore saved job state: {:#}", e);
+        return;
+    }
+    for (owner_uid, log_path) in log_paths {
+        log_to_job_file(
+            owner_uid,
+            &log_path,
+            "Monitoring state restored after node monitor restart",
+        )
+        .await;
+    }
+}
+
+async fn list_running_jobs() -> Result<HashSet<String>> {
+    let mut cmd = tokio::process::Command::new("squeue");
+    cmd.args(["--noheader", "--format=%A", "--node=localhost"]);
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+    let output = cmd.output().await.context("Failed to execute 'squeue'")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "'squeue' failed with status {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use protocol::SupervisedMonitor;
+    use serde_json::json;
+
+    use crate::MetricsSource;
+
+    const SNAPSHOT: &str = r#"{
+        "version": 1,
+        "saved_at": "2026-10-17T08:30:00Z",
+        "enforcement_paused": true,
+        "jobs": {"42": {
+            "last_heartbeat_at": "2026-10-17T08:29:00Z",
+            "registered_at": "2026-10-17T08:00:00Z",
+            "owner_uid": 1500,
+            "partition": "gpu",
+            "gpu_utilizations": {
+                "length_secs": 3600,
+                "samples": [["2026-10-17T08:28:00Z", 4.0], ["2026-10-17T08:29:00Z", 6.0]],
+                "covered_since": "2026-10-17T08:10:00Z"
+            },
+            "gpu_memory_utilizations": null,
+            "cpu_utilizations": null,
+            "gpu_device_utilizations": {
+                "GPU-1": {"length_secs": 3600, "samples": [["2026-10-17T08:29:00Z", 6.0]], "covered_since": null}
+            },
+            "metrics_received": 2,
+            "log_path": "/home/alice/.slurm/info-42.log",
+            "escalation": {"stage": "warned", "since": "2026-10-17T08:20:00Z"},
+            "source": "daemon"
+        }}
+    }"#;
+
+    fn restore_job(content: &str) -> (Snapshot, JobInfo) {
+        let mut snapshot = parse_snapshot(content.as_bytes()).unwrap();
+        let job = snapshot.jobs.remove("42").expect("job 42 missing");
+        (snapshot, serde_json::from_value(job).unwrap())
+    }
+
+    #[test]
+    fn restores_saved_jobs_with_their_windows() {
+        let (snapshot, job) = restore_job(SNAPSHOT);
+        assert!(snapshot.enforcement_paused);
+        assert_eq!((job.owner_uid, job.partition.as_str()), (1500, "gpu"));
+        assert_eq!(job.source, MetricsSource::Daemon);
+        assert_eq!(job.escalation.stage(), "warned");
+        let window = job.gpu_utilizations.unwrap();
+        assert_eq!(window.length(), Duration::from_secs(3600));
+        assert_eq!(window.iter().collect::<Vec<_>>(), [4.0, 6.0]);
+        assert_eq!(window.covered(), Duration::from_secs(19 * 60));
+        assert!(job.cpu_utilizations.is_none());
+        assert_eq!(job.gpu_device_utilizations["GPU-1"].max(), Some(6.0));
+    }
+
+    #[test]
+    fn saved_state_round_trips() {
+        let (_, mut job) = restore_job(SNAPSHOT);
+        // 守护进程重启后据此继续看护监控进程
+        job.supervised_monitor = Some(SupervisedMonitor {
+            pid: Some(4321),
+            restarts: 2,
+            last_exit: Some("exit status: 1".to_string()),
+            last_exit_at: None,
+        });
+        let jobs = HashMap::from([("42".to_string(), job)]);
+        let bytes = encode_snapshot(true, &jobs).unwrap();
+        let saved: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(saved["version"], json!(STATE_VERSION));
+
+        let restored = parse_snapshot(&bytes).unwrap();
+        assert!(restored.enforcement_paused);
+        assert_eq!(restored.jobs["42"], serde_json::to_value(&jobs["42"]).unwrap());
+        let job: JobInfo = serde_json::from_value(restored.jobs["42"].clone()).unwrap();
+        assert_eq!(job.supervised_monitor, jobs["42"].supervised_monitor);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        for version in ["", r#""version": 0,"#, r#""version": 2,"#] {
+            let content = format!(r#"{{{} "saved_at": "2026-10-17T08:30:00Z", "jobs": {{}}}}"#, version);
+            assert!(parse_snapshot(content.as_bytes()).is_err(), "{}", content);
+        }
+        let Err(e) = parse_snapshot(br#"{"version": 2, "saved_at": "2026-10-17T08:30:00Z", "jobs": {}}"#) else {
+            panic!("version 2 accepted");
+        };
+        assert!(e.to_string().contains("unsupported version 2"), "{}", e);
+    }
+
+    #[test]
+    fn rejects_snapshots_that_are_not_json() {
+        assert!(parse_snapshot(b"{\"version\": 1, \"saved_at\": \"2026-10-17T08:30:00Z\", \"jobs\": {").is_err());
+        assert!(parse_snapshot(b"[]").is_err());
+    }
+}
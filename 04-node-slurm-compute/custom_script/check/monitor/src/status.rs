@@ -0,0 +1,106 @@
+use chrono::Utc;
+use protocol::{MetricStatus, StatusResponse};
+
+use crate::JobInfo;
+use crate::config::Config;
+use crate::window::SlidingWindow;
+
+// ============================================================================
+// 状态查询 (Status Query)
+// ============================================================================
+
+pub fn job_status(job_id: &str, job: &JobInfo, config: &Config, enforcement_paused: bool) -> StatusResponse {
+    let now = Utc::now();
+    let buffer_end = job.registered_at + config.buffer_period;
+    let until_idle = |window: &SlidingWindow, threshold| {
+        let idle_at = window.idle_at(threshold, now, buffer_end, config.max_sample_gap);
+        ((idle_at - now).num_milliseconds().max(0) as u64).div_ceil(1000)
+    };
+    let metric_status = |name: &str, window: Option<&SlidingWindow>, threshold| {
+        metric_status(name, window, threshold, window.map(|w| until_idle(w, threshold)))
+    };
+    let metrics = vec![
+        metric_status(
+            "gpu_utilization",
+            job.gpu_utilizations.as_ref(),
+            config.gpu_utilization_threshold,
+        ),
+        metric_status(
+            "gpu_memory_utilization",
+            job.gpu_memory_utilizations.as_ref(),
+            config.gpu_memory_utilization_threshold,
+        ),
+        metric_status(
+            "cpu_utilization",
+            job.cpu_utilizations.as_ref(),
+            config.cpu_utilization_threshold,
+        ),
+    ];
+    let gpus = job
+        .gpu_device_utilizations
+        .iter()
+        .map(|(uuid, window)| metric_status(uuid, Some(window), config.gpu_utilization_threshold))
+        .collect();
+    let closest_to_firing = metrics
+        .iter()
+        .filter_map(|m| m.secs_until_idle.map(|n| (n, &m.name)))
+        .min_by_key(|(n, _)| *n)
+        .map(|(_, name)| name.clone());
+
+    StatusResponse {
+        status: "ok".to_string(),
+        job_id: job_id.to_string(),
+        owner_uid: job.owner_uid,
+        partition: job.partition.clone(),
+        qos: job.qos.clone(),
+        account: job.account.clone(),
+        registered_at: job.registered_at,
+        last_heartbeat_at: job.last_heartbeat_at,
+        metrics_received: job.metrics_received,
+        buffer_remaining_secs: (buffer_end - now).num_seconds().max(0) as u64,
+        enforcement_mode: config.enforcement_for(&job.partition),
+        enforcement_paused,
+        escalation: job.escalation,
+        exemption: job.exemption,
+        monitor_lost: job.monitor_lost,
+        supervised_monitor: job.supervised_monitor.clone(),
+        metrics,
+        gpus,
+        idle_gpus: job.idle_gpus(config.gpu_utilization_threshold),
+        min_idle_gpus: config.policy.idle_gpus.min_idle_gpus,
+        closest_to_firing,
+    }
+}
+
+fn metric_status(
+    name: &str,
+    window: Option<&SlidingWindow>,
+    threshold: f64,
+    secs_until_idle: Option<u64>,
+) -> MetricStatus {
+    let Some(window) = window else {
+        return MetricStatus {
+            name: name.to_string(),
+            enforced: false,
+            threshold,
+            window_secs: 0,
+            covered_secs: 0,
+            window: Vec::new(),
+            max: None,
+            mean: None,
+            secs_until_idle: None,
+        };
+    };
+
+    MetricStatus {
+        name: name.to_string(),
+        enforced: true,
+        threshold,
+        window_secs: window.length().as_secs(),
+        covered_secs: window.covered().as_secs(),
+        window: window.iter().collect(),
+        max: window.max(),
+        mean: window.mean(),
+        secs_until_idle,
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info, warn};
+use protocol::SupervisedMonitor;
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
+
+use crate::exporter::SharedStats;
+use crate::respawn::{self, MonitorProcess};
+use crate::tracker::SharedTracker;
+use crate::{SharedConfig, log_to_job_file};
+
+// ============================================================================
+// 数据结构定义 (Data Structures)
+// ============================================================================
+
+pub type SharedSupervisor = Arc<Supervisor>;
+
+// 守护进程启动并看护的 job_helper monitor: 每个任务一个看护任务, 负责启动、在退出后按退避重启,
+// 并在任务停止跟踪时结束监控进程
+pub struct Supervisor {
+    jobs: Mutex<SupervisedJobs>,
+    tracker: SharedTracker,
+    config: SharedConfig,
+    stats: SharedStats,
+}
+
+#[derive(Default)]
+struct SupervisedJobs {
+    // 任务号 -> (看护任务的编号, 请求通道); 请求通道被丢弃时看护任务停止监控进程并退出
+    by_job: HashMap<String, (u64, mpsc::UnboundedSender<Restart>)>,
+    next_id: u64,
+}
+
+// 要求看护任务立即替换监控进程, 附带原因
+struct Restart(String);
+
+#[derive(Clone)]
+struct MonitorJob {
+    job_id: String,
+    owner_uid: u32,
+    log_path: PathBuf,
+}
+
+// ============================================================================
+// 看护 (Supervision)
+// ============================================================================
+
+impl Supervisor {
+    pub fn new(tracker: SharedTracker, config: SharedConfig, stats: SharedStats) -> SharedSupervisor {
+        Arc::new(Self {
+            jobs: Mutex::new(SupervisedJobs::default()),
+            tracker,
+            config,
+            stats,
+        })
+    }
+
+    // 开始看护任务的 job_helper monitor 并立即启动它; 已在看护时不做任何事
+    pub fn start(self: &Arc<Self>, job_id: &str, owner_uid: u32, log_path: &Path) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if jobs.by_job.contains_key(job_id) {
+            return;
+        }
+        self.spawn_task(&mut jobs, job_id, owner_uid, log_path, None, None);
+    }
+
+    // 守护进程重启后继续看护重启前启动的监控进程: 它仍在运行时接管它, 否则立即重新启动
+    pub fn adopt(self: &Arc<Self>, job_id: &str, owner_uid: u32, log_path: &Path, status: SupervisedMonitor) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if jobs.by_job.contains_key(job_id) {
+            return;
+        }
+        self.spawn_task(&mut jobs, job_id, owner_uid, log_path, None, Some(status));
+    }
+
+    // 守护进程重启后处理快照中由它看护的监控进程: supervise_monitors 仍开启时继续看护, 否则不再显示其状态
+    pub async fn resume(self: &Arc<Self>) {
+        let supervise = self.config.read().await.supervise_monitors;
+        let supervised = match self
+            .tracker
+            .with(move |tracker| {
+                let mut supervised = Vec::new();
+                for (job_id, job) in tracker.jobs.iter_mut() {
+                    if !supervise {
+                        job.supervised_monitor = None;
+                        continue;
+                    }
+                    let Some(status) = job.supervised_monitor.clone() else {
+                        continue;
+                    };
+                    supervised.push((job_id.clone(), job.owner_uid, job.log_path.clone(), status));
+                }
+                supervised
+            })
+            .await
+        {
+            Ok(supervised) => supervised,
+            Err(e) => {
+                error!("Failed to resume supervising job_helper monitors: {:#}", e);
+                return;
+            }
+        };
+        for (job_id, owner_uid, log_path, status) in supervised {
+            self.adopt(&job_id, owner_uid, &log_path, status);
+        }
+    }
+
+    // 替换失去连接的监控进程; 尚未看护的任务 (由 task prolog 启动, 或在守护进程重启前启动) 从此开始看护
+    pub fn respawn(self: &Arc<Self>, job_id: &str, owner_uid: u32, log_path: &Path, reason: &str) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let restarted = jobs
+            .by_job
+            .get(job_id)
+            .is_some_and(|(_, requests)| requests.send(Restart(reason.to_string())).is_ok());
+        if restarted {
+            return;
+        }
+        self.spawn_task(&mut jobs, job_id, owner_uid, log_path, Some(reason.to_string()), None);
+    }
+
+    // 任务已停止跟踪: 丢弃请求通道, 看护任务随之结束监控进程
+    pub fn stop(&self, job_id: &str) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.by_job.remove(job_id);
+    }
+
+    fn spawn_task(
+        self: &Arc<Self>,
+        jobs: &mut SupervisedJobs,
+        job_id: &str,
+        owner_uid: u32,
+        log_path: &Path,
+        reason: Option<String>,
+        adopted: Option<SupervisedMonitor>,
+    ) {
+        let (requests, receiver) = mpsc::unbounded_channel();
+        jobs.next_id += 1;
+        jobs.by_job.insert(job_id.to_string(), (jobs.next_id, requests));
+        let job = MonitorJob {
+            job_id: job_id.to_string(),
+            owner_uid,
+            log_path: log_path.to_path_buf(),
+        };
+        tokio::spawn(self.clone().supervise(job, jobs.next_id, receiver, reason, adopted));
+    }
+
+    // 看护任务自行结束时移除自己的记录, 不影响之后为同一任务新建的看护任务
+    fn forget(&self, job_id: &str, id: u64) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if jobs.by_job.get(job_id).is_some_and(|(current, _)| *current == id) {
+            jobs.by_job.remove(job_id);
+        }
+    }
+
+    // reason 为 None 表示第一次启动, 否则是重启的原因; adopted 为守护进程重启前记录的监控进程状态
+    async fn supervise(
+        self: Arc<Self>,
+        job: MonitorJob,
+        id: u64,
+        mut requests: mpsc::UnboundedReceiver<Restart>,
+        mut reason: Option<String>,
+        adopted: Option<SupervisedMonitor>,
+    ) {
+        let mut status = SupervisedMonitor {
+            pid: None,
+            restarts: 0,
+            last_exit: None,
+            last_exit_at: None,
+        };
+        // 重启前的进程仍在任务的 cgroup 中运行时直接接管, 否则按重启处理
+        let mut adopted_pid = None;
+        if let Some(previous) = adopted {
+            adopted_pid = previous.pid.filter(|pid| respawn::is_job_process(*pid, &job.job_id));
+            if adopted_pid.is_none() {
+                reason = Some("the previous one did not survive the node monitor restart".to_string());
+            }
+            status = SupervisedMonitor { pid: None, ..previous };
+        }
+        // 连续退出的次数, 决定下一次重启前等待多久
+        let mut failures = 0;
+
+        loop {
+            let (job_helper, gpu_backend, gpu_fake_file, restart_initial, restart_max) = {
+                let config = self.config.read().await;
+                (
+                    config.job_helper_path.clone(),
+                    config.daemon_gpu_backend,
+                    config.daemon_gpu_fake_file.clone(),
+                    config.monitor_restart_initial,
+                    config.monitor_restart_max,
+                )
+            };
+            if reason.is_some() {
+                status.restarts += 1;
+                self.stats.record_monitor_respawn();
+            }
+
+            let started_at = Instant::now();
+            let process = match adopted_pid.take() {
+                Some(pid) => Ok(Ok(MonitorProcess::Adopted {
+                    pid,
+                    job_id: job.job_id.clone(),
+                })),
+                None => respawn::spawn_monitor(
+                    &job_helper,
+                    gpu_backend,
+                    gpu_fake_file.as_deref(),
+                    &job.job_id,
+                    job.owner_uid,
+                    &job.log_path,
+                )
+                .await
+                .map(|launched| launched.map(MonitorProcess::Child)),
+            };
+            let exit = match process {
+                Ok(Err(state)) => {
+                    self.log(
+                        &job,
+                        format!(
+                            "Not starting the job_helper monitor of job {}: the job is {}.",
+                            job.job_id, state
+                        ),
+                    )
+                    .await;
+                    break;
+                }
+                Err(e) => format!("could not be started: {:#}", e),
+                Ok(Ok(mut child)) => {
+                    status.pid = child.id();
+                    self.record(&job.job_id, Some(&status)).await;
+                    let message = match &reason {
+                        _ if matches!(child, MonitorProcess::Adopted { .. }) => format!(
+                            "Resumed supervising the job_helper monitor of job {} (PID {}) after the node monitor restarted.",
+                            job.job_id,
+                            format_pid(status.pid)
+                        ),
+                        Some(reason) => format!(
+                            "Restarted the job_helper monitor of job {} (PID {}, restart {}) because {}.",
+                            job.job_id,
+                            format_pid(status.pid),
+                            status.restarts,
+                            reason
+                        ),
+                        None => format!(
+                            "Started the job_helper monitor of job {} (PID {}).",
+                            job.job_id,
+                            format_pid(status.pid)
+                        ),
+                    };
+                    self.log(&job, message).await;
+
+                    let request = tokio::select! {
+                        exit_status = child.wait() => Err(exit_status),
+                        request = requests.recv() => Ok(request),
+                    };
+                    match request {
+                        Err(exit_status) => {
+                            let exit = describe_exit(&exit_status);
+                            status.last_exit = Some(exit.clone());
+                            status.last_exit_at = Some(Utc::now());
+                            format!("(PID {}) exited with {}", format_pid(status.pid), exit)
+                        }
+                        Ok(request) => {
+                            let why = match &request {
+                                Some(Restart(r)) => r.clone(),
+                                None => "the job is no longer tracked".to_string(),
+                            };
+                            child.stop().await;
+                            self.log(
+                                &job,
+                                format!(
+                                    "Stopped the job_helper monitor of job {} (PID {}) because {}.",
+                                    job.job_id,
+                                    format_pid(status.pid),
+                                    why
+                                ),
+                            )
+                            .await;
+                            status.pid = None;
+                            match request {
+                                Some(Restart(r)) => {
+                                    reason = Some(r);
+                                    continue;
+                                }
+                                None => break,
+                            }
+                        }
+                    }
+                }
+            };
+
+            let delay;
+            (delay, failures) = restart_backoff(failures, started_at.elapsed(), restart_initial, restart_max);
+            status.pid = None;
+            self.record(&job.job_id, Some(&status)).await;
+            let message = format!(
+                "The job_helper monitor of job {} {}. Restarting it in {} seconds.",
+                job.job_id,
+                exit,
+                delay.as_secs()
+            );
+            warn!("{}", message);
+            log_to_job_file(job.owner_uid, &job.log_path, &message).await;
+
+            tokio::select! {
+                _ = time::sleep(delay) => reason = Some(format!("the previous one {}", exit)),
+                request = requests.recv() => match request {
+                    Some(Restart(r)) => reason = Some(r),
+                    None => break,
+                },
+            }
+        }
+
+        self.forget(&job.job_id, id);
+        self.record(&job.job_id, None).await;
+    }
+
+    // 把监控进程的状态写回 JobTracker, 供 STATUS 和 `node_monitor ctl show` 显示并随快照保存; None 表示不再看护
+    async fn record(&self, job_id: &str, status: Option<&SupervisedMonitor>) {
+        let job_id = job_id.to_string();
+        let status = status.cloned();
+        let recorded = self
+            .tracker
+            .with(move |tracker| {
+                if let Some(job) = tracker.jobs.get_mut(&job_id) {
+                    job.supervised_monitor = status;
+                    tracker.mark_changed();
+                }
+            })
+            .await;
+        if let Err(e) = recorded {
+            error!("Failed to record the job_helper monitor status: {:#}", e);
+        }
+    }
+
+    async fn log(&self, job: &MonitorJob, message: String) {
+        info!("{}", message);
+        log_to_job_file(job.owner_uid, &job.log_path, &message).await;
+    }
+}
+
+// 任务停止跟踪 (任务结束、被取消或被管理员移除) 时停止它的监控进程
+pub async fn run_supervisor(supervisor: SharedSupervisor, mut removed_jobs: mpsc::UnboundedReceiver<String>) {
+    while let Some(job_id) = removed_jobs.recv().await {
+        supervisor.stop(&job_id);
+    }
+}
+
+// 监控进程运行 ran_for 后退出: 返回重启前的等待时间和新的连续退出次数. 等待时间从 initial 起每次翻倍, 不超过 max;
+// 运行了足够长的时间才退出, 说明不是在反复崩溃, 从 initial 重新开始
+fn restart_backoff(failures: u32, ran_for: Duration, initial: Duration, max: Duration) -> (Duration, u32) {
+    let failures = if ran_for >= max { 0 } else { failures };
+    let delay = initial.saturating_mul(2u32.saturating_pow(failures)).min(max);
+    (delay, failures.saturating_add(1))
+}
+
+fn describe_exit(status: &io::Result<ExitStatus>) -> String {
+    match status {
+        Ok(status) => status.to_string(),
+        Err(e) => format!("unknown status ({})", e),
+    }
+}
+
+fn format_pid(pid: Option<u32>) -> String {
+    pid.map_or_else(|| "unknown".to_string(), |pid| pid.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INITIAL: Duration = Duration::from_secs(5);
+    const MAX: Duration = Duration::from_secs(300);
+
+    #[test]
+    fn restarts_back_off_exponentially_up_to_the_maximum() {
+        let quick = Duration::from_secs(1);
+        let mut failures = 0;
+        let mut delays = Vec::new();
+        for _ in 0..8 {
+            let delay;
+            (delay, failures) = restart_backoff(failures, quick, INITIAL, MAX);
+            delays.push(delay.as_secs());
+        }
+        assert_eq!(delays, [5, 10, 20, 40, 80, 160, 300, 300]);
+        assert_eq!(failures, 8);
+    }
+
+    #[test]
+    fn a_long_run_resets_the_backoff() {
+        assert_eq!(restart_backoff(6, MAX, INITIAL, MAX), (INITIAL, 1));
+        assert_eq!(restart_backoff(6, MAX - Duration::from_secs(1), INITIAL, MAX), (MAX, 7));
+    }
+
+    #[test]
+    fn many_failures_do_not_overflow() {
+        assert_eq!(restart_backoff(u32::MAX, Duration::ZERO, INITIAL, MAX), (MAX, u32::MAX));
+    }
+}
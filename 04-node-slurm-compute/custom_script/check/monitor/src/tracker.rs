@@ -0,0 +1,251 @@
+use std::collections::{BTreeSet, HashMap};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use log::error;
+use tokio::sync::{Notify, mpsc, oneshot};
+use tokio::time::{self, Instant};
+
+use crate::{JobInfo, MetricsSource};
+
+// ============================================================================
+// 常量定义 (Constants)
+// ============================================================================
+
+// 等待状态任务处理的请求数上限, 超出时调用方等待
+const COMMAND_QUEUE_CAPACITY: usize = 1024;
+
+// ============================================================================
+// 任务状态 (Job State)
+// ============================================================================
+
+// 只由状态任务持有; 其他任务通过 SharedTracker 提交同步的闭包来读写,
+// 写任务日志、scancel 等 I/O 都在状态任务之外执行, 慢的家目录不会拖住整个节点
+#[derive(Default)]
+pub struct JobTracker {
+    pub jobs: HashMap<String, JobInfo>,
+    // 管理员暂停了整个节点的取消操作
+    pub enforcement_paused: bool,
+    // 任务增删时通知状态持久化任务立即写快照
+    pub state_changed: Arc<Notify>,
+    // 停止跟踪的任务号, 由 job_helper monitor 的看护任务据此停止该任务的监控进程
+    pub removed_jobs: Option<mpsc::UnboundedSender<String>>,
+    heartbeat_deadlines: Deadlines,
+}
+
+impl JobTracker {
+    // 由 job_helper 上报的任务按最后一次心跳 (monitor lost 状态下按等待重连的截止时间) 设置定时器
+    pub fn insert_job(&mut self, job_id: String, job: JobInfo, heartbeat_timeout: Duration) {
+        if job.source == MetricsSource::Client {
+            self.heartbeat_deadlines.arm(&job_id, job.expires_at(heartbeat_timeout));
+        }
+        self.jobs.insert(job_id, job);
+        self.mark_changed();
+    }
+
+    pub fn remove_job(&mut self, job_id: &str) -> Option<JobInfo> {
+        self.heartbeat_deadlines.disarm(job_id);
+        let removed = self.jobs.remove(job_id);
+        if removed.is_some() {
+            self.mark_changed();
+            if let Some(removed_jobs) = &self.removed_jobs {
+                let _ = removed_jobs.send(job_id.to_string());
+            }
+        }
+        removed
+    }
+
+    // 心跳只更新任务的时间戳; 定时器到期时再按最后一次心跳决定超时还是顺延.
+    // job_helper monitor 的连接断开或重新连上时, 改为按新的截止时间触发
+    pub fn rearm_heartbeat(&mut self, job_id: &str, deadline: Instant) {
+        self.heartbeat_deadlines.arm(job_id, deadline);
+    }
+
+    pub fn mark_changed(&self) {
+        self.state_changed.notify_one();
+    }
+}
+
+// 每个任务一个截止时间, 按时间排序, 状态任务只需等待最早的一个
+#[derive(Default)]
+struct Deadlines {
+    by_job: HashMap<String, Instant>,
+    queue: BTreeSet<(Instant, String)>,
+}
+
+impl Deadlines {
+    fn arm(&mut self, job_id: &str, deadline: Instant) {
+        self.disarm(job_id);
+        self.by_job.insert(job_id.to_string(), deadline);
+        self.queue.insert((deadline, job_id.to_string()));
+    }
+
+    fn disarm(&mut self, job_id: &str) {
+        if let Some(deadline) = self.by_job.remove(job_id) {
+            self.queue.remove(&(deadline, job_id.to_string()));
+        }
+    }
+
+    fn next(&self) -> Option<Instant> {
+        self.queue.first().map(|(deadline, _)| *deadline)
+    }
+
+    fn pop_expired(&mut self, now: Instant) -> Vec<String> {
+        let mut expired = Vec::new();
+        while let Some((deadline, _)) = self.queue.first() {
+            if *deadline > now {
+                break;
+            }
+            if let Some((_, job_id)) = self.queue.pop_first() {
+                self.by_job.remove(&job_id);
+                expired.push(job_id);
+            }
+        }
+        expired
+    }
+}
+
+// ============================================================================
+// 状态任务 (State Task)
+// ============================================================================
+
+type Command = Box<dyn FnOnce(&mut JobTracker) + Send>;
+
+pub type SharedTracker = TrackerHandle;
+
+#[derive(Clone)]
+pub struct TrackerHandle {
+    commands: mpsc::Sender<Command>,
+}
+
+impl TrackerHandle {
+    // 在状态任务中执行 f 并返回结果; f 是同步的, 不能在其中等待 I/O.
+    // f panic 时返回错误, 状态任务继续处理其他请求
+    pub async fn with<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut JobTracker) -> T + Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        let command: Command = Box::new(move |tracker| {
+            let _ = reply.send(f(tracker));
+        });
+        if self.commands.send(command).await.is_err() {
+            return Err(anyhow!("Job state task has stopped"));
+        }
+        result.await.map_err(|_| anyhow!("Job state request panicked"))
+    }
+}
+
+// 启动状态任务; 返回的通道中是心跳定时器到期的任务, 由心跳检测任务处理
+pub fn spawn(tracker: JobTracker) -> (SharedTracker, mpsc::UnboundedReceiver<String>) {
+    let (commands, receiver) = mpsc::channel(COMMAND_QUEUE_CAPACITY);
+    let (expired, expired_jobs) = mpsc::unbounded_channel();
+    tokio::spawn(run(tracker, receiver, expired));
+    (TrackerHandle { commands }, expired_jobs)
+}
+
+async fn run(mut tracker: JobTracker, mut commands: mpsc::Receiver<Command>, expired: mpsc::UnboundedSender<String>) {
+    loop {
+        let next_deadline = tracker.heartbeat_deadlines.next();
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(command) => {
+                    // 一个出错的请求不能让整个状态任务退出; 它的调用方收到错误
+                    if panic::catch_unwind(AssertUnwindSafe(|| command(&mut tracker))).is_err() {
+                        error!("A job state request panicked, the job it was updating may be left partially updated.");
+                    }
+                }
+                None => return,
+            },
+            _ = time::sleep_until(next_deadline.unwrap_or_else(Instant::now)), if next_deadline.is_some() => {
+                for job_id in tracker.heartbeat_deadlines.pop_expired(Instant::now()) {
+                    if expired.send(job_id).is_err() {
+                        error!("Heartbeat checker has stopped, heartbeat timeouts are not enforced.");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadlines_expire_in_order() {
+        let now = Instant::now();
+        let mut deadlines = Deadlines::default();
+        assert_eq!(deadlines.next(), None);
+
+        deadlines.arm("2", now + Duration::from_secs(20));
+        deadlines.arm("1", now + Duration::from_secs(10));
+        deadlines.arm("3", now + Duration::from_secs(30));
+        assert_eq!(deadlines.next(), Some(now + Duration::from_secs(10)));
+
+        assert!(deadlines.pop_expired(now).is_empty());
+        assert_eq!(deadlines.pop_expired(now + Duration::from_secs(20)), ["1", "2"]);
+        assert_eq!(deadlines.next(), Some(now + Duration::from_secs(30)));
+        assert_eq!(deadlines.pop_expired(now + Duration::from_secs(60)), ["3"]);
+        assert_eq!(deadlines.next(), None);
+    }
+
+    #[test]
+    fn rearming_replaces_the_previous_deadline() {
+        let now = Instant::now();
+        let mut deadlines = Deadlines::default();
+
+        deadlines.arm("1", now + Duration::from_secs(10));
+        deadlines.arm("1", now + Duration::from_secs(30));
+        assert_eq!(deadlines.next(), Some(now + Duration::from_secs(30)));
+        assert!(deadlines.pop_expired(now + Duration::from_secs(20)).is_empty());
+        assert_eq!(deadlines.pop_expired(now + Duration::from_secs(30)), ["1"]);
+        // 到期后不再触发
+        assert!(deadlines.pop_expired(now + Duration::from_secs(60)).is_empty());
+    }
+
+    #[test]
+    fn disarmed_deadlines_never_expire() {
+        let now = Instant::now();
+        let mut deadlines = Deadlines::default();
+
+        deadlines.arm("1", now + Duration::from_secs(10));
+        deadlines.arm("2", now + Duration::from_secs(20));
+        deadlines.disarm("1");
+        // 未设置的任务可以直接撤销
+        deadlines.disarm("3");
+        assert_eq!(deadlines.next(), Some(now + Duration::from_secs(20)));
+        assert_eq!(deadlines.pop_expired(now + Duration::from_secs(60)), ["2"]);
+        assert!(deadlines.by_job.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_panicking_request_does_not_stop_the_state_task() {
+        let (tracker, _expired) = spawn(JobTracker::default());
+
+        let result = tracker
+            .with(|tracker| {
+                tracker.enforcement_paused = true;
+                panic!("bad request");
+            })
+            .await;
+        assert!(result.is_err());
+
+        // 之后的请求照常处理, 且能看到 panic 之前已完成的修改
+        assert!(tracker.with(|tracker| tracker.enforcement_paused).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn expired_heartbeats_are_reported() {
+        let (tracker, mut expired) = spawn(JobTracker::default());
+        tracker
+            .with(|tracker| tracker.rearm_heartbeat("42", Instant::now() + Duration::from_millis(10)))
+            .await
+            .unwrap();
+
+        assert_eq!(expired.recv().await.as_deref(), Some("42"));
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+// ============================================================================
+// 常量定义 (Constants)
+// ============================================================================
+
+// 单个窗口最多保留的样本数; 样本来得过密时丢弃最旧的, 同时缩短已覆盖的时间段
+const MAX_WINDOW_SAMPLES: usize = 4096;
+
+// ============================================================================
+// 滑动窗口 (Sliding Window)
+// ============================================================================
+
+// 按时间滑动的窗口: 保留最近 length 时间内的样本, 单调队列维护最大值, 累加和维护平均值.
+// 相邻样本间隔超过 max_gap 视为数据缺失 (未知, 而不是空闲), 需要重新积累一个完整窗口才能判定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "WindowSnapshot", into = "WindowSnapshot")]
+pub struct SlidingWindow {
+    length: TimeDelta,
+    samples: VecDeque<(DateTime<Utc>, f64)>,
+    // 值单调递减, 队首即窗口最大值
+    max_candidates: VecDeque<(DateTime<Utc>, f64)>,
+    sum: f64,
+    // 当前这段连续数据的起点
+    covered_since: Option<DateTime<Utc>>,
+    last_sample_at: Option<DateTime<Utc>>,
+    pushes_since_resum: usize,
+}
+
+// 持久化时只保存窗口长度、样本和连续数据的起点, 恢复时重建聚合值
+#[derive(Serialize, Deserialize)]
+struct WindowSnapshot {
+    length_secs: u64,
+    samples: Vec<(DateTime<Utc>, f64)>,
+    covered_since: Option<DateTime<Utc>>,
+}
+
+impl SlidingWindow {
+    pub fn new(length: Duration) -> Self {
+        Self {
+            length: TimeDelta::from_std(length)
+                .unwrap_or(TimeDelta::MAX)
+                .max(TimeDelta::seconds(1)),
+            samples: VecDeque::new(),
+            max_candidates: VecDeque::new(),
+            sum: 0.0,
+            covered_since: None,
+            last_sample_at: None,
+            pushes_since_resum: 0,
+        }
+    }
+
+    // 样本必须按时间递增, 否则丢弃并返回 false
+    pub fn push(&mut self, at: DateTime<Utc>, value: f64, max_gap: Duration) -> bool {
+        if self.last_sample_at.is_some_and(|last| at <= last) {
+            return false;
+        }
+        let max_gap = TimeDelta::from_std(max_gap).unwrap_or(TimeDelta::MAX);
+        if self.last_sample_at.is_none_or(|last| at - last > max_gap) {
+            self.covered_since = Some(at);
+        }
+        self.last_sample_at = Some(at);
+
+        while self.max_candidates.back().is_some_and(|&(_, v)| v <= value) {
+            self.max_candidates.pop_back();
+        }
+        self.max_candidates.push_back((at, value));
+        self.samples.push_back((at, value));
+        self.sum += value;
+
+        let window_start = at.checked_sub_signed(self.length).unwrap_or(DateTime::<Utc>::MIN_UTC);
+        while self
+            .samples
+            .front()
+            .is_some_and(|&(t, _)| t <= window_start || self.samples.len() > MAX_WINDOW_SAMPLES)
+        {
+            if let Some((_, old)) = self.samples.pop_front() {
+                self.sum -= old;
+            }
+        }
+        if let Some(&(oldest, _)) = self.samples.front() {
+            while self.max_candidates.front().is_some_and(|&(t, _)| t < oldest) {
+                self.max_candidates.pop_front();
+            }
+            // 因样本过多而丢弃的部分不再算作已覆盖
+            if self.samples.len() == MAX_WINDOW_SAMPLES {
+                self.covered_since = self.covered_since.max(Some(oldest));
+            }
+        }
+
+        // 定期重新求和, 避免浮点误差累积
+        self.pushes_since_resum += 1;
+        if self.pushes_since_resum >= self.samples.len() {
+            self.sum = self.samples.iter().map(|&(_, v)| v).sum();
+            self.pushes_since_resum = 0;
+        }
+        true
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self {
+            length: self.length,
+            ..Self::new(Duration::ZERO)
+        };
+    }
+
+    pub fn length(&self) -> Duration {
+        self.length.to_std().unwrap_or_default()
+    }
+
+    // 最近一段连续数据覆盖了多长时间, 最多为窗口长度
+    pub fn covered(&self) -> Duration {
+        match (self.covered_since, self.last_sample_at) {
+            (Some(since), Some(last)) => (last - since).min(self.length).to_std().unwrap_or_default(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    // 连续数据已覆盖整个窗口, 可以据此判定是否空闲
+    pub fn is_complete(&self) -> bool {
+        self.covered() >= self.length()
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.max_candidates.front().map(|&(_, v)| v)
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (!self.samples.is_empty()).then(|| self.sum / self.samples.len() as f64)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = f64> + '_ {
+        self.samples.iter().map(|&(_, v)| v)
+    }
+
+    // 假设从现在起一直空闲, 最早何时能判定为空闲; 覆盖的起点不早于 not_before (缓冲期结束)
+    pub fn idle_at(
+        &self,
+        threshold: f64,
+        now: DateTime<Utc>,
+        not_before: DateTime<Utc>,
+        max_gap: Duration,
+    ) -> DateTime<Utc> {
+        let max_gap = TimeDelta::from_std(max_gap).unwrap_or(TimeDelta::MAX);
+        // 最后一个高于阈值的样本滑出窗口之前不会触发
+        let last_active = self
+            .samples
+            .iter()
+            .rev()
+            .find(|&&(_, v)| v >= threshold)
+            .map(|&(t, _)| t);
+        let coverage_start = match self.last_sample_at {
+            // 超过容忍间隔还没有新样本, 下一个样本到来时覆盖会从头开始
+            Some(last) if now - last <= max_gap => self.covered_since.unwrap_or(now),
+            _ => now,
+        };
+        let start = [Some(coverage_start), last_active, Some(not_before)]
+            .into_iter()
+            .flatten()
+            .max()
+            .unwrap_or(now);
+        start
+            .checked_add_signed(self.length)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+impl From<WindowSnapshot> for SlidingWindow {
+    fn from(snapshot: WindowSnapshot) -> Self {
+        let mut window = SlidingWindow::new(Duration::from_secs(snapshot.length_secs));
+        for (at, value) in snapshot.samples {
+            window.push(at, value, Duration::MAX);
+        }
+        if snapshot.covered_since.is_some() {
+            window.covered_since = snapshot.covered_since;
+        }
+        window
+    }
+}
+
+impl From<SlidingWindow> for WindowSnapshot {
+    fn from(window: SlidingWindow) -> Self {
+        Self {
+            length_secs: window.length().as_secs(),
+            samples: window.samples.into(),
+            covered_since: window.covered_since,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_GAP: Duration = Duration::MAX;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_760_000_000 + secs, 0).unwrap()
+    }
+
+    fn values(window: &SlidingWindow) -> Vec<f64> {
+        window.iter().collect()
+    }
+
+    #[test]
+    fn evicts_samples_that_slid_out_of_the_window() {
+        let mut window = SlidingWindow::new(Duration::from_secs(60));
+        window.push(at(0), 1.0, NO_GAP);
+        window.push(at(30), 2.0, NO_GAP);
+        window.push(at(60), 3.0, NO_GAP);
+        assert_eq!(values(&window), [2.0, 3.0]);
+        window.push(at(150), 4.0, NO_GAP);
+        assert_eq!(values(&window), [4.0]);
+        assert_eq!((window.max(), window.mean()), (Some(4.0), Some(4.0)));
+    }
+
+    #[test]
+    fn max_follows_the_samples_still_in_the_window() {
+        let mut window = SlidingWindow::new(Duration::from_secs(35));
+        window.push(at(0), 90.0, NO_GAP);
+        window.push(at(10), 10.0, NO_GAP);
+        window.push(at(20), 50.0, NO_GAP);
+        window.push(at(30), 20.0, NO_GAP);
+        assert_eq!(window.max(), Some(90.0));
+        window.push(at(40), 5.0, NO_GAP);
+        assert_eq!(window.max(), Some(50.0));
+        window.push(at(56), 1.0, NO_GAP);
+        assert_eq!(values(&window), [20.0, 5.0, 1.0]);
+        assert_eq!(window.max(), Some(20.0));
+    }
+
+    #[test]
+    fn streaming_aggregates_match_a_full_scan() {
+        let mut window = SlidingWindow::new(Duration::from_secs(100));
+        let mut seed: u64 = 42;
+        let mut t = 0;
+        for _ in 0..2000 {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            t += (seed >> 60) as i64 + 1;
+            let value = (seed >> 33) as f64 % 1000.0 / 10.0;
+            window.push(at(t), value, NO_GAP);
+
+            let samples = values(&window);
+            let max = samples.iter().copied().fold(f64::MIN, f64::max);
+            let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+            assert_eq!(window.max(), Some(max));
+            assert!((window.mean().unwrap() - mean).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn gaps_restart_the_coverage() {
+        let gap = Duration::from_secs(15);
+        let mut window = SlidingWindow::new(Duration::from_secs(40));
+        for t in (0..=40).step_by(10) {
+            window.push(at(t), 1.0, gap);
+        }
+        assert_eq!(window.covered(), Duration::from_secs(40));
+        assert!(window.is_complete());
+
+        // 数据缺失之后的样本重新开始积累, 之前的样本仍在窗口中但不能据此判定
+        window.push(at(70), 1.0, gap);
+        assert_eq!(window.covered(), Duration::ZERO);
+        assert!(!window.is_complete());
+        assert_eq!(values(&window), [1.0, 1.0]);
+        for t in (80..=110).step_by(10) {
+            window.push(at(t), 1.0, gap);
+        }
+        assert!(window.is_complete());
+    }
+
+    #[test]
+    fn idle_at_waits_for_coverage_and_the_last_active_sample() {
+        let gap = Duration::from_secs(15);
+        let mut window = SlidingWindow::new(Duration::from_secs(60));
+        window.push(at(0), 1.0, gap);
+        window.push(at(10), 80.0, gap);
+        window.push(at(20), 1.0, gap);
+        assert_eq!(window.idle_at(50.0, at(25), at(0), gap), at(70));
+        assert_eq!(window.idle_at(90.0, at(25), at(0), gap), at(60));
+        assert_eq!(window.idle_at(90.0, at(25), at(30), gap), at(90));
+        // 超过容忍间隔没有样本时, 覆盖要从现在重新开始
+        assert_eq!(window.idle_at(90.0, at(100), at(0), gap), at(160));
+    }
+
+    #[test]
+    fn rejects_late_samples() {
+        let mut window = SlidingWindow::new(Duration::from_secs(60));
+        assert!(window.push(at(10), 1.0, NO_GAP));
+        assert!(!window.push(at(10), 99.0, NO_GAP));
+        assert!(!window.push(at(5), 99.0, NO_GAP));
+        assert_eq!(values(&window), [1.0]);
+        assert_eq!(window.max(), Some(1.0));
+    }
+
+    #[test]
+    fn bursts_do_not_count_as_coverage() {
+        let mut window = SlidingWindow::new(Duration::from_secs(60));
+        let start = at(0);
+        for ms in 0..500 {
+            window.push(start + TimeDelta::milliseconds(ms), 0.0, NO_GAP);
+        }
+        assert_eq!(window.covered(), Duration::from_millis(499));
+        assert!(!window.is_complete());
+    }
+
+    #[test]
+    fn caps_the_number_of_samples() {
+        let mut window = SlidingWindow::new(Duration::from_secs(86400));
+        let total = MAX_WINDOW_SAMPLES as i64 + 10;
+        for t in 0..total {
+            window.push(at(t), t as f64, NO_GAP);
+        }
+        assert_eq!(window.iter().count(), MAX_WINDOW_SAMPLES);
+        assert_eq!(window.iter().next(), Some(10.0));
+        assert_eq!(window.max(), Some((total - 1) as f64));
+        // 丢弃的样本不再算作已覆盖
+        assert_eq!(window.covered(), Duration::from_secs(MAX_WINDOW_SAMPLES as u64 - 1));
+    }
+
+    #[test]
+    fn snapshot_round_trip_keeps_samples_and_coverage() {
+        let gap = Duration::from_secs(15);
+        let mut window = SlidingWindow::new(Duration::from_secs(60));
+        for t in [0, 10, 20, 60, 70, 80] {
+            window.push(at(t), t as f64 / 10.0, gap);
+        }
+        let json = serde_json::to_string(&window).unwrap();
+        let restored: SlidingWindow = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.length(), window.length());
+        assert_eq!(values(&restored), values(&window));
+        assert_eq!(restored.max(), window.max());
+        assert_eq!(restored.mean(), window.mean());
+        assert_eq!(restored.covered(), Duration::from_secs(20));
+        assert_eq!(restored.covered(), window.covered());
+    }
+
+    #[test]
+    fn clear_keeps_the_window_length() {
+        let mut window = SlidingWindow::new(Duration::from_secs(60));
+        window.push(at(0), 1.0, NO_GAP);
+        window.clear();
+        assert_eq!(window.length(), Duration::from_secs(60));
+        assert_eq!((window.max(), window.mean()), (None, None));
+        assert_eq!(window.covered(), Duration::ZERO);
+    }
+}
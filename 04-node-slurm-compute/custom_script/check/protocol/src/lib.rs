@@ -0,0 +1,89 @@
+// job_helper 与 node_monitor 之间的套接字协议: 每行一个 JSON 请求, 守护进程对每个请求答复一行 JSON
+mod message;
+mod response;
+
+pub use message::{
+    CancelPayload, GpuInfo, GpuMetric, HelloPayload, Message, MetricsPayload, RegisterPayload, StatusPayload,
+};
+pub use response::{
+    AckResponse, CancelResponse, EnforcementMode, ErrorCode, ErrorResponse, Escalation, Exemption, HelloResponse,
+    MetricStatus, MonitorLost, RegisterResponse, StatusResponse, SupervisedMonitor,
+};
+
+// ============================================================================
+// 协议版本 (Protocol Versions)
+// ============================================================================
+
+// 版本 1: 没有 HELLO 的旧版协议, 连接上的第一条消息不是 HELLO 时按该版本处理
+pub const LEGACY_VERSION: u32 = 1;
+
+// 版本 2: 增加 HELLO 握手, 以及 unsupported_version / unknown_message_type / unknown_field 错误码
+pub const PROTOCOL_VERSION: u32 = 2;
+
+// 仍然支持的最旧版本, 滚动升级期间新旧两端至少要能互通 N-1
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+// 守护进程端: 在双方都支持的版本中选最高的一个
+pub fn negotiate(hello: &HelloPayload) -> Result<u32, ErrorResponse> {
+    let version = hello.max_version.min(PROTOCOL_VERSION);
+    if version < hello.min_version.max(MIN_PROTOCOL_VERSION) {
+        return Err(ErrorResponse::new(
+            ErrorCode::UnsupportedVersion,
+            format!(
+                "No common protocol version: peer supports {}-{}, node monitor supports {}-{}",
+                hello.min_version, hello.max_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ),
+        ));
+    }
+    Ok(version)
+}
+
+// 版本 1 中只有 REGISTER 有答复: 旧版守护进程不答复 METRICS 和 CANCEL, 也不认识 STATUS,
+// 旧版 job_helper 发送 METRICS 后也从不读取答复 (答复会堆积在套接字缓冲区中)
+pub fn has_reply(message: &Message, version: u32) -> bool {
+    version > LEGACY_VERSION || matches!(message, Message::Hello(_) | Message::Register(_))
+}
+
+// 客户端: HELLO 被拒绝时是否可以按旧版协议继续.
+// 把无法解析的请求答复为 invalid_request 的守护进程不认识 HELLO 时会这样答复并保持连接
+pub fn fallback_version(rejection: &ErrorResponse) -> Option<u32> {
+    (rejection.code == ErrorCode::InvalidRequest && MIN_PROTOCOL_VERSION <= LEGACY_VERSION).then_some(LEGACY_VERSION)
+}
+
+// ============================================================================
+// 请求解析 (Request Parsing)
+// ============================================================================
+
+// 解析一行请求; 失败时区分未知的消息类型、未知的字段和其他格式错误
+pub fn parse_message(line: &str) -> Result<Message, ErrorResponse> {
+    let error = match serde_json::from_str::<Message>(line) {
+        Ok(message) => return Ok(message),
+        Err(e) => e,
+    };
+
+    let value = serde_json::from_str::<serde_json::Value>(line).ok();
+    let message_type = value.as_ref().and_then(|v| v.get("type")?.as_str());
+    if let Some(t) = message_type.filter(|t| !Message::TYPES.contains(t)) {
+        return Err(ErrorResponse::new(
+            ErrorCode::UnknownMessageType,
+            format!("Unknown message type '{}', expected one of {:?}", t, Message::TYPES),
+        ));
+    }
+    // 外层只有 type 和 payload 两个字段; serde 对外层多余的字段只报告为 invalid value
+    let unknown_key = value
+        .as_ref()
+        .and_then(|v| v.as_object()?.keys().find(|k| *k != "type" && *k != "payload"));
+    if let Some(key) = unknown_key {
+        return Err(ErrorResponse::new(
+            ErrorCode::UnknownField,
+            format!("Invalid message: unknown field `{}`, expected `type` or `payload`", key),
+        ));
+    }
+
+    let code = if error.to_string().starts_with("unknown field") {
+        ErrorCode::UnknownField
+    } else {
+        ErrorCode::InvalidRequest
+    };
+    Err(ErrorResponse::new(code, format!("Invalid message: {}", error)))
+}
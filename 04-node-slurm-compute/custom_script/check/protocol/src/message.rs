@@ -0,0 +1,164 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// ============================================================================
+// 请求 (Requests)
+// ============================================================================
+
+// 守护进程拒绝未知的字段, 新增字段只能随协议版本一起引入
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "payload", deny_unknown_fields)]
+pub enum Message {
+    // 版本 2 起: 连接上的第一条消息, 协商协议版本
+    #[serde(rename = "HELLO")]
+    Hello(HelloPayload),
+    #[serde(rename = "REGISTER")]
+    Register(RegisterPayload),
+    #[serde(rename = "METRICS")]
+    Metrics(MetricsPayload),
+    #[serde(rename = "CANCEL")]
+    Cancel(CancelPayload),
+    #[serde(rename = "STATUS")]
+    Status(StatusPayload),
+}
+
+impl Message {
+    // 所有已知的消息类型, 用于区分未知的类型和格式错误
+    pub const TYPES: [&'static str; 5] = ["HELLO", "REGISTER", "METRICS", "CANCEL", "STATUS"];
+
+    // HELLO 不针对具体任务
+    pub fn job_id(&self) -> Option<&str> {
+        match self {
+            Message::Hello(_) => None,
+            Message::Register(p) => Some(&p.job_id),
+            Message::Metrics(p) => Some(&p.job_id),
+            Message::Cancel(p) => Some(&p.job_id),
+            Message::Status(p) => Some(&p.job_id),
+        }
+    }
+}
+
+// 客户端支持的协议版本范围
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct HelloPayload {
+    pub min_version: u32,
+    pub max_version: u32,
+}
+
+impl HelloPayload {
+    pub fn current() -> Self {
+        Self {
+            min_version: crate::MIN_PROTOCOL_VERSION,
+            max_version: crate::PROTOCOL_VERSION,
+        }
+    }
+}
+
+// 注册时只上报任务的事实信息, 监控窗口由守护进程根据策略决定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterPayload {
+    pub job_id: String,
+    pub log_path: PathBuf,
+    #[serde(default)]
+    pub partition: String,
+    #[serde(default)]
+    pub qos: String,
+    #[serde(default)]
+    pub account: String,
+    #[serde(default)]
+    pub gpus: Vec<GpuInfo>,
+    #[serde(default)]
+    pub allocated_cpus: usize,
+    // 版本 1 的监控窗口, 以取样次数 (每 60 秒一次) 表示; 旧版守护进程要求这两个字段.
+    // 新版守护进程按策略决定窗口, 收到后忽略; 新版客户端只向旧版守护进程发送
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpu_monitor_count: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_monitor_count: Option<i64>,
+}
+
+impl RegisterPayload {
+    // 按旧版 job_helper 的规则填写取样次数: debug 分区不检查, gpu 分区不检查 CPU,
+    // GPU 按型号取最小值, 没有 GPU 时不检查 GPU
+    pub fn with_legacy_counts(mut self) -> Self {
+        let partition = self.partition.to_lowercase();
+        let gpu_count = self
+            .gpus
+            .iter()
+            .map(|gpu| legacy_gpu_count(&gpu.name))
+            .min()
+            .unwrap_or(LEGACY_INFINITE_COUNT);
+        let (gpu_count, cpu_count) = if partition.contains("debug") {
+            (LEGACY_INFINITE_COUNT, LEGACY_INFINITE_COUNT)
+        } else if partition.contains("gpu") {
+            (gpu_count, LEGACY_INFINITE_COUNT)
+        } else {
+            (gpu_count, LEGACY_DEFAULT_COUNT)
+        };
+        self.gpu_monitor_count = Some(gpu_count);
+        self.cpu_monitor_count = Some(cpu_count);
+        self
+    }
+}
+
+// 版本 1 中表示不检查的取样次数 (约 100 天)
+const LEGACY_INFINITE_COUNT: i64 = 144000;
+const LEGACY_DEFAULT_COUNT: i64 = 60;
+
+fn legacy_gpu_count(name: &str) -> i64 {
+    let name = name.to_uppercase();
+    match name {
+        s if s.contains("5090") || s.contains("A6000") || s.contains("4090") => 20,
+        s if s.contains("3090") || s.contains("A10") => 60,
+        _ => LEGACY_DEFAULT_COUNT,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GpuInfo {
+    pub uuid: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsPayload {
+    pub job_id: String,
+    pub gpu_utilization: f64,
+    pub gpu_memory_utilization: f64,
+    pub cpu_utilization: f64,
+    // 每张卡各自的读数, 按 UUID 区分; 旧版 job_helper 不发送该字段
+    #[serde(default)]
+    pub gpus: Vec<GpuMetric>,
+    // 采样时间; 旧版 job_helper 不发送该字段, 以收到的时间为准
+    #[serde(default)]
+    pub sampled_at: Option<DateTime<Utc>>,
+    // 无法判断 GPU 进程归属时为 true: 守护进程不把本次的 GPU 读数计入窗口, 按缺失的样本处理
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub gpu_unknown: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GpuMetric {
+    pub uuid: String,
+    pub utilization: f64,
+    pub memory_utilization: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CancelPayload {
+    pub job_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StatusPayload {
+    pub job_id: String,
+}
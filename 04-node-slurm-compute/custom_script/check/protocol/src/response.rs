@@ -0,0 +1,254 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// ============================================================================
+// 答复 (Responses)
+// ============================================================================
+
+// 每个请求都会得到一行 JSON 答复: {"status": "ok", ...} 或 {"status": "error", "code": ..., "message": ...}.
+// 客户端忽略答复中不认识的字段
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HelloResponse {
+    pub status: String,
+    // 协商出的协议版本
+    pub version: u32,
+    // 守护进程自行从任务的 cgroup 采样, 任务内不需要 job_helper monitor
+    #[serde(default)]
+    pub daemon_sampling: bool,
+    // 守护进程在任务的 cgroup 中启动并看护 job_helper monitor, task_prolog.sh 不应再自行启动
+    #[serde(default)]
+    pub supervise_monitors: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RegisterResponse {
+    pub status: String,
+    // 窗口长度 (秒), null 表示不检查该指标
+    #[serde(default)]
+    pub gpu_window_secs: Option<u64>,
+    #[serde(default)]
+    pub cpu_window_secs: Option<u64>,
+}
+
+// METRICS 的确认
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AckResponse {
+    pub status: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CancelResponse {
+    pub status: String,
+    // 守护进程此前是否在跟踪该任务
+    pub removed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ErrorResponse {
+    pub status: String,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    // 请求无法解析, 或任务号不合法
+    InvalidRequest,
+    // 版本 2 起: 消息类型未知
+    UnknownMessageType,
+    // 版本 2 起: 请求中含有当前协议版本未定义的字段
+    UnknownField,
+    // 版本 2 起: HELLO 中没有双方都支持的协议版本
+    UnsupportedVersion,
+    // 对端既不是任务所有者, 也不是 root/SlurmUser
+    Unauthorized,
+    // 守护进程没有跟踪该任务
+    UnknownJob,
+    // 任务已被守护进程取消
+    JobCancelled,
+    // 无法创建或打开任务日志
+    LogFileUnavailable,
+    // 查询 Slurm 失败等守护进程内部错误
+    Internal,
+    // 单条请求超过长度上限, 守护进程随后关闭连接
+    MessageTooLarge,
+    // 该 UID 打开的连接数已达上限, 稍后重试
+    TooManyConnections,
+    // 连接上的请求超过速率上限, 该请求未被处理; 连续超限的连接会被关闭
+    RateLimited,
+    // 更新版本的守护进程新增的错误码
+    #[serde(other)]
+    Other,
+}
+
+impl ErrorCode {
+    // 稍后重试可能成功的错误, 其余错误重试也不会改变结果
+    pub fn is_retryable(self) -> bool {
+        matches!(self, ErrorCode::TooManyConnections | ErrorCode::RateLimited)
+    }
+}
+
+impl HelloResponse {
+    pub fn new(version: u32) -> Self {
+        Self {
+            status: "ok".to_string(),
+            version,
+            daemon_sampling: false,
+            supervise_monitors: false,
+        }
+    }
+}
+
+impl AckResponse {
+    pub fn ok() -> Self {
+        Self {
+            status: "ok".to_string(),
+        }
+    }
+}
+
+impl ErrorResponse {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            status: "error".to_string(),
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:?})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for ErrorResponse {}
+
+// ============================================================================
+// 状态查询 (Status Query)
+// ============================================================================
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatusResponse {
+    pub status: String,
+    pub job_id: String,
+    pub owner_uid: u32,
+    pub partition: String,
+    pub qos: String,
+    pub account: String,
+    pub registered_at: DateTime<Utc>,
+    pub last_heartbeat_at: DateTime<Utc>,
+    pub metrics_received: usize,
+    // 缓冲期还剩多少秒, 期间的样本会被丢弃
+    pub buffer_remaining_secs: u64,
+    pub enforcement_mode: EnforcementMode,
+    pub enforcement_paused: bool,
+    pub escalation: Escalation,
+    pub exemption: Option<Exemption>,
+    // job_helper monitor 的连接断开后等待其重新连接, 旧版守护进程不返回该字段
+    #[serde(default)]
+    pub monitor_lost: Option<MonitorLost>,
+    // node_monitor 启动并看护的 job_helper monitor; 由 task prolog 启动时为空
+    #[serde(default)]
+    pub supervised_monitor: Option<SupervisedMonitor>,
+    pub metrics: Vec<MetricStatus>,
+    // 按 GPU UUID 分别统计的利用率窗口
+    pub gpus: Vec<MetricStatus>,
+    // 整个窗口都闲置的卡数, 以及触发闲置卡策略所需的卡数 (0 表示未启用)
+    pub idle_gpus: usize,
+    pub min_idle_gpus: usize,
+    // 假设之后一直空闲, 最先触发的指标
+    pub closest_to_firing: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetricStatus {
+    pub name: String,
+    pub enforced: bool,
+    pub threshold: f64,
+    pub window_secs: u64,
+    // 最近一段连续数据覆盖的秒数, 达到 window_secs 才会判定
+    pub covered_secs: u64,
+    pub window: Vec<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    // 假设之后一直空闲, 还要多少秒才会触发 (含缓冲期)
+    pub secs_until_idle: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EnforcementMode {
+    // 照常取消空闲任务
+    #[default]
+    Enforce,
+    // 照常评估策略, 但只记录本应取消的任务, 不调用 scancel
+    DryRun,
+}
+
+// 任务当前所处的警告阶段, 随任务状态一起持久化
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum Escalation {
+    // 未处于空闲状态
+    #[default]
+    Active,
+    // 已发出第一次警告
+    Warned {
+        since: DateTime<Utc>,
+    },
+    // 已发出最后警告, 到 deadline 仍空闲则取消
+    FinalWarning {
+        deadline: DateTime<Utc>,
+    },
+}
+
+// 管理员通过 `node_monitor ctl exempt` 设置的豁免, until 为空表示直到任务结束
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Exemption {
+    pub until: Option<DateTime<Utc>>,
+}
+
+// 任务的 job_helper monitor 连接断开后所处的状态, 重新连接后清除
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct MonitorLost {
+    pub since: DateTime<Utc>,
+    // 对端在两条请求之间关闭了连接; false 表示读取出错、请求只发了一半或连接被守护进程关闭
+    pub clean: bool,
+    // 到期仍未重新连接则取消任务
+    pub deadline: DateTime<Utc>,
+}
+
+// node_monitor 启动并看护的 job_helper monitor 进程
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SupervisedMonitor {
+    // 正在运行的进程号, 等待重启时为空
+    pub pid: Option<u32>,
+    pub restarts: u32,
+    // 上一次退出的原因, 例如 "exit status: 1" 或 "signal: 9 (SIGKILL)"
+    pub last_exit: Option<String>,
+    pub last_exit_at: Option<DateTime<Utc>>,
+}
+
+impl fmt::Display for EnforcementMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnforcementMode::Enforce => write!(f, "enforce"),
+            EnforcementMode::DryRun => write!(f, "dry_run"),
+        }
+    }
+}
+
+impl Escalation {
+    pub fn stage(&self) -> &'static str {
+        match self {
+            Escalation::Active => "active",
+            Escalation::Warned { .. } => "warned",
+            Escalation::FinalWarning { .. } => "final_warning",
+        }
+    }
+}
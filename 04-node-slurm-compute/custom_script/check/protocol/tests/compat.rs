@@ -0,0 +1,408 @@
+// 与上一个协议版本 (N-1) 的兼容性: 下面的 JSON 都是旧版 job_helper / node_monitor 实际收发的内容
+use std::path::PathBuf;
+
+use protocol::{
+    AckResponse, CancelPayload, CancelResponse, ErrorCode, ErrorResponse, GpuInfo, HelloPayload, HelloResponse,
+    LEGACY_VERSION, MIN_PROTOCOL_VERSION, Message, MetricsPayload, PROTOCOL_VERSION, RegisterPayload, RegisterResponse,
+    StatusPayload, StatusResponse, fallback_version, has_reply, negotiate, parse_message,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+// ============================================================================
+// 旧版 (v1) 报文 (Legacy Fixtures)
+// ============================================================================
+
+// 以下是目前部署的 job_helper / node_monitor (c51d808) 实际收发的内容.
+// 旧版 job_helper 只发送 REGISTER / METRICS / CANCEL, 旧版守护进程只答复 REGISTER
+const V1_REGISTER: &str = r#"{"type":"REGISTER","payload":{"job_id":"4242","gpu_monitor_count":20,"cpu_monitor_count":144000,"log_path":"/home/alice/.slurm/info-4242.log"}}"#;
+const V1_METRICS: &str = r#"{"type":"METRICS","payload":{"job_id":"4242","gpu_utilization":0.0,"gpu_memory_utilization":0.0,"cpu_utilization":1.5}}"#;
+const V1_CANCEL: &str = r#"{"type":"CANCEL","payload":{"job_id":"4242"}}"#;
+const V1_REGISTER_RESPONSE: &str = "{\"status\": \"ok\"}\n";
+
+// 旧版守护进程解析请求时使用的类型 (没有 deny_unknown_fields)
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", content = "payload")]
+enum V1Message {
+    #[serde(rename = "REGISTER")]
+    Register {
+        job_id: String,
+        gpu_monitor_count: usize,
+        cpu_monitor_count: usize,
+        log_path: PathBuf,
+    },
+    #[serde(rename = "METRICS")]
+    Metrics {
+        job_id: String,
+        gpu_utilization: f64,
+        gpu_memory_utilization: f64,
+        cpu_utilization: f64,
+    },
+    #[serde(rename = "CANCEL")]
+    Cancel { job_id: String },
+}
+
+// 旧版 job_helper 解析 REGISTER 答复时使用的类型
+#[derive(Deserialize, Debug)]
+struct V1DaemonResponse {
+    status: String,
+}
+
+// 版本 2 最初的 STATUS 答复, 还没有 monitor_lost 和 supervised_monitor
+const V2_STATUS_RESPONSE: &str = r#"{"status":"ok","job_id":"4242","owner_uid":1000,"partition":"gpu","qos":"normal","account":"lab","registered_at":"2026-10-17T18:58:28.229035377Z","last_heartbeat_at":"2026-10-17T18:58:30.229805770Z","metrics_received":1,"buffer_remaining_secs":0,"enforcement_mode":"dry_run","enforcement_paused":false,"escalation":{"stage":"warned","since":"2026-10-17T19:00:00Z"},"exemption":null,"metrics":[{"name":"gpu_utilization","enforced":true,"threshold":5.0,"window_secs":3600,"covered_secs":120,"window":[0.0,0.0],"max":0.0,"mean":0.0,"secs_until_idle":3480},{"name":"cpu_utilization","enforced":false,"threshold":5.0,"window_secs":0,"covered_secs":0,"window":[],"max":null,"mean":null,"secs_until_idle":null}],"gpus":[],"idle_gpus":0,"min_idle_gpus":0,"closest_to_firing":"gpu_utilization"}"#;
+
+fn current_register() -> RegisterPayload {
+    RegisterPayload {
+        job_id: "4242".to_string(),
+        log_path: PathBuf::from("/home/alice/.slurm/info-4242.log"),
+        partition: "gpu".to_string(),
+        qos: "normal".to_string(),
+        account: "lab".to_string(),
+        gpus: vec![GpuInfo {
+            uuid: "GPU-1".to_string(),
+            name: "NVIDIA GeForce RTX 4090".to_string(),
+        }],
+        allocated_cpus: 4,
+        gpu_monitor_count: None,
+        cpu_monitor_count: None,
+    }
+}
+
+fn current_metrics() -> MetricsPayload {
+    serde_json::from_value(json!({
+        "job_id": "4242",
+        "gpu_utilization": 0.0,
+        "gpu_memory_utilization": 0.0,
+        "cpu_utilization": 1.5,
+        "gpus": [{"uuid": "GPU-1", "utilization": 0.0, "memory_utilization": 0.0}],
+        "sampled_at": "2026-10-17T08:00:00Z"
+    }))
+    .unwrap()
+}
+
+// ============================================================================
+// 旧版客户端 -> 新版守护进程 (Old Client, New Daemon)
+// ============================================================================
+
+#[test]
+fn parses_v1_requests() {
+    for line in [V1_REGISTER, V1_METRICS, V1_CANCEL] {
+        let message = parse_message(line).unwrap_or_else(|e| panic!("{} rejected: {}", line, e));
+        assert_eq!(message.job_id(), Some("4242"));
+    }
+}
+
+#[test]
+fn v1_register_is_a_known_shape() {
+    let Ok(Message::Register(register)) = parse_message(V1_REGISTER) else {
+        panic!("expected REGISTER");
+    };
+    assert_eq!(register.gpu_monitor_count, Some(20));
+    assert_eq!(register.cpu_monitor_count, Some(144000));
+    assert_eq!(register.log_path, PathBuf::from("/home/alice/.slurm/info-4242.log"));
+    assert!(register.partition.is_empty());
+    assert!(register.gpus.is_empty());
+}
+
+#[test]
+fn v1_metrics_defaults_missing_fields() {
+    let Ok(Message::Metrics(metrics)) = parse_message(V1_METRICS) else {
+        panic!("expected METRICS");
+    };
+    assert!(metrics.gpus.is_empty());
+    assert_eq!(metrics.sampled_at, None);
+}
+
+// 旧版 job_helper 只读取 REGISTER 的答复
+#[test]
+fn v1_peers_only_get_register_replies() {
+    for (line, expected) in [(V1_REGISTER, true), (V1_METRICS, false), (V1_CANCEL, false)] {
+        let message = parse_message(line).unwrap();
+        assert_eq!(has_reply(&message, LEGACY_VERSION), expected, "{}", line);
+        assert!(has_reply(&message, PROTOCOL_VERSION), "{}", line);
+    }
+}
+
+#[test]
+fn v1_client_accepts_current_register_replies() {
+    let ok = RegisterResponse {
+        status: "ok".to_string(),
+        gpu_window_secs: Some(1200),
+        cpu_window_secs: None,
+    };
+    let decoded: V1DaemonResponse = serde_json::from_str(&serde_json::to_string(&ok).unwrap()).unwrap();
+    assert_eq!(decoded.status, "ok");
+
+    // 旧版 job_helper 把 status 不是 "ok" 的答复视为注册失败
+    let refused = ErrorResponse::new(ErrorCode::JobCancelled, "cancelled");
+    let decoded: V1DaemonResponse = serde_json::from_str(&serde_json::to_string(&refused).unwrap()).unwrap();
+    assert_eq!(decoded.status, "error");
+}
+
+#[test]
+fn v1_connections_fall_within_supported_range() {
+    const { assert!(MIN_PROTOCOL_VERSION <= LEGACY_VERSION && LEGACY_VERSION < PROTOCOL_VERSION) };
+}
+
+// ============================================================================
+// 新版客户端 -> 旧版守护进程 (New Client, Old Daemon)
+// ============================================================================
+
+// 旧版守护进程要求取样次数, 新版客户端回退到 v1 时按旧规则填写
+#[test]
+fn v1_daemon_parses_current_register_with_legacy_counts() {
+    let line = serde_json::to_string(&Message::Register(current_register().with_legacy_counts())).unwrap();
+    match serde_json::from_str::<V1Message>(&line).unwrap() {
+        V1Message::Register {
+            job_id,
+            gpu_monitor_count,
+            cpu_monitor_count,
+            log_path,
+        } => {
+            assert_eq!(job_id, "4242");
+            assert_eq!((gpu_monitor_count, cpu_monitor_count), (20, 144000));
+            assert_eq!(log_path, PathBuf::from("/home/alice/.slurm/info-4242.log"));
+        }
+        other => panic!("expected REGISTER, got {:?}", other),
+    }
+
+    let line = serde_json::to_string(&Message::Register(current_register())).unwrap();
+    assert!(serde_json::from_str::<V1Message>(&line).is_err());
+}
+
+#[test]
+fn legacy_counts_follow_the_v1_client() {
+    let counts = |partition: &str, gpus: &[&str]| {
+        let mut payload = current_register();
+        payload.partition = partition.to_string();
+        payload.gpus = gpus
+            .iter()
+            .map(|name| GpuInfo {
+                uuid: String::new(),
+                name: name.to_string(),
+            })
+            .collect();
+        let payload = payload.with_legacy_counts();
+        (payload.gpu_monitor_count.unwrap(), payload.cpu_monitor_count.unwrap())
+    };
+    assert_eq!(counts("debug", &["NVIDIA GeForce RTX 4090"]), (144000, 144000));
+    assert_eq!(
+        counts("gpu", &["NVIDIA GeForce RTX 3090", "NVIDIA RTX A6000"]),
+        (20, 144000)
+    );
+    assert_eq!(counts("cpu", &[]), (144000, 60));
+    assert_eq!(counts("cpu", &["Tesla V100"]), (60, 60));
+}
+
+#[test]
+fn v1_daemon_parses_current_metrics_and_cancel() {
+    let metrics = serde_json::to_string(&Message::Metrics(current_metrics())).unwrap();
+    match serde_json::from_str::<V1Message>(&metrics).unwrap() {
+        V1Message::Metrics {
+            job_id,
+            gpu_utilization,
+            gpu_memory_utilization,
+            cpu_utilization,
+        } => {
+            assert_eq!(job_id, "4242");
+            assert_eq!(
+                (gpu_utilization, gpu_memory_utilization, cpu_utilization),
+                (0.0, 0.0, 1.5)
+            );
+        }
+        other => panic!("expected METRICS, got {:?}", other),
+    }
+    let cancel = serde_json::to_string(&Message::Cancel(CancelPayload {
+        job_id: "4242".to_string(),
+    }))
+    .unwrap();
+    match serde_json::from_str::<V1Message>(&cancel).unwrap() {
+        V1Message::Cancel { job_id } => assert_eq!(job_id, "4242"),
+        other => panic!("expected CANCEL, got {:?}", other),
+    }
+}
+
+// 旧版守护进程无法解析 HELLO 和 STATUS, 记录错误后不答复
+#[test]
+fn v1_daemon_cannot_parse_hello_or_status() {
+    let hello = serde_json::to_string(&Message::Hello(HelloPayload::current())).unwrap();
+    assert!(serde_json::from_str::<V1Message>(&hello).is_err());
+    let status = Message::Status(StatusPayload {
+        job_id: "4242".to_string(),
+    });
+    assert!(serde_json::from_str::<V1Message>(&serde_json::to_string(&status).unwrap()).is_err());
+    assert!(!has_reply(&status, LEGACY_VERSION));
+}
+
+#[test]
+fn decodes_v1_register_response() {
+    let register: RegisterResponse = serde_json::from_str(V1_REGISTER_RESPONSE).unwrap();
+    assert_eq!(register.status, "ok");
+    assert_eq!(register.gpu_window_secs, None);
+    assert_eq!(register.cpu_window_secs, None);
+}
+
+// 把无法解析的请求答复为 invalid_request 的守护进程
+#[test]
+fn falls_back_to_v1_when_hello_is_rejected() {
+    let rejection = ErrorResponse::new(ErrorCode::InvalidRequest, "Invalid message: unknown variant `HELLO`");
+    assert_eq!(fallback_version(&rejection), Some(LEGACY_VERSION));
+
+    let unsupported = negotiate(&HelloPayload {
+        min_version: PROTOCOL_VERSION + 1,
+        max_version: PROTOCOL_VERSION + 1,
+    })
+    .unwrap_err();
+    assert_eq!(fallback_version(&unsupported), None);
+}
+
+// ============================================================================
+// 版本 2 答复 (Version 2 Responses)
+// ============================================================================
+
+#[test]
+fn decodes_v2_responses() {
+    let ack: AckResponse = serde_json::from_str(r#"{"status":"ok"}"#).unwrap();
+    assert_eq!(ack, AckResponse::ok());
+
+    let cancel: CancelResponse = serde_json::from_str(r#"{"status":"ok","removed":true}"#).unwrap();
+    assert!(cancel.removed);
+
+    let error: ErrorResponse = serde_json::from_str(
+        r#"{"status":"error","code":"unknown_job","message":"Job 9999 is not registered with the node monitor"}"#,
+    )
+    .unwrap();
+    assert_eq!(error.code, ErrorCode::UnknownJob);
+
+    let status: StatusResponse = serde_json::from_str(V2_STATUS_RESPONSE).unwrap();
+    assert_eq!(status.escalation.stage(), "warned");
+    assert_eq!(status.enforcement_mode.to_string(), "dry_run");
+    assert_eq!(status.metrics[0].secs_until_idle, Some(3480));
+    assert_eq!(status.monitor_lost, None);
+    assert_eq!(status.supervised_monitor, None);
+}
+
+// 更新版本的守护进程可能在答复中增加字段或错误码
+#[test]
+fn tolerates_newer_responses() {
+    let ack: AckResponse = serde_json::from_str(r#"{"status":"ok","accepted_at":"2026-10-17T08:00:00Z"}"#).unwrap();
+    assert_eq!(ack.status, "ok");
+
+    let error: ErrorResponse =
+        serde_json::from_str(r#"{"status":"error","code":"node_draining","message":"try another node"}"#).unwrap();
+    assert_eq!(error.code, ErrorCode::Other);
+    assert!(!error.code.is_retryable());
+}
+
+#[test]
+fn rate_limited_requests_can_be_retried() {
+    let error: ErrorResponse =
+        serde_json::from_str(r#"{"status":"error","code":"rate_limited","message":"slow down"}"#).unwrap();
+    assert_eq!(error.code, ErrorCode::RateLimited);
+    assert!(error.code.is_retryable());
+}
+
+// ============================================================================
+// 版本协商 (Version Negotiation)
+// ============================================================================
+
+#[test]
+fn negotiates_highest_common_version() {
+    let cases = [
+        ((MIN_PROTOCOL_VERSION, PROTOCOL_VERSION), PROTOCOL_VERSION),
+        ((LEGACY_VERSION, LEGACY_VERSION), LEGACY_VERSION),
+        ((MIN_PROTOCOL_VERSION, PROTOCOL_VERSION + 3), PROTOCOL_VERSION),
+    ];
+    for ((min_version, max_version), expected) in cases {
+        let hello = HelloPayload {
+            min_version,
+            max_version,
+        };
+        assert_eq!(negotiate(&hello).unwrap(), expected, "{:?}", hello);
+    }
+}
+
+#[test]
+fn rejects_disjoint_versions() {
+    let hello = HelloPayload {
+        min_version: PROTOCOL_VERSION + 1,
+        max_version: PROTOCOL_VERSION + 2,
+    };
+    assert_eq!(negotiate(&hello).unwrap_err().code, ErrorCode::UnsupportedVersion);
+}
+
+#[test]
+fn hello_round_trips() {
+    let line = serde_json::to_string(&Message::Hello(HelloPayload::current())).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Value>(&line).unwrap(),
+        json!({"type": "HELLO", "payload": {"min_version": MIN_PROTOCOL_VERSION, "max_version": PROTOCOL_VERSION}})
+    );
+    assert!(matches!(parse_message(&line), Ok(Message::Hello(_))));
+
+    let response: HelloResponse =
+        serde_json::from_str(&serde_json::to_string(&HelloResponse::new(2)).unwrap()).unwrap();
+    assert_eq!(response.version, 2);
+}
+
+#[test]
+fn hello_response_reports_how_monitors_run() {
+    let response = HelloResponse {
+        supervise_monitors: true,
+        ..HelloResponse::new(2)
+    };
+    assert_eq!(
+        serde_json::to_value(&response).unwrap(),
+        json!({"status": "ok", "version": 2, "daemon_sampling": false, "supervise_monitors": true})
+    );
+
+    // 不报告这两项的守护进程: prolog 照常自行启动 job_helper monitor
+    let response: HelloResponse = serde_json::from_str(r#"{"status":"ok","version":2}"#).unwrap();
+    assert!(!response.daemon_sampling);
+    assert!(!response.supervise_monitors);
+}
+
+// ============================================================================
+// 错误答复 (Error Responses)
+// ============================================================================
+
+#[test]
+fn rejects_unknown_message_type() {
+    let error = parse_message(r#"{"type":"PING","payload":{}}"#).unwrap_err();
+    assert_eq!(error.code, ErrorCode::UnknownMessageType);
+    assert!(error.message.contains("PING"), "{}", error.message);
+}
+
+#[test]
+fn rejects_unknown_fields() {
+    for line in [
+        r#"{"type":"STATUS","payload":{"job_id":"4242","verbose":true}}"#,
+        r#"{"type":"STATUS","payload":{"job_id":"4242"},"id":7}"#,
+        r#"{"type":"HELLO","payload":{"min_version":1,"max_version":2,"features":[]}}"#,
+    ] {
+        let error = parse_message(line).unwrap_err();
+        assert_eq!(error.code, ErrorCode::UnknownField, "{}: {}", line, error.message);
+    }
+}
+
+#[test]
+fn rejects_malformed_requests() {
+    for line in [
+        "not json",
+        r#"{"payload":{"job_id":"4242"}}"#,
+        r#"{"type":"STATUS","payload":{}}"#,
+        r#"{"type":"METRICS","payload":{"job_id":"4242","gpu_utilization":"high","gpu_memory_utilization":0,"cpu_utilization":0}}"#,
+    ] {
+        let error = parse_message(line).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidRequest, "{}: {}", line, error.message);
+    }
+}
+
+#[test]
+fn known_types_cover_every_message() {
+    for message_type in Message::TYPES {
+        let line = json!({"type": message_type, "payload": {}}).to_string();
+        let code = parse_message(&line).map(|_| ()).unwrap_err().code;
+        assert_ne!(code, ErrorCode::UnknownMessageType, "{}", message_type);
+    }
+}
@@ -0,0 +1,400 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{Context, Result, anyhow};
+
+// ============================================================================
+// 常量定义 (Constants)
+// ============================================================================
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+// 扫描 cgroup 树时最多向下找几层 (例如 system.slice/slurmstepd.scope/job_42)
+const MAX_JOB_CGROUP_DEPTH: usize = 4;
+
+// ============================================================================
+// 任务 cgroup (Job Cgroup)
+// ============================================================================
+
+#[derive(Debug, Clone)]
+pub enum JobCgroup {
+    // cgroup v2: 统一层级, 读取 cpu.stat 中的 usage_usec
+    V2(PathBuf),
+    // cgroup v1: cpuacct 控制器读取 cpuacct.usage (纳秒), cpuset 控制器读取 cpuset.cpus
+    V1 { cpuacct: PathBuf, cpuset: Option<PathBuf> },
+}
+
+impl JobCgroup {
+    // 从 /proc/self/cgroup 中找到 Slurm 的 job_<id> 层级; 监控进程由 task prolog 启动, 与任务处于同一个 cgroup
+    pub fn discover(job_id: &str) -> Result<Self> {
+        let content = fs::read_to_string("/proc/self/cgroup").context("Failed to read /proc/self/cgroup")?;
+        Self::from_proc_cgroup(&content, job_id)
+    }
+
+    // 扫描 cgroup 树, 找出本节点上所有 Slurm 任务的 cgroup (供守护进程直接采样)
+    pub fn discover_all() -> Result<Vec<(String, Self)>> {
+        let root = Path::new(CGROUP_ROOT);
+        if root.join("cgroup.controllers").exists() {
+            return Ok(find_job_dirs(root, MAX_JOB_CGROUP_DEPTH)
+                .into_iter()
+                .map(|(job_id, dir)| (job_id, JobCgroup::V2(dir)))
+                .collect());
+        }
+
+        let cpuacct_root = ["cpuacct", "cpu,cpuacct"]
+            .iter()
+            .map(|name| root.join(name))
+            .find(|path| path.is_dir())
+            .ok_or_else(|| anyhow!("Neither cgroup v2 nor the v1 cpuacct controller is mounted"))?;
+        let cpuset_root = root.join("cpuset");
+
+        Ok(find_job_dirs(&cpuacct_root, MAX_JOB_CGROUP_DEPTH)
+            .into_iter()
+            .map(|(job_id, cpuacct)| {
+                let cpuset = cpuacct
+                    .strip_prefix(&cpuacct_root)
+                    .ok()
+                    .map(|relative| cpuset_root.join(relative))
+                    .filter(|path| path.is_dir());
+                (job_id, JobCgroup::V1 { cpuacct, cpuset })
+            })
+            .collect())
+    }
+
+    fn from_proc_cgroup(content: &str, job_id: &str) -> Result<Self> {
+        let job_dir = format!("job_{}", job_id);
+        let mut cpuacct = None;
+        let mut cpuset = None;
+
+        for line in content.lines() {
+            // 格式: hierarchy-ID:controller-list:cgroup-path
+            let mut parts = line.splitn(3, ':');
+            let (Some(_), Some(controllers), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+                continue;
+            };
+            let Some(job_path) = truncate_at_job(path, &job_dir) else {
+                continue;
+            };
+
+            if controllers.is_empty() {
+                return Ok(JobCgroup::V2(Path::new(CGROUP_ROOT).join(job_path)));
+            }
+            let controller_list: Vec<&str> = controllers.split(',').collect();
+            if controller_list.contains(&"cpuacct") {
+                cpuacct = Some(Path::new(CGROUP_ROOT).join(controllers).join(&job_path));
+            }
+            if controller_list.contains(&"cpuset") {
+                cpuset = Some(Path::new(CGROUP_ROOT).join(controllers).join(job_path));
+            }
+        }
+
+        match cpuacct {
+            Some(cpuacct) => Ok(JobCgroup::V1 { cpuacct, cpuset }),
+            None => Err(anyhow!("No cgroup for job {} found in /proc/self/cgroup", job_id)),
+        }
+    }
+
+    // 任务累计 CPU 时间 (微秒)
+    pub fn cpu_usage_usec(&self) -> Result<u64> {
+        match self {
+            JobCgroup::V2(dir) => {
+                let path = dir.join("cpu.stat");
+                let stat = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+                stat.lines()
+                    .find_map(|line| line.strip_prefix("usage_usec "))
+                    .ok_or_else(|| anyhow!("usage_usec not found in {}", path.display()))?
+                    .trim()
+                    .parse::<u64>()
+                    .with_context(|| format!("Invalid usage_usec in {}", path.display()))
+            }
+            JobCgroup::V1 { cpuacct, .. } => {
+                let path = cpuacct.join("cpuacct.usage");
+                let nanos = fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?
+                    .trim()
+                    .parse::<u64>()
+                    .with_context(|| format!("Invalid value in {}", path.display()))?;
+                Ok(nanos / 1000)
+            }
+        }
+    }
+
+    // 任务可用的 CPU 列表, 例如 "0-3,8"
+    fn cpuset(&self) -> Result<String> {
+        let path = match self {
+            JobCgroup::V2(dir) => dir.join("cpuset.cpus.effective"),
+            JobCgroup::V1 { cpuset: Some(dir), .. } => dir.join("cpuset.cpus"),
+            JobCgroup::V1 { cpuset: None, .. } => return Err(anyhow!("cpuset controller not mounted")),
+        };
+        Ok(fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .trim()
+            .to_string())
+    }
+}
+
+// 进程与任务 cgroup 的关系
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessMembership {
+    InJob,
+    NotInJob,
+    // 读不到 /proc/<pid>/cgroup: 进程已退出, 或 GPU 驱动报告的是另一个 PID 命名空间中的进程号
+    Unknown,
+}
+
+// 判断进程是否属于该任务的 cgroup (包括其下的 step/task 子层级)
+pub fn process_in_job(pid: u32, job_id: &str) -> ProcessMembership {
+    match fs::read_to_string(format!("/proc/{}/cgroup", pid)) {
+        Ok(content) => membership_from_proc_cgroup(&content, job_id),
+        Err(_) => ProcessMembership::Unknown,
+    }
+}
+
+fn membership_from_proc_cgroup(content: &str, job_id: &str) -> ProcessMembership {
+    let job_dir = format!("job_{}", job_id);
+    let in_job = content.lines().any(|line| {
+        line.splitn(3, ':')
+            .nth(2)
+            .is_some_and(|path| truncate_at_job(path, &job_dir).is_some())
+    });
+    if in_job {
+        ProcessMembership::InJob
+    } else {
+        ProcessMembership::NotInJob
+    }
+}
+
+// 进程所在的、属于该任务的 cgroup 目录 (v2 只有一个, v1 每个层级一个);
+// 守护进程把它启动的 job_helper monitor 加入这些目录, 使其与任务一起统计和清理
+pub fn job_cgroup_dirs(pid: u32, job_id: &str) -> Result<Vec<PathBuf>> {
+    let path = format!("/proc/{}/cgroup", pid);
+    let content = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+    let job_dir = format!("job_{}", job_id);
+    let root = Path::new(CGROUP_ROOT);
+
+    let mut dirs = Vec::new();
+    for line in content.lines() {
+        let mut parts = line.splitn(3, ':');
+        let (Some(_), Some(controllers), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+            continue;
+        };
+        if truncate_at_job(path, &job_dir).is_none() {
+            continue;
+        }
+        // 进程只能位于叶子层级 (例如 step_0/user/task_0), 所以使用完整路径而不是 job_<id> 本身
+        let relative = path.trim_start_matches('/');
+        let dir = if controllers.is_empty() {
+            root.join(relative)
+        } else {
+            root.join(controllers.trim_start_matches("name=")).join(relative)
+        };
+        dirs.push(dir);
+    }
+    if dirs.is_empty() {
+        return Err(anyhow!("Process {} is not in a cgroup of job {}", pid, job_id));
+    }
+    Ok(dirs)
+}
+
+// 在 dir 下查找 job_<id> 目录, 找到后不再向下进入 step 子层级
+fn find_job_dirs(dir: &Path, depth: usize) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut jobs = Vec::new();
+    for entry in entries.flatten() {
+        if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        match name.strip_prefix("job_") {
+            Some(id) if !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()) => {
+                jobs.push((id.to_string(), entry.path()));
+            }
+            _ if depth > 0 => jobs.extend(find_job_dirs(&entry.path(), depth - 1)),
+            _ => {}
+        }
+    }
+    jobs
+}
+
+// "/system.slice/slurmstepd.scope/job_42/step_0/user" -> "system.slice/slurmstepd.scope/job_42"
+fn truncate_at_job(path: &str, job_dir: &str) -> Option<PathBuf> {
+    let mut result = PathBuf::new();
+    for component in path.trim_start_matches('/').split('/') {
+        result.push(component);
+        if component == job_dir {
+            return Some(result);
+        }
+    }
+    None
+}
+
+// ============================================================================
+// 任务 CPU 利用率采样 (Job CPU Sampler)
+// ============================================================================
+
+pub struct JobCpuSampler {
+    cgroup: JobCgroup,
+    allocated_cpus: usize,
+    last_sample: Option<(Instant, u64)>,
+}
+
+impl JobCpuSampler {
+    pub fn new(job_id: &str) -> Result<Self> {
+        Self::from_cgroup(JobCgroup::discover(job_id)?)
+    }
+
+    pub fn from_cgroup(cgroup: JobCgroup) -> Result<Self> {
+        let allocated_cpus = allocated_cpus(&cgroup)?;
+        Ok(Self {
+            cgroup,
+            allocated_cpus,
+            last_sample: None,
+        })
+    }
+
+    pub fn allocated_cpus(&self) -> usize {
+        self.allocated_cpus
+    }
+
+    pub fn cgroup(&self) -> &JobCgroup {
+        &self.cgroup
+    }
+
+    // 返回自上次采样以来, 任务占用其分配 CPU 的百分比 (0-100)
+    // 第一次调用时没有上一次的数据, 先等待一秒再采样
+    pub fn sample(&mut self) -> Result<f64> {
+        if self.last_sample.is_none() {
+            self.last_sample = Some((Instant::now(), self.cgroup.cpu_usage_usec()?));
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+
+        self.sample_since_last()?
+            .ok_or_else(|| anyhow!("No previous CPU sample to compare against"))
+    }
+
+    // 不等待的版本: 第一次调用只记录当前值并返回 None, 适合按固定间隔轮询的调用方
+    pub fn sample_since_last(&mut self) -> Result<Option<f64>> {
+        let now = Instant::now();
+        let usage = self.cgroup.cpu_usage_usec()?;
+        let Some((last_time, last_usage)) = self.last_sample.replace((now, usage)) else {
+            return Ok(None);
+        };
+
+        let elapsed_usec = now.duration_since(last_time).as_micros() as f64;
+        if elapsed_usec <= 0.0 {
+            return Err(anyhow!("No time elapsed since the last CPU sample"));
+        }
+        let used_usec = usage.saturating_sub(last_usage) as f64;
+        let utilization = used_usec / (elapsed_usec * self.allocated_cpus as f64) * 100.0;
+        Ok(Some(utilization.min(100.0)))
+    }
+}
+
+// 优先使用 SLURM_CPUS_ON_NODE, 否则根据 cgroup 的 cpuset 计算
+fn allocated_cpus(cgroup: &JobCgroup) -> Result<usize> {
+    if let Some(cpus) = env::var("SLURM_CPUS_ON_NODE")
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|&n| n > 0)
+    {
+        return Ok(cpus);
+    }
+
+    let cpuset = cgroup.cpuset()?;
+    let count = parse_cpu_list(&cpuset)?;
+    if count == 0 {
+        return Err(anyhow!("Empty cpuset for job cgroup"));
+    }
+    Ok(count)
+}
+
+// 解析 "0-3,8,10-11" 这样的 CPU 列表, 返回 CPU 个数
+pub fn parse_cpu_list(list: &str) -> Result<usize> {
+    let mut count = 0;
+    for range in list.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => {
+                let start: usize = start
+                    .parse()
+                    .with_context(|| format!("Invalid CPU range '{}'", range))?;
+                let end: usize = end.parse().with_context(|| format!("Invalid CPU range '{}'", range))?;
+                if end < start {
+                    return Err(anyhow!("Invalid CPU range '{}'", range));
+                }
+                count += end - start + 1;
+            }
+            None => {
+                range
+                    .parse::<usize>()
+                    .with_context(|| format!("Invalid CPU '{}'", range))?;
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V2_JOB: &str = "0::/system.slice/slurmstepd.scope/job_42/step_0/user/task_0\n";
+    const V1_JOB: &str = "\
+12:pids:/slurm/uid_1500/job_42/step_0
+4:cpuacct,cpu:/slurm/uid_1500/job_42/step_0/task_0
+1:name=systemd:/user.slice/user-1500.slice
+";
+
+    #[test]
+    fn processes_in_the_job_cgroup_belong_to_the_job() {
+        assert_eq!(membership_from_proc_cgroup(V2_JOB, "42"), ProcessMembership::InJob);
+        assert_eq!(membership_from_proc_cgroup(V1_JOB, "42"), ProcessMembership::InJob);
+    }
+
+    #[test]
+    fn processes_of_other_jobs_do_not_belong_to_the_job() {
+        assert_eq!(membership_from_proc_cgroup(V2_JOB, "4"), ProcessMembership::NotInJob);
+        assert_eq!(membership_from_proc_cgroup(V2_JOB, "420"), ProcessMembership::NotInJob);
+        assert_eq!(
+            membership_from_proc_cgroup("0::/user.slice/user-1500.slice/session-3.scope\n", "42"),
+            ProcessMembership::NotInJob
+        );
+    }
+
+    #[test]
+    fn counts_cpus_in_cpu_lists() {
+        assert_eq!(parse_cpu_list("0-3").unwrap(), 4);
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n").unwrap(), 7);
+        assert_eq!(parse_cpu_list("5").unwrap(), 1);
+        assert_eq!(parse_cpu_list(" 0 , 2-2 ").unwrap(), 2);
+        assert_eq!(parse_cpu_list("").unwrap(), 0);
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("0-x").is_err());
+        assert!(parse_cpu_list("a,b").is_err());
+    }
+
+    #[test]
+    fn truncates_cgroup_paths_at_the_job_directory() {
+        assert_eq!(
+            truncate_at_job("/system.slice/slurmstepd.scope/job_42/step_0/user", "job_42"),
+            Some(PathBuf::from("system.slice/slurmstepd.scope/job_42"))
+        );
+        assert_eq!(
+            truncate_at_job("/slurm/uid_1500/job_42", "job_42"),
+            Some(PathBuf::from("slurm/uid_1500/job_42"))
+        );
+        // 只按完整的目录名匹配
+        assert_eq!(truncate_at_job("/slurm/uid_1500/job_420/step_0", "job_42"), None);
+        assert_eq!(truncate_at_job("/user.slice/user-1500.slice", "job_42"), None);
+    }
+
+    #[test]
+    fn unreadable_processes_are_unknown() {
+        // 超出 pid_max 的进程号, 与其他 PID 命名空间中的进程一样在本机 /proc 下不存在
+        assert_eq!(process_in_job(u32::MAX, "42"), ProcessMembership::Unknown);
+    }
+}
@@ -0,0 +1,138 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+
+use super::{GpuBackend, GpuDevice, GpuProcessSample};
+
+// ============================================================================
+// 伪造数据 (Fake Backend)
+// ============================================================================
+
+// 从 JSON 文件回放预先录制的读数, 用于没有 GPU 的构建机和测试:
+// {
+//   "devices": [{ "index": "0", "uuid": "GPU-fake-0", "name": "NVIDIA A10", "memory_total_mib": 24576 }],
+//   "frames": [
+//     [{ "gpu_uuid": "GPU-fake-0", "sm_utilization": 80, "used_memory_mib": 4096 }],
+//     []
+//   ]
+// }
+// 每次采样返回下一帧, 回放到最后一帧后从头开始
+#[derive(Deserialize)]
+struct FakeData {
+    devices: Vec<GpuDevice>,
+    #[serde(default)]
+    frames: Vec<Vec<GpuProcessSample>>,
+}
+
+pub struct FakeBackend {
+    data: FakeData,
+    next_frame: usize,
+}
+
+impl FakeBackend {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read fake GPU data {}", path.display()))?;
+        let data: FakeData = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse fake GPU data {}", path.display()))?;
+        Ok(Self { data, next_frame: 0 })
+    }
+}
+
+impl GpuBackend for FakeBackend {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    fn devices(&mut self, visible_devices: &str) -> Result<Vec<GpuDevice>> {
+        let wanted: Vec<&str> = visible_devices
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .collect();
+        let devices: Vec<GpuDevice> = self
+            .data
+            .devices
+            .iter()
+            .filter(|d| wanted.is_empty() || wanted.contains(&d.index.as_str()) || wanted.contains(&d.uuid.as_str()))
+            .cloned()
+            .collect();
+        if devices.is_empty() {
+            return Err(anyhow!("No fake GPUs match '{}'", visible_devices));
+        }
+        Ok(devices)
+    }
+
+    fn process_samples(&mut self, _devices: &[GpuDevice]) -> Result<Vec<GpuProcessSample>> {
+        if self.data.frames.is_empty() {
+            return Ok(Vec::new());
+        }
+        let frame = self.data.frames[self.next_frame].clone();
+        self.next_frame = (self.next_frame + 1) % self.data.frames.len();
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend() -> FakeBackend {
+        let data = serde_json::from_str(
+            r#"{
+                "devices": [
+                    { "index": "0", "uuid": "GPU-fake-0", "name": "NVIDIA A10", "memory_total_mib": 24576 },
+                    { "index": "1", "uuid": "GPU-fake-1", "name": "NVIDIA A10", "memory_total_mib": 24576 }
+                ],
+                "frames": [
+                    [{ "gpu_uuid": "GPU-fake-0", "pid": 7, "sm_utilization": 80, "used_memory_mib": 4096 }],
+                    []
+                ]
+            }"#,
+        )
+        .unwrap();
+        FakeBackend { data, next_frame: 0 }
+    }
+
+    #[test]
+    fn selects_devices_by_index_or_uuid() {
+        let mut backend = backend();
+        let uuids = |devices: Vec<GpuDevice>| devices.into_iter().map(|d| d.uuid).collect::<Vec<_>>();
+        assert_eq!(uuids(backend.devices("1").unwrap()), ["GPU-fake-1"]);
+        assert_eq!(
+            uuids(backend.devices("GPU-fake-0, 1").unwrap()),
+            ["GPU-fake-0", "GPU-fake-1"]
+        );
+        assert_eq!(uuids(backend.devices("").unwrap()), ["GPU-fake-0", "GPU-fake-1"]);
+        assert!(backend.devices("2").is_err());
+    }
+
+    #[test]
+    fn replays_frames_in_a_loop() {
+        let mut backend = backend();
+        let devices = backend.devices("0").unwrap();
+        let pids = |samples: Vec<GpuProcessSample>| samples.into_iter().map(|s| s.pid).collect::<Vec<_>>();
+        assert_eq!(pids(backend.process_samples(&devices).unwrap()), [7]);
+        assert!(backend.process_samples(&devices).unwrap().is_empty());
+        assert_eq!(pids(backend.process_samples(&devices).unwrap()), [7]);
+    }
+
+    #[test]
+    fn omitted_pid_defaults_to_the_current_process() {
+        let sample: GpuProcessSample = serde_json::from_str(r#"{ "gpu_uuid": "GPU-fake-0" }"#).unwrap();
+        assert_eq!(sample.pid, std::process::id());
+        assert_eq!((sample.sm_utilization, sample.used_memory_mib), (0.0, 0.0));
+    }
+
+    #[test]
+    fn missing_or_invalid_files_are_errors() {
+        let dir = std::env::temp_dir();
+        assert!(FakeBackend::load(&dir.join("no_such_fake_gpu_data.json")).is_err());
+        let path = dir.join(format!("fake_gpu_invalid_{}.json", std::process::id()));
+        std::fs::write(&path, "{ \"frames\": [] }").unwrap();
+        let result = FakeBackend::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
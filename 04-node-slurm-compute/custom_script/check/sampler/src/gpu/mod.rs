@@ -0,0 +1,324 @@
+mod fake;
+mod nvidia_smi;
+#[cfg(feature = "nvml")]
+mod nvml;
+
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::cgroup::ProcessMembership;
+
+// ============================================================================
+// 数据结构定义 (Data Structures)
+// ============================================================================
+
+// 任务可见的一张 GPU
+#[derive(Deserialize, Debug, Clone)]
+pub struct GpuDevice {
+    pub index: String,
+    pub uuid: String,
+    pub name: String,
+    pub memory_total_mib: f64,
+}
+
+// 某个计算进程在某张 GPU 上的占用
+#[derive(Deserialize, Debug, Clone)]
+pub struct GpuProcessSample {
+    pub gpu_uuid: String,
+    // 伪造数据中省略 pid 时使用当前进程, 这样在任务内回放时会被算作任务自己的进程
+    #[serde(default = "std::process::id")]
+    pub pid: u32,
+    #[serde(default)]
+    pub sm_utilization: f64,
+    #[serde(default)]
+    pub used_memory_mib: f64,
+}
+
+// 归属到任务自身进程的 GPU 占用, 各卡取平均 (百分比)
+#[derive(Debug, Clone, Default)]
+pub struct JobGpuUsage {
+    pub utilization: f64,
+    pub memory_utilization: f64,
+    // 有卡上的进程全部无法判断归属, 平均值不可信, 应当作缺失的样本而不是空闲
+    pub unknown: bool,
+    // 每张卡各自的占用, 用于发现多卡任务中闲置的卡; 不含无法判断归属的卡
+    pub devices: Vec<DeviceGpuUsage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceGpuUsage {
+    pub uuid: String,
+    pub utilization: f64,
+    pub memory_utilization: f64,
+}
+
+// ============================================================================
+// GPU 数据来源 (GPU Backend)
+// ============================================================================
+
+pub trait GpuBackend: Send {
+    fn name(&self) -> &'static str;
+
+    // 列出 CUDA_VISIBLE_DEVICES (编号或 UUID, 逗号分隔) 对应的 GPU
+    fn devices(&mut self, visible_devices: &str) -> Result<Vec<GpuDevice>>;
+
+    // 采样这些 GPU 上所有计算进程的 SM 与显存占用
+    fn process_samples(&mut self, devices: &[GpuDevice]) -> Result<Vec<GpuProcessSample>>;
+}
+
+#[derive(clap::ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackendKind {
+    // 编译了 NVML 支持且能加载驱动库时使用 NVML, 否则使用 nvidia-smi
+    #[default]
+    Auto,
+    Nvml,
+    NvidiaSmi,
+    Fake,
+}
+
+pub fn create_backend(kind: BackendKind, fake_file: Option<&Path>) -> Result<Box<dyn GpuBackend>> {
+    let backend: Box<dyn GpuBackend> = match kind {
+        BackendKind::Auto => auto_backend(),
+        BackendKind::Nvml => nvml_backend()?,
+        BackendKind::NvidiaSmi => Box::new(nvidia_smi::NvidiaSmiBackend),
+        BackendKind::Fake => {
+            let path = fake_file.ok_or_else(|| anyhow!("The fake GPU backend requires --gpu-fake-file"))?;
+            Box::new(fake::FakeBackend::load(path)?)
+        }
+    };
+    info!("Using GPU backend: {}", backend.name());
+    Ok(backend)
+}
+
+fn auto_backend() -> Box<dyn GpuBackend> {
+    match nvml_backend() {
+        Ok(backend) => backend,
+        Err(e) => {
+            info!("NVML backend unavailable ({:#}), falling back to nvidia-smi.", e);
+            Box::new(nvidia_smi::NvidiaSmiBackend)
+        }
+    }
+}
+
+#[cfg(feature = "nvml")]
+fn nvml_backend() -> Result<Box<dyn GpuBackend>> {
+    Ok(Box::new(nvml::NvmlBackend::new().context("Failed to initialize NVML")?))
+}
+
+#[cfg(not(feature = "nvml"))]
+fn nvml_backend() -> Result<Box<dyn GpuBackend>> {
+    Err(anyhow!("built without the 'nvml' feature")).context("NVML backend not available")
+}
+
+// ============================================================================
+// 任务归属计算 (Job Attribution)
+// ============================================================================
+
+// 只统计属于任务的进程, 其他进程 (例如上一个任务残留的进程) 产生的负载不算在内.
+// 某张卡上有进程、但全部无法判断归属时, 这张卡的读数未知
+pub fn job_gpu_usage(
+    devices: &[GpuDevice],
+    samples: &[GpuProcessSample],
+    membership: impl Fn(u32) -> ProcessMembership,
+) -> JobGpuUsage {
+    if devices.is_empty() {
+        return JobGpuUsage::default();
+    }
+
+    let mut usage = JobGpuUsage::default();
+    for device in devices {
+        let (mut job_sm, mut job_memory, mut foreign_sm) = (0.0, 0.0, 0.0);
+        let (mut processes, mut unresolved) = (0, 0);
+        for sample in samples.iter().filter(|s| s.gpu_uuid == device.uuid) {
+            processes += 1;
+            match membership(sample.pid) {
+                ProcessMembership::InJob => {
+                    job_sm += sample.sm_utilization;
+                    job_memory += sample.used_memory_mib;
+                }
+                ProcessMembership::NotInJob => foreign_sm += sample.sm_utilization,
+                ProcessMembership::Unknown => unresolved += 1,
+            }
+        }
+        if processes > 0 && unresolved == processes {
+            warn!(
+                "Could not tell which job the {} process(es) on GPU {} belong to. Treating its sample as unknown.",
+                processes, device.index
+            );
+            usage.unknown = true;
+            continue;
+        }
+        if job_sm == 0.0 && foreign_sm > 0.0 {
+            warn!(
+                "GPU {} shows {:.0}% SM activity, but none of it comes from the job's own processes.",
+                device.index, foreign_sm
+            );
+        }
+
+        let utilization = job_sm.min(100.0);
+        let memory_utilization = if device.memory_total_mib > 0.0 {
+            (job_memory / device.memory_total_mib * 100.0).min(100.0)
+        } else {
+            0.0
+        };
+        usage.utilization += utilization;
+        usage.memory_utilization += memory_utilization;
+        usage.devices.push(DeviceGpuUsage {
+            uuid: device.uuid.clone(),
+            utilization,
+            memory_utilization,
+        });
+    }
+
+    if !usage.devices.is_empty() {
+        usage.utilization /= usage.devices.len() as f64;
+        usage.memory_utilization /= usage.devices.len() as f64;
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const JOB_PID: u32 = 100;
+    const FOREIGN_PID: u32 = 200;
+    const HIDDEN_PID: u32 = 300;
+
+    fn device(index: &str) -> GpuDevice {
+        GpuDevice {
+            index: index.to_string(),
+            uuid: format!("GPU-{}", index),
+            name: "NVIDIA GeForce RTX 4090".to_string(),
+            memory_total_mib: 1000.0,
+        }
+    }
+
+    fn sample(index: &str, pid: u32, sm_utilization: f64, used_memory_mib: f64) -> GpuProcessSample {
+        GpuProcessSample {
+            gpu_uuid: format!("GPU-{}", index),
+            pid,
+            sm_utilization,
+            used_memory_mib,
+        }
+    }
+
+    fn membership(pid: u32) -> ProcessMembership {
+        match pid {
+            JOB_PID => ProcessMembership::InJob,
+            FOREIGN_PID => ProcessMembership::NotInJob,
+            _ => ProcessMembership::Unknown,
+        }
+    }
+
+    #[test]
+    fn counts_only_the_jobs_processes() {
+        let samples = [sample("0", JOB_PID, 30.0, 200.0), sample("0", FOREIGN_PID, 60.0, 500.0)];
+        let usage = job_gpu_usage(&[device("0")], &samples, membership);
+        assert!(!usage.unknown);
+        assert_eq!((usage.utilization, usage.memory_utilization), (30.0, 20.0));
+        assert_eq!(usage.devices.len(), 1);
+    }
+
+    #[test]
+    fn foreign_load_on_an_otherwise_idle_gpu_is_idle() {
+        let samples = [sample("0", FOREIGN_PID, 90.0, 800.0)];
+        let usage = job_gpu_usage(&[device("0")], &samples, membership);
+        assert!(!usage.unknown);
+        assert_eq!((usage.utilization, usage.memory_utilization), (0.0, 0.0));
+    }
+
+    #[test]
+    fn gpu_without_processes_is_idle_not_unknown() {
+        let usage = job_gpu_usage(&[device("0")], &[], membership);
+        assert!(!usage.unknown);
+        assert_eq!(usage.devices.len(), 1);
+        assert_eq!(usage.utilization, 0.0);
+    }
+
+    #[test]
+    fn gpu_with_only_unresolvable_processes_is_unknown() {
+        let samples = [sample("0", HIDDEN_PID, 90.0, 800.0)];
+        let usage = job_gpu_usage(&[device("0")], &samples, membership);
+        assert!(usage.unknown);
+        assert!(usage.devices.is_empty());
+    }
+
+    #[test]
+    fn unresolvable_processes_next_to_resolved_ones_are_ignored() {
+        let samples = [sample("0", JOB_PID, 40.0, 100.0), sample("0", HIDDEN_PID, 50.0, 300.0)];
+        let usage = job_gpu_usage(&[device("0")], &samples, membership);
+        assert!(!usage.unknown);
+        assert_eq!((usage.utilization, usage.memory_utilization), (40.0, 10.0));
+    }
+
+    #[test]
+    fn unknown_gpus_are_left_out_of_the_average() {
+        let devices = [device("0"), device("1"), device("2")];
+        let samples = [
+            sample("0", JOB_PID, 80.0, 500.0),
+            sample("1", HIDDEN_PID, 90.0, 900.0),
+            sample("2", FOREIGN_PID, 70.0, 300.0),
+        ];
+        let usage = job_gpu_usage(&devices, &samples, membership);
+        assert!(usage.unknown);
+        assert_eq!((usage.utilization, usage.memory_utilization), (40.0, 25.0));
+        let uuids: Vec<&str> = usage.devices.iter().map(|d| d.uuid.as_str()).collect();
+        assert_eq!(uuids, ["GPU-0", "GPU-2"]);
+    }
+
+    #[test]
+    fn utilization_is_capped_per_gpu() {
+        let samples = [sample("0", JOB_PID, 70.0, 900.0), sample("0", JOB_PID, 60.0, 300.0)];
+        let usage = job_gpu_usage(&[device("0")], &samples, membership);
+        assert_eq!((usage.utilization, usage.memory_utilization), (100.0, 100.0));
+    }
+
+    #[test]
+    fn fake_backend_requires_a_data_file() {
+        assert!(create_backend(BackendKind::Fake, None).is_err());
+    }
+
+    #[cfg(not(feature = "nvml"))]
+    #[test]
+    fn nvml_backend_is_unavailable_without_the_feature() {
+        assert!(create_backend(BackendKind::Nvml, None).is_err());
+    }
+
+    #[test]
+    fn attributes_fake_backend_frames_to_the_job() {
+        let path = std::env::temp_dir().join(format!("fake_gpu_select_{}.json", std::process::id()));
+        fs::write(
+            &path,
+            r#"{
+                "devices": [
+                    { "index": "0", "uuid": "GPU-0", "name": "NVIDIA A10", "memory_total_mib": 1000 },
+                    { "index": "1", "uuid": "GPU-1", "name": "NVIDIA A10", "memory_total_mib": 1000 }
+                ],
+                "frames": [[
+                    { "gpu_uuid": "GPU-0", "pid": 100, "sm_utilization": 60, "used_memory_mib": 400 },
+                    { "gpu_uuid": "GPU-1", "pid": 200, "sm_utilization": 90, "used_memory_mib": 900 }
+                ]]
+            }"#,
+        )
+        .unwrap();
+        let backend = create_backend(BackendKind::Fake, Some(&path));
+        fs::remove_file(&path).unwrap();
+        let mut backend = backend.unwrap();
+        assert_eq!(backend.name(), "fake");
+
+        let devices = backend.devices("0,1").unwrap();
+        let samples = backend.process_samples(&devices).unwrap();
+        let usage = job_gpu_usage(&devices, &samples, membership);
+        assert!(!usage.unknown);
+        assert_eq!((usage.utilization, usage.memory_utilization), (30.0, 20.0));
+        let per_device: Vec<f64> = usage.devices.iter().map(|d| d.utilization).collect();
+        assert_eq!(per_device, [60.0, 0.0]);
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
+
+use super::{GpuBackend, GpuDevice, GpuProcessSample};
+use crate::run_command;
+
+// ============================================================================
+// nvidia-smi 解析 (nvidia-smi Backend)
+// ============================================================================
+
+pub struct NvidiaSmiBackend;
+
+impl GpuBackend for NvidiaSmiBackend {
+    fn name(&self) -> &'static str {
+        "nvidia-smi"
+    }
+
+    fn devices(&mut self, visible_devices: &str) -> Result<Vec<GpuDevice>> {
+        let output = run_command(
+            "nvidia-smi",
+            &["--query-gpu=index,uuid,name,memory.total", "--format=csv,noheader,nounits", "--id", visible_devices],
+        )?;
+
+        Ok(output
+            .lines()
+            .filter_map(|line| {
+                let parts: Vec<&str> = line.split(',').map(str::trim).collect();
+                if parts.len() != 4 {
+                    return None;
+                }
+                Some(GpuDevice {
+                    index: parts[0].to_string(),
+                    uuid: parts[1].to_string(),
+                    name: parts[2].to_string(),
+                    memory_total_mib: parts[3].parse().ok()?,
+                })
+            })
+            .collect())
+    }
+
+    // pmon 按 GPU 编号给出 SM 占用, compute-apps 按 UUID 给出显存, 这里按 (UUID, PID) 合并
+    fn process_samples(&mut self, devices: &[GpuDevice]) -> Result<Vec<GpuProcessSample>> {
+        let uuid_by_index: HashMap<&str, &str> = devices.iter().map(|d| (d.index.as_str(), d.uuid.as_str())).collect();
+        let mut samples: HashMap<(String, u32), GpuProcessSample> = HashMap::new();
+
+        let pmon = run_command("nvidia-smi", &["pmon", "--count", "1", "--select", "u"])?;
+        for (index, pid, sm) in parse_pmon(&pmon)? {
+            let Some(uuid) = uuid_by_index.get(index.as_str()) else {
+                continue;
+            };
+            sample_entry(&mut samples, uuid, pid).sm_utilization += sm;
+        }
+
+        let apps = run_command(
+            "nvidia-smi",
+            &["--query-compute-apps=pid,gpu_uuid,used_memory", "--format=csv,noheader,nounits"],
+        )?;
+        for line in apps.lines() {
+            let parts: Vec<&str> = line.split(',').map(str::trim).collect();
+            if parts.len() != 3 {
+                continue;
+            }
+            let (Ok(pid), Ok(used)) = (parts[0].parse::<u32>(), parts[2].parse::<f64>()) else {
+                continue;
+            };
+            sample_entry(&mut samples, parts[1], pid).used_memory_mib += used;
+        }
+
+        Ok(samples.into_values().collect())
+    }
+}
+
+fn sample_entry<'a>(
+    samples: &'a mut HashMap<(String, u32), GpuProcessSample>,
+    uuid: &str,
+    pid: u32,
+) -> &'a mut GpuProcessSample {
+    samples.entry((uuid.to_string(), pid)).or_insert_with(|| GpuProcessSample {
+        gpu_uuid: uuid.to_string(),
+        pid,
+        sm_utilization: 0.0,
+        used_memory_mib: 0.0,
+    })
+}
+
+// pmon 的列随驱动版本变化, 所以按表头定位列:
+// # gpu        pid  type    sm   mem   enc   dec   command
+// # Idx          #   C/G     %     %     %     %   name
+//     0      12345     C    45    12     -     -   python
+fn parse_pmon(output: &str) -> Result<Vec<(String, u32, f64)>> {
+    let header: Vec<&str> = output
+        .lines()
+        .find(|line| line.starts_with('#'))
+        .ok_or_else(|| anyhow!("Missing header in nvidia-smi pmon output"))?
+        .trim_start_matches('#')
+        .split_whitespace()
+        .collect();
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|c| *c == name)
+            .ok_or_else(|| anyhow!("Column '{}' missing in nvidia-smi pmon output", name))
+    };
+    let (gpu_col, pid_col, sm_col) = (column("gpu")?, column("pid")?, column("sm")?);
+
+    Ok(output
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            Some((
+                fields.get(gpu_col)?.to_string(),
+                fields.get(pid_col)?.parse().ok()?,
+                // 没有采样到的值显示为 "-"
+                fields.get(sm_col)?.parse().unwrap_or(0.0),
+            ))
+        })
+        .collect())
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use nvml_wrapper::Nvml;
+use nvml_wrapper::enums::device::UsedGpuMemory;
+
+use super::{GpuBackend, GpuDevice, GpuProcessSample};
+
+// ============================================================================
+// NVML (NVML Backend)
+// ============================================================================
+
+pub struct NvmlBackend {
+    nvml: Nvml,
+    // 每张卡上次取到的进程采样时间戳, 下次只取之后的样本
+    last_seen: HashMap<String, u64>,
+}
+
+impl NvmlBackend {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            nvml: Nvml::init()?,
+            last_seen: HashMap::new(),
+        })
+    }
+}
+
+impl GpuBackend for NvmlBackend {
+    fn name(&self) -> &'static str {
+        "nvml"
+    }
+
+    fn devices(&mut self, visible_devices: &str) -> Result<Vec<GpuDevice>> {
+        let mut devices = Vec::new();
+        for id in visible_devices.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+            let device = match id.parse::<u32>() {
+                Ok(index) => self.nvml.device_by_index(index),
+                Err(_) => self.nvml.device_by_uuid(id),
+            }
+            .with_context(|| format!("Failed to look up GPU '{}'", id))?;
+
+            devices.push(GpuDevice {
+                index: device.index()?.to_string(),
+                uuid: device.uuid()?,
+                name: device.name()?,
+                memory_total_mib: device.memory_info()?.total as f64 / (1024.0 * 1024.0),
+            });
+        }
+        Ok(devices)
+    }
+
+    fn process_samples(&mut self, devices: &[GpuDevice]) -> Result<Vec<GpuProcessSample>> {
+        let mut samples = Vec::new();
+        for gpu in devices {
+            let device = self.nvml.device_by_uuid(gpu.uuid.as_str())?;
+
+            // 驱动缓存了一段时间内的多个样本, 同一进程取平均
+            let last_seen = self.last_seen.get(&gpu.uuid).copied();
+            let mut sm_by_pid: HashMap<u32, (f64, usize)> = HashMap::new();
+            for sample in device.process_utilization_stats(last_seen).unwrap_or_default() {
+                let entry = sm_by_pid.entry(sample.pid).or_default();
+                entry.0 += sample.sm_util as f64;
+                entry.1 += 1;
+                let newest = self.last_seen.entry(gpu.uuid.clone()).or_default();
+                *newest = (*newest).max(sample.timestamp);
+            }
+
+            for process in device.running_compute_processes()? {
+                let used_memory_mib = match process.used_gpu_memory {
+                    UsedGpuMemory::Used(bytes) => bytes as f64 / (1024.0 * 1024.0),
+                    UsedGpuMemory::Unavailable => 0.0,
+                };
+                let sm_utilization = sm_by_pid
+                    .remove(&process.pid)
+                    .map(|(sum, count)| sum / count as f64)
+                    .unwrap_or(0.0);
+                samples.push(GpuProcessSample {
+                    gpu_uuid: gpu.uuid.clone(),
+                    pid: process.pid,
+                    sm_utilization,
+                    used_memory_mib,
+                });
+            }
+
+            // 有 SM 占用但已不在计算进程列表中的进程 (例如刚退出), 同样计入
+            for (pid, (sum, count)) in sm_by_pid {
+                samples.push(GpuProcessSample {
+                    gpu_uuid: gpu.uuid.clone(),
+                    pid,
+                    sm_utilization: sum / count as f64,
+                    used_memory_mib: 0.0,
+                });
+            }
+        }
+        Ok(samples)
+    }
+}
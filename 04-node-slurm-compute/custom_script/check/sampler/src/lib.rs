@@ -0,0 +1,31 @@
+// job_helper 与 node_monitor 共用的任务资源采样: cgroup 中的 CPU 时间, 以及各 GPU 后端
+pub mod cgroup;
+pub mod gpu;
+
+use std::process::Command;
+use std::str;
+
+use anyhow::{Context, Result, anyhow};
+
+// ============================================================================
+// 辅助函数 (Helper Functions)
+// ============================================================================
+
+pub(crate) fn run_command(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to execute '{}'", program))?;
+
+    if !output.status.success() {
+        let stderr = str::from_utf8(&output.stderr).unwrap_or("Non-UTF8 error output");
+        return Err(anyhow!(
+            "'{}' command failed with status {}: {}",
+            program,
+            output.status,
+            stderr
+        ));
+    }
+
+    Ok(str::from_utf8(&output.stdout)?.trim().to_string())
+}
@@ -25,33 +25,80 @@ PROLOG_LOCK_FILE="${LOCK_DIR}/prolog-${SLURM_JOB_ID}-${NODE_HOSTNAME}.lock"
         INFO_LOG_PATH="${LOG_DIR}/info-${SLURM_JOB_ID}.log"
         MONITOR_LOG="/tmp/monitor_debug_${SLURM_JOB_ID}.log"
 
-        # --- 注册任务信息 ---
-        $HELPER_PATH register $INFO_LOG_PATH
-        if [ $? -ne 0 ]; then
-            echo "[Prolog on ${NODE_HOSTNAME}] Error: Job registration with monitoring daemon failed. Aborting."
-            exit 1
-        fi
-        echo "[Prolog on ${NODE_HOSTNAME}] Registration successful."
-
-        # --- 监控进程后台运行 ---
         TARGET_CUDA_DEVICES="${CUDA_VISIBLE_DEVICES:-}"
         if [ -z "$TARGET_CUDA_DEVICES" ]; then
              TARGET_CUDA_DEVICES="${SLURM_JOB_GPUS:-}"
         fi
-        echo "[Prolog] Launching monitor in background..."
-        
-        setsid bash -c "
-            export SLURM_JOB_ID='${SLURM_JOB_ID}'
-            export CUDA_VISIBLE_DEVICES='${TARGET_CUDA_DEVICES}'
-            nohup ${HELPER_PATH} monitor > '${MONITOR_LOG}' 2>&1 < /dev/null &
-        " &
-        sleep 1
-        
-        if pgrep -f "${HELPER_PATH} monitor" > /dev/null; then
-             echo "[Prolog] Monitor process started successfully. Log: ${MONITOR_LOG}"
-        else
-             echo "[Prolog] WARNING: Monitor process failed to start! Check ${MONITOR_LOG}"
-        fi
+        # 后台重试注册的进程也要用到
+        export HELPER_PATH INFO_LOG_PATH MONITOR_LOG MONITOR_DIR NODE_HOSTNAME TARGET_CUDA_DEVICES
+
+        # --- 监控进程后台运行 ---
+        # 以本任务的 PID 文件 (由 job_helper monitor 启动时写入) 确认启动成功, 不会误认其他任务的监控进程
+        start_monitor() {
+            local pid_file="${MONITOR_DIR}/monitor-${SLURM_JOB_ID}-${NODE_HOSTNAME}.pid"
+            rm -f "$pid_file"
+            echo "[Prolog] Launching monitor in background..."
+            CUDA_VISIBLE_DEVICES="$TARGET_CUDA_DEVICES" setsid nohup "$HELPER_PATH" monitor --log-path "$INFO_LOG_PATH" >> "$MONITOR_LOG" 2>&1 < /dev/null &
+            sleep 1
+
+            if [ -s "$pid_file" ] && kill -0 "$(cat "$pid_file")" 2>/dev/null; then
+                 echo "[Prolog] Monitor process started successfully. Log: ${MONITOR_LOG}"
+            else
+                 echo "[Prolog] WARNING: Monitor process failed to start! Check ${MONITOR_LOG}"
+            fi
+        }
+
+        # job_helper register 在标准输出打印监控进程由谁启动 (来自 node_monitor 的答复):
+        # prolog 由本脚本启动; supervised 由 node_monitor 在任务的 cgroup 中启动并看护; none 表示 node_monitor 自行采样
+        handle_launch() {
+            case "$1" in
+                none)
+                    echo "[Prolog on ${NODE_HOSTNAME}] node_monitor samples jobs itself. Skipping job_helper."
+                    ;;
+                supervised)
+                    echo "[Prolog on ${NODE_HOSTNAME}] node_monitor starts and supervises the monitor."
+                    ;;
+                *)
+                    start_monitor
+                    ;;
+            esac
+        }
+        export -f start_monitor handle_launch
+
+        # --- 注册任务信息 ---
+        # 退出码: 0 成功; 3 连不上守护进程; 4 守护进程无响应; 5 守护进程拒绝注册; 其他为本地错误
+        MONITOR_LAUNCH=$($HELPER_PATH register "$INFO_LOG_PATH")
+        REGISTER_STATUS=$?
+        case $REGISTER_STATUS in
+            0)
+                echo "[Prolog on ${NODE_HOSTNAME}] Registration successful."
+                handle_launch "$MONITOR_LAUNCH"
+                ;;
+            3|4)
+                # 守护进程恢复之前无从得知监控进程该由谁启动, 自行启动可能与 node_monitor 启动的重复:
+                # 在后台重试注册, 成功后再按答复处理
+                echo "[Prolog on ${NODE_HOSTNAME}] Warning: monitoring daemon is unavailable (exit ${REGISTER_STATUS}). Retrying registration in the background."
+                setsid bash -c '
+                    for attempt in $(seq 30); do
+                        sleep 10
+                        MONITOR_LAUNCH=$("$HELPER_PATH" register "$INFO_LOG_PATH")
+                        case $? in
+                            0) handle_launch "$MONITOR_LAUNCH"; exit ;;
+                            3|4) ;;
+                            *) exit ;;
+                        esac
+                    done
+                ' >> "${MONITOR_LOG}" 2>&1 < /dev/null &
+                ;;
+            5)
+                echo "[Prolog on ${NODE_HOSTNAME}] Error: monitoring daemon refused to register the job. Aborting."
+                exit 1
+                ;;
+            *)
+                echo "[Prolog on ${NODE_HOSTNAME}] Error: Job registration with monitoring daemon failed (exit ${REGISTER_STATUS}). Aborting."
+                exit 1
+                ;;
+        esac
     ) >> "${MONITOR_DIR}/monitor-${SLURM_JOB_ID}-${NODE_HOSTNAME}.log" 2>&1
 
 